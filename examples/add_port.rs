@@ -8,12 +8,16 @@ fn main() {
         Ok(gateway) => {
             let local_addr = match std::env::args().nth(1) {
                 Some(local_addr) => local_addr,
-                None => panic!("Expected IP address (cargo run --example add_port <your IP here>)"),
+                None => panic!("Expected IP address (cargo run --example add_port <your IP here> [tcp|udp])"),
             };
             let local_addr = local_addr.parse::<Ipv4Addr>().unwrap();
             let local_addr = SocketAddrV4::new(local_addr, 8080u16);
+            let protocol = match std::env::args().nth(2) {
+                Some(protocol) => protocol.parse::<igd::PortMappingProtocol>().unwrap(),
+                None => igd::PortMappingProtocol::TCP,
+            };
 
-            match gateway.add_port(igd::PortMappingProtocol::TCP, 80, local_addr, 60, "add_port example") {
+            match gateway.add_port(protocol, 80, local_addr, 60, "add_port example") {
                 Err(ref err) => {
                     println!("There was an error! {}", err);
                 }
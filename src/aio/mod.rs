@@ -3,6 +3,10 @@
 mod gateway;
 mod search;
 mod soap;
+mod transport;
 
 pub use self::gateway::Gateway;
-pub use self::search::search_gateway;
+#[allow(deprecated)]
+pub use self::search::search_gateway_with_info;
+pub use self::search::{listen_gateways, search_gateway, search_gateways};
+pub use self::transport::{HyperTransport, Transport};
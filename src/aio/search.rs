@@ -1,121 +1,474 @@
 use std::collections::HashMap;
+use std::io;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
 use futures::prelude::*;
-use hyper::Client;
+use hyper::{Body, Client, Request};
 use tokio::net::UdpSocket;
-use tokio::time::timeout;
+use tokio::time::{timeout, timeout_at, Instant};
 
-use crate::aio::Gateway;
-use crate::common::{messages, parsing, SearchOptions};
+use crate::aio::{Gateway, HyperTransport};
+use crate::common::{
+    self, encoding, messages, parsing, parsing::DeviceDescription, parsing::GatewayInfo, parsing::SearchResponse,
+    SearchOptions, Timing,
+};
 use crate::errors::SearchError;
 
-const MAX_RESPONSE_SIZE: usize = 1500;
-
 /// Search for a gateway with the provided options
+///
+/// Returns `SearchError::Timeout` if no gateway answered before `options.timeout`.
+///
+/// Dropping the returned future cancels the search at once: its socket is closed, and so is the
+/// connection the device description is being fetched on, if any. Nothing keeps running in the
+/// background, so `tokio::time::timeout`, `tokio::select!` or aborting the task the search runs in
+/// are enough to stop it early.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "igd.search", level = "debug", skip_all, fields(broadcast_address = %options.broadcast_address))
+)]
 pub async fn search_gateway(options: SearchOptions) -> Result<Gateway, SearchError> {
+    let _timing = Timing::start(&options.timing_hook, "search");
     // Create socket for future calls
-    let mut socket = UdpSocket::bind(&options.bind_addr).await?;
+    let mut socket = bind_search_socket(&options)?;
 
-    send_search_request(&mut socket, options.broadcast_address).await?;
+    let deadline = options.timeout.map(|t| Instant::now() + t);
+    let mut retransmissions = common::Retransmissions::new(options.retransmissions);
 
-    let search_response = receive_search_response(&mut socket);
+    send_search_request(&mut socket, options.broadcast_address, &options.search_target).await?;
 
     // Receive search response, optionally with a timeout
-    let (response_body, from) = match options.timeout {
-        Some(t) => timeout(t, search_response).await?,
-        None => search_response.await,
-    }?;
+    let (response_body, from) =
+        match receive_search_response_until(&mut socket, &options, &mut retransmissions, deadline).await? {
+            Some(response) => response,
+            None => return Err(SearchError::Timeout),
+        };
+
+    get_gateway(&from, &response_body, &options)
+        .await
+        .map(|(gateway, _)| gateway)
+}
+
+/// Search for a gateway with the provided options, and also return its `GatewayInfo`.
+///
+/// The gateway keeps the same information in its `info` field.
+#[deprecated(since = "0.13.0", note = "use `search_gateway` and the `info` field of the gateway")]
+pub async fn search_gateway_with_info(options: SearchOptions) -> Result<(Gateway, GatewayInfo), SearchError> {
+    let gateway = search_gateway(options).await?;
+    let info = gateway.info.clone();
+    Ok((gateway, info))
+}
+
+/// Search for all gateways with the provided options.
+///
+/// Unlike `search_gateway`, this keeps collecting responses until `options.timeout` has elapsed,
+/// and returns every gateway that answered, once each. Without a timeout, responses are collected
+/// for the 3 seconds that gateways are given to answer the search. A gateway that answers from
+/// several addresses is recognized by the USN of its replies.
+///
+/// With `options.all_interfaces`, the search is made from every local IPv4 interface at once, and
+/// the gateways are merged. Interfaces whose search fails are skipped.
+///
+/// The gateways are ordered by latency, fastest first: the time from the search request to the
+/// resolved description of the gateway. With `options.prefer_default_route`, the gateways on the
+/// subnet of the default route of this host come first, whatever their latency.
+///
+/// Like `search_gateway`, the search is cancelled by dropping the returned future.
+pub async fn search_gateways(options: SearchOptions) -> Result<Vec<Gateway>, SearchError> {
+    let prefer_default_route = options.prefer_default_route;
+    let mut gateways = if options.all_interfaces {
+        search_gateways_on_interfaces(options).await?
+    } else {
+        search_gateways_from(options).await?
+    };
+    common::sort_by_latency(&mut gateways, prefer_default_route, |gateway| gateway.addr);
+    Ok(gateways.into_iter().map(|(gateway, _)| gateway).collect())
+}
+
+// Search all gateways from `options.bind_addr` only.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "igd.search", level = "debug", skip_all, fields(broadcast_address = %options.broadcast_address))
+)]
+async fn search_gateways_from(options: SearchOptions) -> Result<Vec<(Gateway, Duration)>, SearchError> {
+    let _timing = Timing::start(&options.timing_hook, "search");
+    let mut socket = bind_search_socket(&options)?;
+    let deadline = Instant::now() + options.timeout.unwrap_or(messages::SEARCH_RESPONSE_DELAY);
+    let mut retransmissions = common::Retransmissions::new(options.retransmissions);
+
+    send_search_request(&mut socket, options.broadcast_address, &options.search_target).await?;
+    let mut latency = common::SearchLatency::new(std::time::Instant::now());
+
+    let mut gateways: Vec<(Gateway, Duration)> = Vec::new();
+    while let Some((response_body, from)) =
+        receive_search_response_until(&mut socket, &options, &mut retransmissions, Some(deadline)).await?
+    {
+        let response = match handle_broadcast_resp(&from, &response_body) {
+            Ok(response) => response,
+            Err(e) => {
+                debug!("ignoring search response from: {}, error: {}", from, e);
+                continue;
+            }
+        };
+        let known = gateways.iter().any(|(gateway, _)| {
+            common::is_same_device(
+                gateway.addr,
+                gateway.usn.as_deref(),
+                response.addr,
+                response.usn.as_deref(),
+            )
+        });
+        if known {
+            continue;
+        }
+        let started = std::time::Instant::now();
+        let result = get_gateway_at(response, &options).await;
+        let ended = std::time::Instant::now();
+        match result {
+            Ok((gateway, _)) if gateways.iter().any(|(known, _)| *known == gateway) => latency.skipped(started, ended),
+            Ok((gateway, _)) => gateways.push((gateway, latency.resolved(started, ended))),
+            Err(e) => {
+                debug!("ignoring search response from: {}, error: {}", from, e);
+                latency.skipped(started, ended);
+            }
+        }
+    }
 
-    let (addr, root_url) = handle_broadcast_resp(&from, &response_body)?;
+    Ok(gateways)
+}
 
-    let (control_schema_url, control_url) = get_control_urls(&addr, &root_url).await?;
-    let control_schema = get_control_schemas(&addr, &control_schema_url).await?;
+async fn search_gateways_on_interfaces(options: SearchOptions) -> Result<Vec<(Gateway, Duration)>, SearchError> {
+    let searches = options.per_interface()?.into_iter().map(|options| {
+        let bind_addr = options.bind_addr;
+        search_gateways_from(options).map(move |result| (bind_addr, result))
+    });
 
-    let addr = match addr {
-        SocketAddr::V4(a) => Ok(a),
-        _ => {
-            warn!("unsupported IPv6 gateway response from addr: {}", addr);
-            Err(SearchError::InvalidResponse)
+    let mut gateways: Vec<(Gateway, Duration)> = Vec::new();
+    for (bind_addr, result) in future::join_all(searches).await {
+        match result {
+            Ok(found) => common::merge_gateways(&mut gateways, found, |gateway| (gateway.addr, gateway.usn.as_deref())),
+            Err(e) => debug!("search from {} failed, error: {}", bind_addr, e),
         }
-    }?;
+    }
+    Ok(gateways)
+}
+
+fn bind_search_socket(options: &SearchOptions) -> io::Result<UdpSocket> {
+    let socket = common::bind_search_socket(options)?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket)
+}
+
+// Fetch the description and control schema of the gateway that answered the search.
+async fn get_gateway(
+    from: &SocketAddr,
+    response_body: &[u8],
+    options: &SearchOptions,
+) -> Result<(Gateway, GatewayInfo), SearchError> {
+    let response = handle_broadcast_resp(from, response_body)?;
+    get_gateway_at(response, options).await
+}
+
+// Fetch the description and control schema of the gateway whose description is at `root_url`.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "igd.fetch_description", level = "debug", skip_all, fields(gateway = %response.addr, url = %response.root_url))
+)]
+async fn get_gateway_at(
+    response: SearchResponse,
+    options: &SearchOptions,
+) -> Result<(Gateway, GatewayInfo), SearchError> {
+    let SearchResponse {
+        addr,
+        root_url,
+        server,
+        usn,
+        max_age,
+    } = response;
+    let _timing = Timing::start(&options.timing_hook, "fetch_description");
+    let headers = common::request_headers(options);
+    let description_headers = common::with_credentials(&headers, &options.credentials);
+
+    let description = get_description(&addr, &root_url, &description_headers, options.http_timeout).await?;
+    let (control_schema_url, control_url, service_type) = description.connection_services[0].urls.clone();
 
-    Ok(Gateway {
+    let mut gateway = Gateway {
         addr,
         root_url,
         control_url,
         control_schema_url,
-        control_schema,
-    })
+        control_schema: HashMap::new(),
+        service_type,
+        connection_retries: common::DEFAULT_CONNECTION_RETRIES,
+        port_range: common::DEFAULT_PORT_RANGE,
+        port_picker: common::default_port_picker(),
+        headers,
+        http_timeout: options.http_timeout,
+        common_interface_url: description.common_interface_url.clone(),
+        event_url: description.connection_services[0].event_url.clone(),
+        services: description.services.clone(),
+        info: description.info.clone(),
+        server,
+        usn,
+        max_age,
+        credentials: options.credentials.clone(),
+        timing_hook: options.timing_hook.clone(),
+        soap_hook: options.soap_hook.clone(),
+        transport: Arc::new(HyperTransport::new()),
+    };
+    gateway
+        .select_connection_service(
+            &description.connection_services,
+            description.layer3_forwarding_url.as_deref(),
+        )
+        .await;
+    gateway.control_schema = get_control_schemas(
+        &addr,
+        &gateway.control_schema_url,
+        &description_headers,
+        options.http_timeout,
+    )
+    .await?;
+    Ok((gateway, description.info))
 }
 
 // Create a new search
-async fn send_search_request(socket: &mut UdpSocket, addr: SocketAddr) -> Result<(), SearchError> {
+async fn send_search_request(socket: &mut UdpSocket, addr: SocketAddr, search_target: &str) -> Result<(), SearchError> {
     debug!(
         "sending broadcast request to: {} on interface: {:?}",
         addr,
         socket.local_addr()
     );
     socket
-        .send_to(messages::SEARCH_REQUEST.as_bytes(), &addr)
+        .send_to(messages::format_search_request(search_target, addr).as_bytes(), &addr)
         .map_ok(|_| ())
         .map_err(SearchError::from)
         .await
 }
 
+// Receive the next search response, resending the search request whenever a retransmission is due.
+// Returns `None` once the deadline has passed
+async fn receive_search_response_until(
+    socket: &mut UdpSocket,
+    options: &SearchOptions,
+    retransmissions: &mut common::Retransmissions,
+    deadline: Option<Instant>,
+) -> Result<Option<(Vec<u8>, SocketAddr)>, SearchError> {
+    loop {
+        if retransmissions.due() {
+            send_search_request(socket, options.broadcast_address, &options.search_target).await?;
+        }
+
+        let wake = match (deadline, retransmissions.next().map(Instant::from_std)) {
+            (Some(deadline), Some(next)) => Some(deadline.min(next)),
+            (deadline, next) => deadline.or(next),
+        };
+        let search_response = match wake {
+            Some(wake) => match timeout_at(wake, receive_search_response(socket)).await {
+                Ok(search_response) => search_response,
+                Err(_) if deadline == Some(wake) => return Ok(None),
+                Err(_) => continue,
+            },
+            None => receive_search_response(socket).await,
+        };
+        match search_response {
+            Ok((ref response, from)) if !parsing::is_search_response(response) => {
+                debug!("ignoring datagram that is not a search response from: {}", from);
+            }
+            search_response => return search_response.map(Some),
+        }
+    }
+}
+
+// Receive the next search response, skipping the ones that were truncated because they are too large
 async fn receive_search_response(socket: &mut UdpSocket) -> Result<(Vec<u8>, SocketAddr), SearchError> {
-    let mut buff = [0u8; MAX_RESPONSE_SIZE];
-    let (n, from) = socket.recv_from(&mut buff).map_err(SearchError::from).await?;
-    debug!("received broadcast response from: {}", from);
-    Ok((buff[..n].to_vec(), from))
+    // One extra byte to tell a response that fills the buffer exactly from a truncated one
+    let mut buff = [0u8; messages::MAX_SEARCH_RESPONSE_SIZE + 1];
+    loop {
+        let (n, from) = socket.recv_from(&mut buff).map_err(SearchError::from).await?;
+        if n > messages::MAX_SEARCH_RESPONSE_SIZE {
+            debug!(
+                "ignoring broadcast response larger than {} bytes from: {}",
+                messages::MAX_SEARCH_RESPONSE_SIZE,
+                from
+            );
+            continue;
+        }
+        debug!("received broadcast response from: {}", from);
+        return Ok((buff[..n].to_vec(), from));
+    }
+}
+
+/// Listen for the announcements that gateways multicast periodically, and when they come up.
+///
+/// Unlike the search functions, no search request is sent. The socket is bound to the port of
+/// `options.broadcast_address`, `1900` by default, with `SO_REUSEADDR` so that other SSDP listeners
+/// of the machine keep working, and joins its multicast group. Every `ssdp:alive` announcement of
+/// an Internet gateway device yields the gateway, with its description fetched like the search
+/// does. Gateways announce themselves several times in a row and again every few minutes, so the
+/// same gateway is yielded repeatedly; compare them to notice new ones. Announcements whose
+/// description can't be fetched are skipped.
+///
+/// This is meant for long-running programs that want to notice a gateway reboot or a new gateway
+/// without polling.
+///
+/// # Example
+/// ```no_run
+/// use futures::StreamExt;
+///
+/// # async fn run() -> Result<(), igd::SearchError> {
+/// let mut gateways = igd::aio::listen_gateways(Default::default()).await?;
+/// while let Some(gateway) = gateways.next().await {
+///     println!("Gateway announced: {}", gateway?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub async fn listen_gateways(
+    options: SearchOptions,
+) -> Result<stream::BoxStream<'static, Result<Gateway, SearchError>>, SearchError> {
+    let socket = common::bind_notify_socket(&options)?;
+    socket.set_nonblocking(true)?;
+    let socket = UdpSocket::from_std(socket)?;
+
+    let gateways = stream::unfold((socket, options), |(mut socket, options)| async move {
+        loop {
+            let (body, from) = match receive_search_response(&mut socket).await {
+                Ok(received) => received,
+                Err(e) => return Some((Err(e), (socket, options))),
+            };
+            let response = match std::str::from_utf8(&body)
+                .map_err(SearchError::from)
+                .and_then(parsing::parse_notify)
+            {
+                Ok(Some(location)) => location,
+                Ok(None) => continue,
+                Err(e) => {
+                    debug!("ignoring invalid announcement from: {}, error: {}", from, e);
+                    continue;
+                }
+            };
+            match get_gateway_at(response, &options).await {
+                Ok((gateway, _)) => return Some((Ok(gateway), (socket, options))),
+                Err(e) => debug!("ignoring announcement from: {}, error: {}", from, e),
+            }
+        }
+    });
+    Ok(gateways.boxed())
 }
 
 // Handle a UDP response message
-fn handle_broadcast_resp(from: &SocketAddr, data: &[u8]) -> Result<(SocketAddr, String), SearchError> {
+fn handle_broadcast_resp(from: &SocketAddr, data: &[u8]) -> Result<SearchResponse, SearchError> {
     debug!("handling broadcast response from: {}", from);
 
     // Convert response to text
-    let text = std::str::from_utf8(&data).map_err(SearchError::from)?;
+    let text = std::str::from_utf8(data).map_err(SearchError::from)?;
 
     // Parse socket address and path
-    let (addr, root_url) = parsing::parse_search_result(text)?;
-
-    Ok((SocketAddr::V4(addr), root_url))
+    parsing::parse_search_result(text)
 }
 
-async fn get_control_urls(addr: &SocketAddr, path: &str) -> Result<(String, String), SearchError> {
-    let uri = match format!("http://{}{}", addr, path).parse() {
+async fn get_description(
+    addr: &SocketAddr,
+    path: &str,
+    headers: &[(String, String)],
+    http_timeout: Option<Duration>,
+) -> Result<DeviceDescription, SearchError> {
+    let url = parsing::resolve_url(addr, path);
+    let uri: hyper::Uri = match url.parse() {
         Ok(uri) => uri,
         Err(err) => return Err(SearchError::from(err)),
     };
 
     debug!("requesting control url from: {}", uri);
-    let client = Client::new();
-    let resp = hyper::body::to_bytes(client.get(uri).await?.into_body())
-        .map_err(SearchError::from)
-        .await?;
+    let (resp, final_uri) = get(uri.clone(), headers, http_timeout).await?;
 
     debug!("handling control response from: {}", addr);
-    let c = std::io::Cursor::new(&resp);
-    parsing::parse_control_urls(c)
+    let final_url = if final_uri != uri {
+        final_uri.to_string()
+    } else {
+        url.clone()
+    };
+    parsing::parse_device_description(&resp, &url, &final_url)
 }
 
 async fn get_control_schemas(
     addr: &SocketAddr,
     control_schema_url: &str,
+    headers: &[(String, String)],
+    http_timeout: Option<Duration>,
 ) -> Result<HashMap<String, Vec<String>>, SearchError> {
-    let uri = match format!("http://{}{}", addr, control_schema_url).parse() {
+    let uri: hyper::Uri = match parsing::resolve_url(addr, control_schema_url).parse() {
         Ok(uri) => uri,
         Err(err) => return Err(SearchError::from(err)),
     };
 
     debug!("requesting control schema from: {}", uri);
-    let client = Client::new();
-    let resp = hyper::body::to_bytes(client.get(uri).await?.into_body())
-        .map_err(SearchError::from)
-        .await?;
+    let (resp, _) = get(uri, headers, http_timeout).await?;
 
     debug!("handling schema response from: {}", addr);
     let c = std::io::Cursor::new(&resp);
     parsing::parse_schemas(c)
 }
+
+// Fetch a document, following redirects, and return it along with the uri it was finally found at.
+async fn get(
+    mut uri: hyper::Uri,
+    headers: &[(String, String)],
+    http_timeout: Option<Duration>,
+) -> Result<(Vec<u8>, hyper::Uri), SearchError> {
+    let client = Client::new();
+    let mut headers = headers.to_vec();
+    for _ in 0..=common::MAX_REDIRECTS {
+        let mut request = Request::get(uri.clone());
+        for (name, value) in &headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        let response = async {
+            let resp = client.request(request.body(Body::empty())?).await?;
+            let status = resp.status();
+            let location = resp
+                .headers()
+                .get(hyper::header::LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .filter(|_| common::is_redirect(status.as_u16()))
+                .map(String::from);
+            if location.is_none() && !status.is_success() {
+                return Err(SearchError::HttpStatus(status.as_u16()));
+            }
+            let encoding = resp
+                .headers()
+                .get(hyper::header::CONTENT_ENCODING)
+                .and_then(|encoding| encoding.to_str().ok())
+                .map(String::from);
+            let body = hyper::body::to_bytes(resp.into_body())
+                .map_err(SearchError::from)
+                .await?;
+            let body = encoding::decode_body(encoding.as_deref(), body.to_vec())?;
+            Ok::<_, SearchError>((body, location))
+        };
+        let (body, location) = match http_timeout {
+            // The gateway did answer the search, this is not the `SearchError::Timeout` of the search.
+            Some(t) => timeout(t, response)
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, format!("request to {} timed out", uri)))??,
+            None => response.await?,
+        };
+        match location {
+            Some(location) => {
+                debug!("following redirect from {} to {}", uri, location);
+                let redirected = parsing::join_url(&uri.to_string(), &location);
+                if !parsing::is_same_origin(&uri.to_string(), &redirected) {
+                    headers = common::without_credentials(&headers);
+                }
+                uri = redirected.parse()?;
+            }
+            None => return Ok((body, uri)),
+        }
+    }
+    Err(SearchError::InvalidResponse(format!(
+        "too many redirects, last to {}",
+        uri
+    )))
+}
@@ -1,8 +1,10 @@
 use hyper::{
-    header::{CONTENT_LENGTH, CONTENT_TYPE},
+    client::HttpConnector,
+    header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, DATE},
     Body, Client, Request,
 };
 
+use crate::common::{self, encoding};
 use crate::errors::RequestError;
 
 #[derive(Clone, Debug)]
@@ -16,10 +18,18 @@ impl Action {
 
 const HEADER_NAME: &str = "SOAPAction";
 
-pub async fn send_async(url: &str, action: Action, body: &str) -> Result<String, RequestError> {
-    let client = Client::new();
-
-    let req = Request::builder()
+pub async fn send_async(
+    client: &Client<HttpConnector>,
+    url: &str,
+    action: Action,
+    body: &str,
+    headers: &[(String, String)],
+) -> Result<String, RequestError> {
+    let mut req = Request::builder();
+    for (name, value) in headers {
+        req = req.header(name.as_str(), value.as_str());
+    }
+    let req = req
         .uri(url)
         .method("POST")
         .header(HEADER_NAME, action.0)
@@ -28,7 +38,31 @@ pub async fn send_async(url: &str, action: Action, body: &str) -> Result<String,
         .body(Body::from(body.to_string()))?;
 
     let resp = client.request(req).await?;
+    common::check_soap_status(resp.status().as_u16())?;
+    let encoding = resp
+        .headers()
+        .get(CONTENT_ENCODING)
+        .and_then(|encoding| encoding.to_str().ok())
+        .map(String::from);
     let body = hyper::body::to_bytes(resp.into_body()).await?;
-    let string = String::from_utf8(body.to_vec())?;
+    let body = encoding::decode_body(encoding.as_deref(), body.to_vec())?;
+    let string = String::from_utf8(body)?;
     Ok(string)
 }
+
+pub async fn get_date(
+    client: &Client<HttpConnector>,
+    url: &str,
+    headers: &[(String, String)],
+) -> Result<Option<String>, RequestError> {
+    let mut req = Request::get(url);
+    for (name, value) in headers {
+        req = req.header(name.as_str(), value.as_str());
+    }
+    let resp = client.request(req.body(Body::empty())?).await?;
+    Ok(resp
+        .headers()
+        .get(DATE)
+        .and_then(|date| date.to_str().ok())
+        .map(String::from))
+}
@@ -0,0 +1,146 @@
+use std::fmt;
+
+use futures::future::BoxFuture;
+use hyper::client::HttpConnector;
+use hyper::Client;
+
+use super::soap;
+use crate::errors::RequestError;
+
+/// Sends the SOAP requests of a `Gateway` and returns the responses.
+///
+/// The default is `HyperTransport`. Another transport can be set on the `transport` field of the
+/// gateway, to record or replay the requests in tests for instance.
+pub trait Transport: fmt::Debug + Send + Sync {
+    /// Post the `body` to the control `url`, with the given value of the `SOAPAction` header and the
+    /// extra `headers` of the gateway, and return the body of the response.
+    fn send<'a>(
+        &'a self,
+        url: &'a str,
+        action: &'a str,
+        body: &'a str,
+        headers: &'a [(String, String)],
+    ) -> BoxFuture<'a, Result<String, RequestError>>;
+
+    /// Get the `url` with the extra `headers` of the gateway, and return the `Date` header of the
+    /// response, or `None` if the server did not send one.
+    fn date<'a>(
+        &'a self,
+        url: &'a str,
+        headers: &'a [(String, String)],
+    ) -> BoxFuture<'a, Result<Option<String>, RequestError>>;
+}
+
+/// Transport that sends the requests with a `hyper` client.
+///
+/// The client is created once and kept, so the requests reuse the connections it pools when the
+/// gateway keeps them alive. The clones of a gateway share its transport, and so its connections.
+#[derive(Clone, Debug, Default)]
+pub struct HyperTransport {
+    client: Client<HttpConnector>,
+}
+
+impl HyperTransport {
+    /// Create a transport with a new `hyper` client.
+    pub fn new() -> HyperTransport {
+        HyperTransport::default()
+    }
+}
+
+impl Transport for HyperTransport {
+    fn send<'a>(
+        &'a self,
+        url: &'a str,
+        action: &'a str,
+        body: &'a str,
+        headers: &'a [(String, String)],
+    ) -> BoxFuture<'a, Result<String, RequestError>> {
+        Box::pin(soap::send_async(
+            &self.client,
+            url,
+            soap::Action::new(action),
+            body,
+            headers,
+        ))
+    }
+
+    fn date<'a>(
+        &'a self,
+        url: &'a str,
+        headers: &'a [(String, String)],
+    ) -> BoxFuture<'a, Result<Option<String>, RequestError>> {
+        Box::pin(soap::get_date(&self.client, url, headers))
+    }
+}
+
+// Transport that replays canned responses in order and records the SOAP actions it was sent.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct MockTransport {
+    responses: std::sync::Mutex<std::collections::VecDeque<Result<String, RequestError>>>,
+    actions: std::sync::Mutex<Vec<String>>,
+    date: Option<String>,
+}
+
+#[cfg(test)]
+impl MockTransport {
+    pub(crate) fn new(responses: Vec<Result<String, RequestError>>) -> MockTransport {
+        MockTransport {
+            responses: std::sync::Mutex::new(responses.into()),
+            actions: Default::default(),
+            date: None,
+        }
+    }
+
+    // Send the given `Date` header with every response to a GET.
+    pub(crate) fn with_date(mut self, date: &str) -> MockTransport {
+        self.date = Some(date.to_string());
+        self
+    }
+
+    // Response of a successful action, with the given output arguments.
+    pub(crate) fn response(action: &str, body: &str) -> Result<String, RequestError> {
+        Ok(format!(
+            r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body><u:{action}Response xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">{body}</u:{action}Response></s:Body></s:Envelope>"#,
+            action = action,
+            body = body,
+        ))
+    }
+
+    // Response of an action that failed with the given UPnP error code.
+    pub(crate) fn fault(code: u16) -> Result<String, RequestError> {
+        Ok(format!(
+            r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body><s:Fault><detail><UPnPError xmlns="urn:schemas-upnp-org:control-1-0"><errorCode>{}</errorCode><errorDescription>Error</errorDescription></UPnPError></detail></s:Fault></s:Body></s:Envelope>"#,
+            code
+        ))
+    }
+
+    // The SOAP actions sent so far, without the service type.
+    pub(crate) fn actions(&self) -> Vec<String> {
+        self.actions.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl Transport for MockTransport {
+    fn send<'a>(
+        &'a self,
+        _url: &'a str,
+        action: &'a str,
+        _body: &'a str,
+        _headers: &'a [(String, String)],
+    ) -> BoxFuture<'a, Result<String, RequestError>> {
+        let name = action.trim_matches('"').rsplit('#').next().unwrap_or_default();
+        self.actions.lock().unwrap().push(name.to_string());
+        let response = self.responses.lock().unwrap().pop_front().expect("no response left");
+        Box::pin(futures::future::ready(response))
+    }
+
+    fn date<'a>(
+        &'a self,
+        _url: &'a str,
+        _headers: &'a [(String, String)],
+    ) -> BoxFuture<'a, Result<Option<String>, RequestError>> {
+        Box::pin(futures::future::ready(Ok(self.date.clone())))
+    }
+}
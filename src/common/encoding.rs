@@ -0,0 +1,107 @@
+// Decoding of compressed HTTP bodies received with hyper. The crate does not ask for compression,
+// but a few gateways send the device description or the SOAP responses with
+// `Content-Encoding: gzip` anyway. With the `compress` feature, gzip and deflate bodies are
+// decoded; attohttpc does the same for the sync requests.
+
+use std::io;
+#[cfg(feature = "compress")]
+use std::io::Read;
+
+#[cfg(feature = "compress")]
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+
+// Largest decoded body accepted, descriptions and responses are a few kilobytes.
+#[cfg(feature = "compress")]
+const MAX_DECODED_LEN: usize = 4 * 1024 * 1024;
+
+// Decode a body according to the `Content-Encoding` header of the response. Bodies with an
+// encoding that can't be decoded are passed on as they are, some gateways send a bogus header.
+pub fn decode_body(content_encoding: Option<&str>, body: Vec<u8>) -> io::Result<Vec<u8>> {
+    let encoding = match content_encoding {
+        Some(encoding) => encoding.trim().to_ascii_lowercase(),
+        None => return Ok(body),
+    };
+    match encoding.as_str() {
+        "" | "identity" => Ok(body),
+        #[cfg(feature = "compress")]
+        "gzip" | "x-gzip" => read_decoded(GzDecoder::new(&body[..])),
+        // Meant to be zlib, but some servers send raw deflate data.
+        #[cfg(feature = "compress")]
+        "deflate" => {
+            read_decoded(ZlibDecoder::new(&body[..])).or_else(|_| read_decoded(DeflateDecoder::new(&body[..])))
+        }
+        _ => {
+            debug!("Using body with unsupported Content-Encoding {} as it is", encoding);
+            Ok(body)
+        }
+    }
+}
+
+#[cfg(feature = "compress")]
+fn read_decoded(decoder: impl Read) -> io::Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    decoder.take(MAX_DECODED_LEN as u64 + 1).read_to_end(&mut decoded)?;
+    if decoded.len() > MAX_DECODED_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "decoded body is too large"));
+    }
+    Ok(decoded)
+}
+
+#[cfg(all(test, feature = "compress"))]
+fn encode<W: io::Write>(mut encoder: W, data: &[u8]) -> W {
+    encoder.write_all(data).unwrap();
+    encoder
+}
+
+#[cfg(feature = "compress")]
+#[test]
+fn test_decode_gzip() {
+    use flate2::{write::GzEncoder, Compression};
+
+    let data = "<root><device><friendlyName>Router</friendlyName></device></root>".repeat(20);
+    let body = encode(GzEncoder::new(Vec::new(), Compression::default()), data.as_bytes())
+        .finish()
+        .unwrap();
+    assert_eq!(decode_body(Some("gzip"), body.clone()).unwrap(), data.as_bytes());
+    assert_eq!(decode_body(Some(" X-GZIP "), body.clone()).unwrap(), data.as_bytes());
+
+    let mut corrupted = body;
+    let crc = corrupted.len() - 8;
+    corrupted[crc] ^= 0xff;
+    assert!(decode_body(Some("gzip"), corrupted).is_err());
+}
+
+#[cfg(feature = "compress")]
+#[test]
+fn test_decode_deflate() {
+    use flate2::{write::DeflateEncoder, write::ZlibEncoder, Compression};
+
+    let data = b"<s:Envelope><s:Body>ok</s:Body></s:Envelope>";
+    let zlib = encode(ZlibEncoder::new(Vec::new(), Compression::default()), data)
+        .finish()
+        .unwrap();
+    assert_eq!(decode_body(Some("deflate"), zlib).unwrap(), data);
+    let raw = encode(DeflateEncoder::new(Vec::new(), Compression::default()), data)
+        .finish()
+        .unwrap();
+    assert_eq!(decode_body(Some("deflate"), raw).unwrap(), data);
+}
+
+#[cfg(feature = "compress")]
+#[test]
+fn test_decode_rejects_oversized() {
+    use flate2::{write::GzEncoder, Compression};
+
+    let data = vec![b'a'; MAX_DECODED_LEN + 1];
+    let body = encode(GzEncoder::new(Vec::new(), Compression::best()), &data)
+        .finish()
+        .unwrap();
+    assert!(decode_body(Some("gzip"), body).is_err());
+}
+
+#[test]
+fn test_decode_other_encodings() {
+    assert_eq!(decode_body(None, b"plain".to_vec()).unwrap(), b"plain");
+    assert_eq!(decode_body(Some("identity"), b"plain".to_vec()).unwrap(), b"plain");
+    assert_eq!(decode_body(Some("br"), b"plain".to_vec()).unwrap(), b"plain");
+}
@@ -0,0 +1,90 @@
+use std::net::SocketAddrV4;
+
+use crate::PortMappingProtocol;
+
+/// A port mapping as it was requested from the gateway
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mapping {
+    /// The protocol of the mapping
+    pub protocol: PortMappingProtocol,
+    /// The external port of the mapping
+    pub external_port: u16,
+    /// The address where the traffic is sent to
+    pub local_addr: SocketAddrV4,
+    /// The lease duration in seconds. A value of 0 is infinite.
+    pub lease_duration: u32,
+    /// A description for this port mapping
+    pub description: String,
+}
+
+/// The set of port mappings an application has created.
+///
+/// Record each mapping after adding it and persist the set (with the `serde` feature). After a
+/// crash or a gateway reboot, `Gateway::restore` adds back the mappings that are missing.
+///
+/// There is at most one mapping per external port and protocol, like on the gateway.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MappingSet {
+    mappings: Vec<Mapping>,
+}
+
+impl MappingSet {
+    /// Create an empty set.
+    pub fn new() -> MappingSet {
+        MappingSet::default()
+    }
+
+    /// Record a mapping, replacing any mapping for the same external port and protocol.
+    pub fn insert(&mut self, mapping: Mapping) {
+        self.remove(mapping.protocol, mapping.external_port);
+        self.mappings.push(mapping);
+    }
+
+    /// Forget the mapping for this external port and protocol, returning it if there was one.
+    pub fn remove(&mut self, protocol: PortMappingProtocol, external_port: u16) -> Option<Mapping> {
+        let index = self
+            .mappings
+            .iter()
+            .position(|m| m.protocol == protocol && m.external_port == external_port)?;
+        Some(self.mappings.remove(index))
+    }
+
+    /// Iterate over the recorded mappings.
+    pub fn iter(&self) -> impl Iterator<Item = &Mapping> {
+        self.mappings.iter()
+    }
+
+    /// Number of recorded mappings.
+    pub fn len(&self) -> usize {
+        self.mappings.len()
+    }
+
+    /// Whether no mappings are recorded.
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+}
+
+#[test]
+fn test_mapping_set_replaces_same_port() {
+    let mapping = |port, description: &str| Mapping {
+        protocol: PortMappingProtocol::TCP,
+        external_port: port,
+        local_addr: "192.168.0.2:8080".parse().unwrap(),
+        lease_duration: 0,
+        description: description.to_string(),
+    };
+
+    let mut set = MappingSet::new();
+    set.insert(mapping(1234, "first"));
+    set.insert(mapping(1234, "second"));
+    set.insert(mapping(2345, "third"));
+    assert_eq!(set.len(), 2);
+    assert_eq!(set.iter().next().unwrap().description, "second");
+
+    assert_eq!(set.remove(PortMappingProtocol::UDP, 1234), None);
+    assert!(set.remove(PortMappingProtocol::TCP, 1234).is_some());
+    assert_eq!(set.len(), 1);
+}
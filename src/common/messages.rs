@@ -1,53 +1,170 @@
 use crate::PortMappingProtocol;
-use std::net::SocketAddrV4;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
 
-// Content of the request.
-pub const SEARCH_REQUEST: &str = "M-SEARCH * HTTP/1.1\r
-Host:239.255.255.250:1900\r
-ST:urn:schemas-upnp-org:device:InternetGatewayDevice:1\r
+// User-Agent of the HTTP requests when none is configured.
+pub const DEFAULT_USER_AGENT: &str = concat!("rust-igd/", env!("CARGO_PKG_VERSION"));
+
+// Service type of gateways whose device description was not fetched.
+pub const DEFAULT_SERVICE_TYPE: &str = "urn:schemas-upnp-org:service:WANIPConnection:1";
+
+// Search target of the request when none is configured.
+pub const DEFAULT_SEARCH_TARGET: &str = "urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+
+// Content of the request, sent to the multicast group `host`.
+pub fn format_search_request(search_target: &str, host: SocketAddr) -> String {
+    // The scope of an IPv6 group is left out, like in urls.
+    let host = match host {
+        SocketAddr::V4(host) => host.to_string(),
+        SocketAddr::V6(host) => format!("[{}]:{}", host.ip(), host.port()),
+    };
+    format!(
+        "M-SEARCH * HTTP/1.1\r
+Host:{}\r
+ST:{}\r
 Man:\"ssdp:discover\"\r
-MX:3\r\n\r\n";
+MX:3\r\n\r\n",
+        host, search_target
+    )
+}
+
+// Multicast group of the search over IPv6, link-local scope.
+pub const IPV6_SEARCH_ADDRESS: &str = "[ff02::c]:1900";
+
+// Largest search response that is accepted. Responses are only headers, but some gateways send
+// many of them, more than fit in a single MTU. Larger responses are skipped rather than parsed
+// truncated.
+pub const MAX_SEARCH_RESPONSE_SIZE: usize = 4096;
+
+// Time gateways are given to answer the search, the MX value of the request.
+pub const SEARCH_RESPONSE_DELAY: Duration = Duration::from_secs(3);
+
+// Names of the actions sent to the connection service.
+pub const GET_EXTERNAL_IP_ACTION: &str = "GetExternalIPAddress";
+pub const ADD_ANY_PORT_MAPPING_ACTION: &str = "AddAnyPortMapping";
+pub const ADD_PORT_MAPPING_ACTION: &str = "AddPortMapping";
+pub const DELETE_PORT_MAPPING_ACTION: &str = "DeletePortMapping";
+pub const DELETE_PORT_MAPPING_RANGE_ACTION: &str = "DeletePortMappingRange";
+pub const GET_GENERIC_PORT_MAPPING_ENTRY_ACTION: &str = "GetGenericPortMappingEntry";
+pub const GET_SPECIFIC_PORT_MAPPING_ENTRY_ACTION: &str = "GetSpecificPortMappingEntry";
+pub const GET_STATUS_INFO_ACTION: &str = "GetStatusInfo";
+pub const GET_CONNECTION_TYPE_INFO_ACTION: &str = "GetConnectionTypeInfo";
+pub const GET_NAT_RSIP_STATUS_ACTION: &str = "GetNATRSIPStatus";
+pub const GET_LIST_OF_PORT_MAPPINGS_ACTION: &str = "GetListOfPortMappings";
 
-pub const GET_EXTERNAL_IP_HEADER: &str = r#""urn:schemas-upnp-org:service:WANIPConnection:1#GetExternalIPAddress""#;
+// Service type of the traffic counters, and the names of its actions.
+pub const COMMON_INTERFACE_SERVICE_TYPE: &str = "urn:schemas-upnp-org:service:WANCommonInterfaceConfig:1";
+pub const GET_TOTAL_BYTES_SENT_ACTION: &str = "GetTotalBytesSent";
+pub const GET_TOTAL_BYTES_RECEIVED_ACTION: &str = "GetTotalBytesReceived";
+pub const GET_TOTAL_PACKETS_SENT_ACTION: &str = "GetTotalPacketsSent";
+pub const GET_TOTAL_PACKETS_RECEIVED_ACTION: &str = "GetTotalPacketsReceived";
+pub const GET_COMMON_LINK_PROPERTIES_ACTION: &str = "GetCommonLinkProperties";
 
-pub const ADD_ANY_PORT_MAPPING_HEADER: &str = r#""urn:schemas-upnp-org:service:WANIPConnection:1#AddAnyPortMapping""#;
+// Service type of the forwarding of the root device, and its action naming the active connection service.
+pub const LAYER3_FORWARDING_SERVICE_TYPE: &str = "urn:schemas-upnp-org:service:Layer3Forwarding:1";
+pub const GET_DEFAULT_CONNECTION_SERVICE_ACTION: &str = "GetDefaultConnectionService";
 
-pub const ADD_PORT_MAPPING_HEADER: &str = r#""urn:schemas-upnp-org:service:WANIPConnection:1#AddPortMapping""#;
+// Service type of the IPv6 firewall of IGDv2 gateways, and the names of its actions.
+pub const WAN_IPV6_FIREWALL_CONTROL_SERVICE_TYPE: &str = "urn:schemas-upnp-org:service:WANIPv6FirewallControl:1";
+pub const ADD_PINHOLE_ACTION: &str = "AddPinhole";
 
-pub const DELETE_PORT_MAPPING_HEADER: &str = r#""urn:schemas-upnp-org:service:WANIPConnection:1#DeletePortMapping""#;
+// Longest lease of a pinhole WANIPv6FirewallControl allows, one day.
+pub const MAX_PINHOLE_LEASE_DURATION: u32 = 86_400;
 
-pub const GET_GENERIC_PORT_MAPPING_ENTRY: &str =
-    r#""urn:schemas-upnp-org:service:WANIPConnection:1#GetGenericPortMappingEntry""#;
+// Arguments of the actions of a WANIPConnection:1 service, for gateways whose control schema was
+// not fetched. Only the actions with arguments that are formatted from the schema are listed.
+pub fn default_control_schema() -> HashMap<String, Vec<String>> {
+    let action = |name: &str, arguments: &[&str]| {
+        (
+            name.to_string(),
+            arguments.iter().map(|argument| argument.to_string()).collect(),
+        )
+    };
+    vec![
+        action(
+            ADD_PORT_MAPPING_ACTION,
+            &[
+                "NewRemoteHost",
+                "NewExternalPort",
+                "NewProtocol",
+                "NewInternalPort",
+                "NewInternalClient",
+                "NewEnabled",
+                "NewPortMappingDescription",
+                "NewLeaseDuration",
+            ],
+        ),
+        action(
+            DELETE_PORT_MAPPING_ACTION,
+            &["NewRemoteHost", "NewExternalPort", "NewProtocol"],
+        ),
+    ]
+    .into_iter()
+    .collect()
+}
+
+// Value of the SOAPAction header for an action of the given service.
+pub fn format_soap_action(service_type: &str, action: &str) -> String {
+    format!(r#""{}#{}""#, service_type, action)
+}
 
-const MESSAGE_HEAD: &str = r#"<?xml version="1.0"?>
+/// Start of a SOAP envelope, up to and including the opening `<s:Body>` tag.
+pub const MESSAGE_HEAD: &str = r#"<?xml version="1.0"?>
 <s:Envelope s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/" xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
 <s:Body>"#;
 
-const MESSAGE_TAIL: &str = r#"</s:Body>
+/// End of a SOAP envelope, from the closing `</s:Body>` tag.
+pub const MESSAGE_TAIL: &str = r#"</s:Body>
 </s:Envelope>"#;
 
-fn format_message(body: String) -> String {
+/// Escape the characters that have a special meaning in XML text and attribute values.
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Wrap the element of an action in a SOAP envelope.
+///
+/// The body is inserted as is: callers are responsible for XML-escaping the argument values,
+/// with `escape_xml` for instance.
+pub fn format_message(body: String) -> String {
     format!("{}{}{}", MESSAGE_HEAD, body, MESSAGE_TAIL)
 }
 
-pub fn format_get_external_ip_message() -> String {
-    r#"<?xml version="1.0"?>
+pub fn format_get_external_ip_message(service_type: &str) -> String {
+    format!(
+        r#"<?xml version="1.0"?>
 <s:Envelope s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/" xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
     <s:Body>
-        <m:GetExternalIPAddress xmlns:m="urn:schemas-upnp-org:service:WANIPConnection:1">
+        <m:GetExternalIPAddress xmlns:m="{service_type}">
         </m:GetExternalIPAddress>
     </s:Body>
-</s:Envelope>"#
-    .into()
+</s:Envelope>"#,
+        service_type = service_type
+    )
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn format_add_any_port_mapping_message(
+    service_type: &str,
     schema: &[String],
     protocol: PortMappingProtocol,
     external_port: u16,
     local_addr: SocketAddrV4,
     lease_duration: u32,
     description: &str,
+    remote_host: Option<Ipv4Addr>,
 ) -> String {
     let args = schema
         .iter()
@@ -58,9 +175,9 @@ pub fn format_add_any_port_mapping_message(
                 "NewInternalClient" => local_addr.ip().to_string(),
                 "NewInternalPort" => local_addr.port().to_string(),
                 "NewLeaseDuration" => lease_duration.to_string(),
-                "NewPortMappingDescription" => description.to_string(),
+                "NewPortMappingDescription" => escape_xml(description),
                 "NewProtocol" => protocol.to_string(),
-                "NewRemoteHost" => "".to_string(),
+                "NewRemoteHost" => remote_host.map(|ip| ip.to_string()).unwrap_or_default(),
                 unknown => {
                     warn!("Unknown argument: {}", unknown);
                     return None;
@@ -76,20 +193,24 @@ pub fn format_add_any_port_mapping_message(
         .join("\n");
 
     format_message(format!(
-        r#"<u:AddAnyPortMapping xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        r#"<u:AddAnyPortMapping xmlns:u="{service_type}">
         {}
         </u:AddAnyPortMapping>"#,
         args,
+        service_type = service_type,
     ))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn format_add_port_mapping_message(
+    service_type: &str,
     schema: &[String],
     protocol: PortMappingProtocol,
     external_port: u16,
     local_addr: SocketAddrV4,
     lease_duration: u32,
     description: &str,
+    remote_host: Option<Ipv4Addr>,
 ) -> String {
     let args = schema
         .iter()
@@ -100,9 +221,9 @@ pub fn format_add_port_mapping_message(
                 "NewInternalClient" => local_addr.ip().to_string(),
                 "NewInternalPort" => local_addr.port().to_string(),
                 "NewLeaseDuration" => lease_duration.to_string(),
-                "NewPortMappingDescription" => description.to_string(),
+                "NewPortMappingDescription" => escape_xml(description),
                 "NewProtocol" => protocol.to_string(),
-                "NewRemoteHost" => "".to_string(),
+                "NewRemoteHost" => remote_host.map(|ip| ip.to_string()).unwrap_or_default(),
                 unknown => {
                     warn!("Unknown argument: {}", unknown);
                     return None;
@@ -118,14 +239,20 @@ pub fn format_add_port_mapping_message(
         .join("\n");
 
     format_message(format!(
-        r#"<u:AddPortMapping xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        r#"<u:AddPortMapping xmlns:u="{service_type}">
         {}
         </u:AddPortMapping>"#,
         args,
+        service_type = service_type,
     ))
 }
 
-pub fn format_delete_port_message(schema: &[String], protocol: PortMappingProtocol, external_port: u16) -> String {
+pub fn format_delete_port_message(
+    service_type: &str,
+    schema: &[String],
+    protocol: PortMappingProtocol,
+    external_port: u16,
+) -> String {
     let args = schema
         .iter()
         .filter_map(|argument| {
@@ -148,18 +275,315 @@ pub fn format_delete_port_message(schema: &[String], protocol: PortMappingProtoc
         .join("\n");
 
     format_message(format!(
-        r#"<u:DeletePortMapping xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        r#"<u:DeletePortMapping xmlns:u="{service_type}">
         {}
         </u:DeletePortMapping>"#,
         args,
+        service_type = service_type,
+    ))
+}
+
+pub fn format_delete_port_range_message(
+    service_type: &str,
+    schema: &[String],
+    protocol: PortMappingProtocol,
+    start_port: u16,
+    end_port: u16,
+    manage: bool,
+) -> String {
+    let args = schema
+        .iter()
+        .filter_map(|argument| {
+            let value = match argument.as_str() {
+                "NewStartPort" => start_port.to_string(),
+                "NewEndPort" => end_port.to_string(),
+                "NewProtocol" => protocol.to_string(),
+                "NewManage" => (manage as u8).to_string(),
+                unknown => {
+                    warn!("Unknown argument: {}", unknown);
+                    return None;
+                }
+            };
+            Some(format!(
+                "<{argument}>{value}</{argument}>",
+                argument = argument,
+                value = value
+            ))
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format_message(format!(
+        r#"<u:DeletePortMappingRange xmlns:u="{service_type}">
+        {}
+        </u:DeletePortMappingRange>"#,
+        args,
+        service_type = service_type,
+    ))
+}
+
+pub fn format_get_list_of_port_mappings_message(
+    service_type: &str,
+    schema: &[String],
+    protocol: PortMappingProtocol,
+    start_port: u16,
+    end_port: u16,
+    manage: bool,
+    num_entries: u32,
+) -> String {
+    let args = schema
+        .iter()
+        .filter_map(|argument| {
+            let value = match argument.as_str() {
+                "NewStartPort" => start_port.to_string(),
+                "NewEndPort" => end_port.to_string(),
+                "NewProtocol" => protocol.to_string(),
+                "NewManage" => (manage as u8).to_string(),
+                "NewNumberOfPorts" => num_entries.to_string(),
+                unknown => {
+                    warn!("Unknown argument: {}", unknown);
+                    return None;
+                }
+            };
+            Some(format!(
+                "<{argument}>{value}</{argument}>",
+                argument = argument,
+                value = value
+            ))
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format_message(format!(
+        r#"<u:GetListOfPortMappings xmlns:u="{service_type}">
+        {}
+        </u:GetListOfPortMappings>"#,
+        args,
+        service_type = service_type,
     ))
 }
 
-pub fn formate_get_generic_port_mapping_entry_message(port_mapping_index: u32) -> String {
+pub fn formate_get_generic_port_mapping_entry_message(service_type: &str, port_mapping_index: u32) -> String {
     format_message(format!(
-        r#"<u:GetGenericPortMappingEntry xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+        r#"<u:GetGenericPortMappingEntry xmlns:u="{service_type}">
         <NewPortMappingIndex>{}</NewPortMappingIndex>
         </u:GetGenericPortMappingEntry>"#,
-        port_mapping_index
+        port_mapping_index,
+        service_type = service_type,
     ))
 }
+
+pub fn format_get_specific_port_mapping_entry_message(
+    service_type: &str,
+    protocol: PortMappingProtocol,
+    external_port: u16,
+) -> String {
+    format_message(format!(
+        r#"<u:GetSpecificPortMappingEntry xmlns:u="{service_type}">
+        <NewRemoteHost></NewRemoteHost>
+        <NewExternalPort>{}</NewExternalPort>
+        <NewProtocol>{}</NewProtocol>
+        </u:GetSpecificPortMappingEntry>"#,
+        external_port,
+        protocol,
+        service_type = service_type,
+    ))
+}
+
+pub fn format_get_status_info_message(service_type: &str) -> String {
+    format_message(format!(
+        r#"<u:GetStatusInfo xmlns:u="{service_type}">
+        </u:GetStatusInfo>"#,
+        service_type = service_type,
+    ))
+}
+
+pub fn format_get_connection_type_info_message(service_type: &str) -> String {
+    format_message(format!(
+        r#"<u:GetConnectionTypeInfo xmlns:u="{service_type}">
+        </u:GetConnectionTypeInfo>"#,
+        service_type = service_type,
+    ))
+}
+
+pub fn format_get_nat_rsip_status_message(service_type: &str) -> String {
+    format_message(format!(
+        r#"<u:GetNATRSIPStatus xmlns:u="{service_type}">
+        </u:GetNATRSIPStatus>"#,
+        service_type = service_type,
+    ))
+}
+
+// Unlike port mappings, pinholes name the protocol by its IANA number and the remote host can be
+// left empty to accept any host.
+pub fn format_add_pinhole_message(
+    protocol: PortMappingProtocol,
+    remote_host: Option<Ipv6Addr>,
+    remote_port: u16,
+    internal_client: Ipv6Addr,
+    internal_port: u16,
+    lease_duration: u32,
+) -> String {
+    let protocol = match protocol {
+        PortMappingProtocol::TCP => "6",
+        PortMappingProtocol::UDP => "17",
+    };
+    format_action_message(
+        WAN_IPV6_FIREWALL_CONTROL_SERVICE_TYPE,
+        ADD_PINHOLE_ACTION,
+        &[
+            (
+                "RemoteHost",
+                &remote_host.map(|host| host.to_string()).unwrap_or_default(),
+            ),
+            ("RemotePort", &remote_port.to_string()),
+            ("InternalClient", &internal_client.to_string()),
+            ("InternalPort", &internal_port.to_string()),
+            ("Protocol", protocol),
+            ("LeaseTime", &lease_duration.to_string()),
+        ],
+    )
+}
+
+// Message of any action of the given service, with the argument values escaped.
+pub fn format_action_message(service_type: &str, action: &str, arguments: &[(&str, &str)]) -> String {
+    let mut args = String::new();
+    for (name, value) in arguments {
+        args.push_str(&format!(
+            "<{name}>{value}</{name}>",
+            name = name,
+            value = escape_xml(value)
+        ));
+    }
+    format_message(format!(
+        r#"<u:{action} xmlns:u="{service_type}">
+        {args}
+        </u:{action}>"#,
+        action = action,
+        service_type = service_type,
+        args = args,
+    ))
+}
+
+// Message of the actions of the WANCommonInterfaceConfig service, none of which take arguments.
+pub fn format_common_interface_message(action: &str) -> String {
+    format_message(format!(
+        r#"<u:{action} xmlns:u="{service_type}">
+        </u:{action}>"#,
+        action = action,
+        service_type = COMMON_INTERFACE_SERVICE_TYPE,
+    ))
+}
+
+#[test]
+fn test_format_search_request() {
+    let request = format_search_request("ssdp:all", "239.255.255.250:1900".parse().unwrap());
+    assert!(request.starts_with("M-SEARCH * HTTP/1.1\r\nHost:239.255.255.250:1900\r\n"));
+    assert!(request.contains("\r\nST:ssdp:all\r\n"));
+    assert!(request.ends_with("MX:3\r\n\r\n"));
+
+    let request = format_search_request("ssdp:all", "[ff02::c%2]:1900".parse().unwrap());
+    assert!(request.contains("\r\nHost:[ff02::c]:1900\r\n"));
+}
+
+#[test]
+fn test_format_add_port_mapping_message_other_internal_client() {
+    // The internal client is another host on the LAN than the one sending the request.
+    let schema = ["NewInternalClient".to_string(), "NewInternalPort".to_string()];
+    let message = format_add_port_mapping_message(
+        "urn:schemas-upnp-org:service:WANIPConnection:1",
+        &schema,
+        PortMappingProtocol::UDP,
+        1234,
+        "192.168.0.42:4321".parse().unwrap(),
+        0,
+        "",
+        None,
+    );
+
+    let xml = xmltree::Element::parse(message.as_bytes()).unwrap();
+    let action = xml
+        .get_child("Body")
+        .and_then(|body| body.get_child("AddPortMapping"))
+        .unwrap();
+    let text = |name: &str| action.get_child(name).and_then(|e| e.get_text()).unwrap();
+    assert_eq!(text("NewInternalClient"), "192.168.0.42");
+    assert_eq!(text("NewInternalPort"), "4321");
+}
+
+#[test]
+fn test_format_add_port_mapping_message_escapes_description() {
+    let schema = ["NewPortMappingDescription".to_string()];
+    let message = format_add_port_mapping_message(
+        "urn:schemas-upnp-org:service:WANIPConnection:1",
+        &schema,
+        PortMappingProtocol::TCP,
+        1234,
+        "192.168.0.2:4321".parse().unwrap(),
+        0,
+        "a & b <c>",
+        None,
+    );
+
+    let xml = xmltree::Element::parse(message.as_bytes()).unwrap();
+    let description = xml
+        .get_child("Body")
+        .and_then(|body| body.get_child("AddPortMapping"))
+        .and_then(|action| action.get_child("NewPortMappingDescription"))
+        .and_then(|description| description.get_text())
+        .unwrap();
+    assert_eq!(description, "a & b <c>");
+}
+
+#[test]
+fn test_format_action_message() {
+    let message = format_action_message(
+        DEFAULT_SERVICE_TYPE,
+        "SetConnectionType",
+        &[("NewConnectionType", "IP_Routed & <more>")],
+    );
+    assert!(message.contains(r#"<u:SetConnectionType xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">"#));
+    assert!(message.contains("<NewConnectionType>IP_Routed &amp; &lt;more&gt;</NewConnectionType>"));
+}
+
+#[test]
+fn test_format_add_pinhole_message() {
+    let message = format_add_pinhole_message(
+        PortMappingProtocol::UDP,
+        None,
+        0,
+        "2001:db8::2".parse().unwrap(),
+        8080,
+        3600,
+    );
+    assert!(message.contains(r#"<u:AddPinhole xmlns:u="urn:schemas-upnp-org:service:WANIPv6FirewallControl:1">"#));
+    assert!(message.contains(
+        "<RemoteHost></RemoteHost><RemotePort>0</RemotePort><InternalClient>2001:db8::2</InternalClient>\
+         <InternalPort>8080</InternalPort><Protocol>17</Protocol><LeaseTime>3600</LeaseTime>"
+    ));
+}
+
+#[test]
+fn test_format_get_list_of_port_mappings_message() {
+    let schema: Vec<String> = [
+        "NewStartPort",
+        "NewEndPort",
+        "NewProtocol",
+        "NewManage",
+        "NewNumberOfPorts",
+    ]
+    .iter()
+    .map(|argument| argument.to_string())
+    .collect();
+    let message = format_get_list_of_port_mappings_message(
+        DEFAULT_SERVICE_TYPE,
+        &schema,
+        PortMappingProtocol::TCP,
+        1000,
+        2000,
+        false,
+        50,
+    );
+    assert!(message.contains("<NewManage>0</NewManage>"));
+    assert!(message.contains("<NewNumberOfPorts>50</NewNumberOfPorts>"));
+}
@@ -1,11 +1,614 @@
+#[cfg(feature = "aio")]
+pub mod encoding;
+pub mod mapping_set;
 pub mod messages;
 pub mod options;
 pub mod parsing;
+pub mod port_picker;
+pub mod soap_hook;
+pub mod timing;
 
-pub use self::options::SearchOptions;
+pub use self::options::{SearchOptions, SearchOptionsBuilder};
+pub use self::port_picker::{PortPicker, RandomPortPicker};
+pub use self::soap_hook::SoapHook;
+pub use self::timing::{Timing, TimingHook};
 
-use rand::{self, Rng};
+use std::io;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-pub fn random_port() -> u16 {
-    rand::thread_rng().gen_range(32_768_u16..65_535_u16)
+use base64::Engine;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use self::parsing::PortMappingEntry;
+use crate::errors::{GetGenericPortMappingEntryError, RequestError};
+
+// Number of times a request is resent after the gateway reset the connection, unless configured otherwise.
+pub const DEFAULT_CONNECTION_RETRIES: u32 = 1;
+
+// External ports picked at random by `add_any_port`, unless configured otherwise: the dynamic ports.
+pub const DEFAULT_PORT_RANGE: RangeInclusive<u16> = 32_768..=65_535;
+
+pub fn default_port_picker() -> Arc<dyn PortPicker> {
+    Arc::new(RandomPortPicker)
+}
+
+// When to resend the search request, spread evenly over the time gateways are given to answer it.
+pub struct Retransmissions {
+    remaining: u32,
+    interval: Duration,
+    next: Instant,
+}
+
+impl Retransmissions {
+    pub fn new(count: u32) -> Retransmissions {
+        let interval = messages::SEARCH_RESPONSE_DELAY / count.saturating_add(1);
+        Retransmissions {
+            remaining: count,
+            interval,
+            next: Instant::now() + interval,
+        }
+    }
+
+    // Whether the request should be resent now, in which case it is counted as resent.
+    pub fn due(&mut self) -> bool {
+        if self.remaining > 0 && Instant::now() >= self.next {
+            self.remaining -= 1;
+            self.next += self.interval;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Time of the next retransmission, if there is one left.
+    pub fn next(&self) -> Option<Instant> {
+        if self.remaining > 0 {
+            Some(self.next)
+        } else {
+            None
+        }
+    }
+}
+
+// Headers sent with every HTTP request to a gateway found with the given options.
+pub fn request_headers(options: &SearchOptions) -> Vec<(String, String)> {
+    options
+        .user_agent
+        .iter()
+        .map(|user_agent| ("User-Agent".to_string(), user_agent.clone()))
+        .collect()
+}
+
+// The headers with the `Authorization` header of HTTP basic authentication added, if there are credentials.
+pub fn with_credentials(headers: &[(String, String)], credentials: &Option<(String, String)>) -> Vec<(String, String)> {
+    let mut headers = headers.to_vec();
+    if let Some((username, password)) = credentials {
+        let token = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", username, password));
+        headers.push(("Authorization".to_string(), format!("Basic {}", token)));
+    }
+    headers
+}
+
+// The headers without the `Authorization` header, for a request redirected to another origin than
+// the one the credentials were given for.
+pub fn without_credentials(headers: &[(String, String)]) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .filter(|(name, _)| !name.eq_ignore_ascii_case("Authorization"))
+        .cloned()
+        .collect()
+}
+
+// Add the given headers to a request, failing on names or values that are not valid in HTTP.
+pub fn add_headers(
+    mut request: attohttpc::RequestBuilder,
+    headers: &[(String, String)],
+) -> Result<attohttpc::RequestBuilder, attohttpc::Error> {
+    for (name, value) in headers {
+        let name = attohttpc::header::HeaderName::from_bytes(name.as_bytes())
+            .map_err(|e| attohttpc::Error::from(attohttpc::ErrorKind::Http(e.into())))?;
+        request = request.try_header(name, value.as_str())?;
+    }
+    Ok(request)
+}
+
+// Longest lease duration UPnP allows, one week in seconds.
+pub const MAX_LEASE_DURATION: u32 = 604_800;
+
+// Lease duration in whole seconds, or `None` if it is longer than UPnP allows. Partial seconds are
+// rounded up so that a short lease does not turn into an infinite one.
+pub fn lease_duration_secs(lease_duration: Duration) -> Option<u32> {
+    let secs = lease_duration.as_secs() + u64::from(lease_duration.subsec_nanos() > 0);
+    if secs > u64::from(MAX_LEASE_DURATION) {
+        None
+    } else {
+        Some(secs as u32)
+    }
+}
+
+// Redirects followed when fetching the device description, before giving up.
+pub const MAX_REDIRECTS: usize = 5;
+
+// A SOAP action answers with 200, or with 500 and a fault in the body. Any other status has no
+// envelope to parse.
+pub fn check_soap_status(status: u16) -> Result<(), RequestError> {
+    match status {
+        200..=299 | 500 => Ok(()),
+        _ => Err(RequestError::HttpStatus(status)),
+    }
+}
+
+// Whether a status code is a redirect to the `Location` header.
+pub fn is_redirect(status: u16) -> bool {
+    matches!(status, 301 | 302 | 303 | 307 | 308)
+}
+
+// Whether traffic can be forwarded to an internal client. Loopback addresses would be the gateway
+// itself, and multicast or broadcast addresses are not a single host. The unspecified address is
+// left to the callers, which reject it with an error of its own.
+pub fn is_valid_internal_client(ip: std::net::Ipv4Addr) -> bool {
+    !(ip.is_loopback() || ip.is_multicast() || ip.is_broadcast())
+}
+
+// Whether two search results are the same device. The USN stays the same when the device is reached
+// over another interface, so it is compared when both have one, and the addresses otherwise.
+pub fn is_same_device(
+    addr: std::net::SocketAddr,
+    usn: Option<&str>,
+    other_addr: std::net::SocketAddr,
+    other_usn: Option<&str>,
+) -> bool {
+    match (usn, other_usn) {
+        (Some(usn), Some(other_usn)) => usn == other_usn,
+        _ => addr == other_addr,
+    }
+}
+
+// Bind the UDP socket of the search with the socket options of `options`.
+pub fn bind_search_socket(options: &SearchOptions) -> io::Result<std::net::UdpSocket> {
+    let socket = Socket::new(Domain::for_address(options.bind_addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if options.reuse_address {
+        socket.set_reuse_address(true)?;
+        #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+        socket.set_reuse_port(true)?;
+    }
+    if let (Some(ref interface), true) = (options.multicast_interface, options.bind_addr.is_ipv4()) {
+        socket.set_multicast_if_v4(interface)?;
+    }
+    socket.bind(&options.bind_addr.into())?;
+    Ok(socket.into())
+}
+
+// Bind the UDP socket that listens for the announcements of gateways, on the port of the search
+// address and in its multicast group. The address is shared with the other SSDP listeners.
+#[cfg(feature = "aio")]
+pub fn bind_notify_socket(options: &SearchOptions) -> io::Result<std::net::UdpSocket> {
+    let socket = bind_search_socket(&SearchOptions {
+        bind_addr: std::net::SocketAddr::new(options.bind_addr.ip(), options.broadcast_address.port()),
+        reuse_address: true,
+        ..options.clone()
+    })?;
+    match options.broadcast_address {
+        std::net::SocketAddr::V4(group) if group.ip().is_multicast() => socket.join_multicast_v4(
+            group.ip(),
+            &options.multicast_interface.unwrap_or(std::net::Ipv4Addr::UNSPECIFIED),
+        )?,
+        std::net::SocketAddr::V6(group) if group.ip().is_multicast() => {
+            socket.join_multicast_v6(group.ip(), group.scope_id())?
+        }
+        _ => {}
+    }
+    Ok(socket)
+}
+
+// The address of this host on the route to the gateway. Connecting a UDP socket sends nothing,
+// it only makes the OS pick the source address of that route.
+pub fn local_ip(gateway: std::net::SocketAddr) -> io::Result<std::net::Ipv4Addr> {
+    let socket = std::net::UdpSocket::bind((std::net::Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect(gateway)?;
+    match socket.local_addr()?.ip() {
+        std::net::IpAddr::V4(ip) if !ip.is_unspecified() => Ok(ip),
+        ip => Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            format!("no IPv4 address on the route to the gateway, got {}", ip),
+        )),
+    }
+}
+
+// Any address outside of the local networks, so that the route to it is the default route.
+const DEFAULT_ROUTE_PROBE: &str = "192.0.2.1:9";
+
+// The address and netmask of this host on the interface of the default route.
+fn default_route_subnet() -> io::Result<(std::net::Ipv4Addr, std::net::Ipv4Addr)> {
+    let ip = local_ip(DEFAULT_ROUTE_PROBE.parse().unwrap())?;
+    if_addrs::get_if_addrs()?
+        .into_iter()
+        .find_map(|interface| match interface.addr {
+            if_addrs::IfAddr::V4(ref addr) if addr.ip == ip => Some((ip, addr.netmask)),
+            _ => None,
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no interface with address {}", ip)))
+}
+
+fn is_on_subnet(addr: std::net::SocketAddr, (ip, netmask): (std::net::Ipv4Addr, std::net::Ipv4Addr)) -> bool {
+    match addr.ip() {
+        std::net::IpAddr::V4(addr) => u32::from(addr) & u32::from(netmask) == u32::from(ip) & u32::from(netmask),
+        std::net::IpAddr::V6(_) => false,
+    }
+}
+
+// Latency of the gateways answering a search, from the search request to their resolved
+// description. Gateways are resolved one at a time, so the time spent resolving the others,
+// including those that fail, is left out.
+pub struct SearchLatency {
+    sent: Instant,
+    resolving: Duration,
+}
+
+impl SearchLatency {
+    // Start measuring from the search request sent at `sent`.
+    pub fn new(sent: Instant) -> SearchLatency {
+        SearchLatency {
+            sent,
+            resolving: Duration::from_secs(0),
+        }
+    }
+
+    // Latency of the gateway whose resolution ran from `started` to `ended`.
+    pub fn resolved(&mut self, started: Instant, ended: Instant) -> Duration {
+        let latency = ended
+            .saturating_duration_since(self.sent)
+            .saturating_sub(self.resolving);
+        self.skipped(started, ended);
+        latency
+    }
+
+    // Leave out of the next latencies the resolution that ran from `started` to `ended` without
+    // giving a new gateway.
+    pub fn skipped(&mut self, started: Instant, ended: Instant) {
+        self.resolving += ended.saturating_duration_since(started);
+    }
+}
+
+// Order the gateways by latency, fastest first. With `prefer_default_route`, the gateways on the
+// subnet of the default route come first whatever their latency. The order is left to latency
+// alone when the default route can't be found.
+pub fn sort_by_latency<T, F>(gateways: &mut [(T, Duration)], prefer_default_route: bool, addr: F)
+where
+    F: Fn(&T) -> std::net::SocketAddr,
+{
+    let subnet = if prefer_default_route {
+        default_route_subnet()
+            .map_err(|e| debug!("not sorting the gateways by default route. error: {}", e))
+            .ok()
+    } else {
+        None
+    };
+    sort_by_subnet_and_latency(gateways, subnet, addr);
+}
+
+fn sort_by_subnet_and_latency<T, F>(
+    gateways: &mut [(T, Duration)],
+    subnet: Option<(std::net::Ipv4Addr, std::net::Ipv4Addr)>,
+    addr: F,
+) where
+    F: Fn(&T) -> std::net::SocketAddr,
+{
+    gateways.sort_by_key(|(gateway, latency)| {
+        let off_subnet = subnet.is_some_and(|subnet| !is_on_subnet(addr(gateway), subnet));
+        (off_subnet, *latency)
+    });
+}
+
+// Merge the gateways found from one interface into those found from the others. A gateway found
+// from several interfaces, recognized by its address and USN, is kept once with its lowest latency.
+pub fn merge_gateways<T, F>(gateways: &mut Vec<(T, Duration)>, found: Vec<(T, Duration)>, id: F)
+where
+    F: Fn(&T) -> (std::net::SocketAddr, Option<&str>),
+{
+    for (gateway, latency) in found {
+        let (addr, usn) = id(&gateway);
+        let known = gateways.iter().position(|(known, _)| {
+            let (known_addr, known_usn) = id(known);
+            is_same_device(known_addr, known_usn, addr, usn)
+        });
+        match known {
+            Some(index) => gateways[index].1 = gateways[index].1.min(latency),
+            None => gateways.push((gateway, latency)),
+        }
+    }
+}
+
+// Walk the port mapping table by index until the gateway reports that the index is out of bounds.
+pub fn collect_port_mappings<F>(mut get_entry: F) -> Result<Vec<PortMappingEntry>, GetGenericPortMappingEntryError>
+where
+    F: FnMut(u32) -> Result<PortMappingEntry, GetGenericPortMappingEntryError>,
+{
+    let mut entries = Vec::new();
+    for index in 0.. {
+        match get_entry(index) {
+            Ok(entry) => entries.push(entry),
+            Err(GetGenericPortMappingEntryError::SpecifiedArrayIndexInvalid) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(entries)
+}
+
+// Time left before the deadline, or a timeout error if it has already passed.
+pub fn remaining(deadline: Instant) -> Result<Duration, RequestError> {
+    let now = Instant::now();
+    if now >= deadline {
+        Err(RequestError::Timeout)
+    } else {
+        Ok(deadline - now)
+    }
+}
+
+// The shorter of two optional timeouts.
+pub fn earliest_timeout(a: Option<Duration>, b: Option<Duration>) -> Option<Duration> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+pub fn is_timeout(err: &RequestError) -> bool {
+    matches!(err, RequestError::Timeout)
+        || matches!(
+            io_error_kind(err),
+            Some(io::ErrorKind::TimedOut) | Some(io::ErrorKind::WouldBlock)
+        )
+}
+
+// Errors that cheap gateways produce when they drop an idle connection; the request is safe to resend.
+pub fn is_connection_reset(err: &RequestError) -> bool {
+    matches!(
+        io_error_kind(err),
+        Some(io::ErrorKind::ConnectionReset) | Some(io::ErrorKind::BrokenPipe)
+    )
+}
+
+fn io_error_kind(err: &RequestError) -> Option<io::ErrorKind> {
+    match err {
+        RequestError::IoError(e) => Some(e.kind()),
+        RequestError::AttoHttpError(e) => match e.kind() {
+            attohttpc::ErrorKind::Io(e) => Some(e.kind()),
+            _ => None,
+        },
+        #[cfg(feature = "aio")]
+        RequestError::HyperError(e) => {
+            let mut source = std::error::Error::source(e);
+            while let Some(err) = source {
+                if let Some(e) = err.downcast_ref::<io::Error>() {
+                    return Some(e.kind());
+                }
+                source = err.source();
+            }
+            None
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn test_local_ip() {
+    let gateway: std::net::SocketAddr = "127.0.0.1:5000".parse().unwrap();
+    assert_eq!(local_ip(gateway).unwrap(), std::net::Ipv4Addr::LOCALHOST);
+    let gateway: std::net::SocketAddr = "[::1]:5000".parse().unwrap();
+    assert!(local_ip(gateway).is_err());
+}
+
+#[test]
+fn test_sort_by_subnet() {
+    let latency = Duration::from_millis(100);
+    let mut gateways: Vec<(std::net::SocketAddr, Duration)> = vec![
+        ("10.8.0.1:5000".parse().unwrap(), latency),
+        ("[fd00::1]:5000".parse().unwrap(), latency),
+        ("192.168.1.1:5000".parse().unwrap(), latency),
+        ("172.17.0.1:5000".parse().unwrap(), latency),
+        ("192.168.1.254:5000".parse().unwrap(), latency),
+    ];
+    let subnet = ("192.168.1.20".parse().unwrap(), "255.255.255.0".parse().unwrap());
+    sort_by_subnet_and_latency(&mut gateways, Some(subnet), |addr| *addr);
+    let expected: Vec<std::net::SocketAddr> = vec![
+        "192.168.1.1:5000".parse().unwrap(),
+        "192.168.1.254:5000".parse().unwrap(),
+        "10.8.0.1:5000".parse().unwrap(),
+        "[fd00::1]:5000".parse().unwrap(),
+        "172.17.0.1:5000".parse().unwrap(),
+    ];
+    assert_eq!(gateways.iter().map(|(addr, _)| *addr).collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn test_sort_by_subnet_before_latency() {
+    let mut gateways: Vec<(std::net::SocketAddr, Duration)> = vec![
+        ("10.8.0.1:5000".parse().unwrap(), Duration::from_millis(10)),
+        ("192.168.1.1:5000".parse().unwrap(), Duration::from_millis(300)),
+    ];
+    let subnet = ("192.168.1.20".parse().unwrap(), "255.255.255.0".parse().unwrap());
+    sort_by_subnet_and_latency(&mut gateways, Some(subnet), |addr| *addr);
+    assert_eq!(gateways[0].0, "192.168.1.1:5000".parse().unwrap());
+    assert_eq!(gateways[1].0, "10.8.0.1:5000".parse().unwrap());
+}
+
+#[test]
+fn test_sort_by_latency() {
+    let mut gateways = vec![
+        ("slow", Duration::from_millis(300)),
+        ("fast", Duration::from_millis(10)),
+        ("first tied", Duration::from_millis(100)),
+        ("second tied", Duration::from_millis(100)),
+    ];
+    sort_by_subnet_and_latency(&mut gateways, None, |_| "192.168.1.1:1900".parse().unwrap());
+    let names: Vec<&str> = gateways.iter().map(|(name, _)| *name).collect();
+    assert_eq!(names, ["fast", "first tied", "second tied", "slow"]);
+}
+
+#[test]
+fn test_search_latency() {
+    let sent = Instant::now();
+    let at = |millis| sent + Duration::from_millis(millis);
+    let mut latency = SearchLatency::new(sent);
+    assert_eq!(latency.resolved(at(10), at(60)), Duration::from_millis(60));
+
+    // The description of the next gateway could not be fetched.
+    latency.skipped(at(60), at(160));
+
+    // This gateway answered while the others were resolved, which is not counted.
+    assert_eq!(latency.resolved(at(160), at(200)), Duration::from_millis(50));
+}
+
+#[test]
+fn test_collect_port_mappings() {
+    let entry = |external_port| PortMappingEntry {
+        remote_host: "".into(),
+        external_port,
+        protocol: crate::PortMappingProtocol::TCP,
+        internal_port: 8080,
+        internal_client: "192.168.0.2".into(),
+        enabled: true,
+        port_mapping_description: "".into(),
+        lease_duration: 0,
+    };
+
+    let entries = collect_port_mappings(|index| match index {
+        0 => Ok(entry(1234)),
+        1 => Ok(entry(2345)),
+        _ => Err(GetGenericPortMappingEntryError::SpecifiedArrayIndexInvalid),
+    })
+    .unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[1].external_port, 2345);
+
+    let result = collect_port_mappings(|index| match index {
+        0 => Ok(entry(1234)),
+        _ => Err(GetGenericPortMappingEntryError::ActionNotAuthorized),
+    });
+    assert!(matches!(
+        result,
+        Err(GetGenericPortMappingEntryError::ActionNotAuthorized)
+    ));
+}
+
+#[test]
+fn test_request_headers() {
+    let headers = request_headers(&SearchOptions::default());
+    assert_eq!(
+        headers,
+        vec![("User-Agent".to_string(), messages::DEFAULT_USER_AGENT.to_string())]
+    );
+
+    let options = SearchOptions {
+        user_agent: None,
+        ..Default::default()
+    };
+    assert!(request_headers(&options).is_empty());
+}
+
+#[test]
+fn test_with_credentials() {
+    let headers = vec![("User-Agent".to_string(), "test".to_string())];
+    assert_eq!(with_credentials(&headers, &None), headers);
+
+    let credentials = Some(("Aladdin".to_string(), "open sesame".to_string()));
+    assert_eq!(
+        with_credentials(&headers, &credentials)[1],
+        (
+            "Authorization".to_string(),
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==".to_string()
+        )
+    );
+    assert_eq!(without_credentials(&with_credentials(&headers, &credentials)), headers);
+}
+
+#[test]
+fn test_retransmissions() {
+    let mut retransmissions = Retransmissions::new(2);
+    assert!(!retransmissions.due());
+    let first = retransmissions.next().unwrap();
+    assert!(first <= Instant::now() + messages::SEARCH_RESPONSE_DELAY / 3);
+
+    assert!(Retransmissions::new(0).next().is_none());
+    assert!(Retransmissions::new(u32::MAX).next().is_some());
+}
+
+#[test]
+fn test_lease_duration_secs() {
+    assert_eq!(lease_duration_secs(Duration::from_secs(0)), Some(0));
+    assert_eq!(lease_duration_secs(Duration::from_millis(1)), Some(1));
+    assert_eq!(lease_duration_secs(Duration::from_secs(3600)), Some(3600));
+    assert_eq!(
+        lease_duration_secs(Duration::from_secs(MAX_LEASE_DURATION.into())),
+        Some(MAX_LEASE_DURATION)
+    );
+    assert_eq!(
+        lease_duration_secs(Duration::from_secs(u64::from(MAX_LEASE_DURATION) + 1)),
+        None
+    );
+}
+
+#[test]
+fn test_bind_search_socket_reuse_address() {
+    let options = SearchOptions::builder()
+        .bind_addr("127.0.0.1:0".parse().unwrap())
+        .reuse_address(true)
+        .multicast_interface(Some(std::net::Ipv4Addr::LOCALHOST))
+        .build();
+    let first = bind_search_socket(&options).unwrap();
+
+    let options = SearchOptions {
+        bind_addr: first.local_addr().unwrap(),
+        ..options
+    };
+    let second = bind_search_socket(&options).unwrap();
+    assert_eq!(second.local_addr().unwrap(), first.local_addr().unwrap());
+}
+
+#[test]
+fn test_timeout_errors() {
+    let err = RequestError::from(io::Error::from(io::ErrorKind::TimedOut));
+    assert!(matches!(err, RequestError::Timeout));
+    assert!(is_timeout(&err));
+
+    let err = RequestError::from(io::Error::from(io::ErrorKind::ConnectionReset));
+    assert!(matches!(err, RequestError::IoError(_)));
+    assert!(!is_timeout(&err));
+
+    assert!(matches!(remaining(Instant::now()), Err(RequestError::Timeout)));
+}
+
+#[test]
+fn test_check_soap_status() {
+    assert!(check_soap_status(200).is_ok());
+    assert!(check_soap_status(500).is_ok());
+    assert!(matches!(check_soap_status(404), Err(RequestError::HttpStatus(404))));
+    assert!(matches!(check_soap_status(401), Err(RequestError::HttpStatus(401))));
+}
+
+#[test]
+fn test_is_same_device() {
+    let addr = "192.168.1.1:5000".parse().unwrap();
+    let other_addr = "10.0.0.1:5000".parse().unwrap();
+    assert!(is_same_device(addr, Some("uuid:1"), other_addr, Some("uuid:1")));
+    assert!(!is_same_device(addr, Some("uuid:1"), addr, Some("uuid:2")));
+    assert!(is_same_device(addr, None, addr, Some("uuid:1")));
+    assert!(!is_same_device(addr, Some("uuid:1"), other_addr, None));
+}
+
+#[test]
+fn test_is_valid_internal_client() {
+    assert!(is_valid_internal_client(std::net::Ipv4Addr::new(192, 168, 1, 2)));
+    for ip in &[
+        "127.0.0.1",
+        "127.1.2.3",
+        "224.0.0.1",
+        "239.255.255.250",
+        "255.255.255.255",
+    ] {
+        assert!(!is_valid_internal_client(ip.parse().unwrap()), "{}", ip);
+    }
 }
@@ -1,6 +1,9 @@
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
 use std::time::Duration;
 
+use crate::common::{messages, SoapHook, TimingHook};
+
 /// Gateway search configuration
 ///
 /// SearchOptions::default() should suffice for most situations.
@@ -16,6 +19,17 @@ use std::time::Duration;
 ///     ..Default::default()
 /// };
 /// ```
+///
+/// Or with the builder returned by `SearchOptions::builder()`.
+/// ```
+/// # use std::time::Duration;
+/// # use igd::SearchOptions;
+/// let opts = SearchOptions::builder()
+///     .timeout(Some(Duration::from_secs(60)))
+///     .retransmissions(4)
+///     .build();
+/// ```
+#[derive(Clone, Debug)]
 pub struct SearchOptions {
     /// Bind address for UDP socket (defaults to all `0.0.0.0`)
     pub bind_addr: SocketAddr,
@@ -23,6 +37,42 @@ pub struct SearchOptions {
     pub broadcast_address: SocketAddr,
     /// Timeout for a search iteration (defaults to 10s)
     pub timeout: Option<Duration>,
+    /// Search target (`ST` header) of discovery packets
+    /// (defaults to `urn:schemas-upnp-org:device:InternetGatewayDevice:1`)
+    pub search_target: String,
+    /// `User-Agent` header sent with every HTTP request to the gateway
+    /// (defaults to `rust-igd/<version>`, `None` sends no header)
+    pub user_agent: Option<String>,
+    /// Timeout for each HTTP request made during the search and later by the gateway,
+    /// such as fetching the device description or sending an action (defaults to 10s)
+    pub http_timeout: Option<Duration>,
+    /// Number of times the discovery packet is resent, spread over the 3 seconds gateways are given
+    /// to answer, in case it gets lost (defaults to 2)
+    pub retransmissions: u32,
+    /// Make `search_gateways` search from every local IPv4 interface at once instead of only
+    /// `bind_addr`, for machines with several networks such as a VPN or docker bridges.
+    /// The port of `bind_addr` is kept (defaults to `false`)
+    pub all_interfaces: bool,
+    /// Make `search_gateways` return first the gateways on the subnet of the default route of this
+    /// host, which is usually the router the traffic goes through, for networks with several
+    /// UPnP devices, whatever their latency. The other gateways follow, ordered by latency
+    /// (defaults to `false`)
+    pub prefer_default_route: bool,
+    /// Set `SO_REUSEADDR`, and `SO_REUSEPORT` on unix, on the UDP socket, so that several processes
+    /// can bind the same address to search at the same time (defaults to `false`)
+    pub reuse_address: bool,
+    /// Local IPv4 address of the interface the discovery packets are sent out of (`IP_MULTICAST_IF`),
+    /// `None` lets the OS choose. Ignored when searching over IPv6 (defaults to `None`)
+    pub multicast_interface: Option<Ipv4Addr>,
+    /// Username and password sent with HTTP basic authentication, for gateways that require it on
+    /// their device description and control urls. Found gateways keep them (defaults to `None`)
+    pub credentials: Option<(String, String)>,
+    /// Told how long the search, the fetch of the device description, and later each action of
+    /// the found gateways took. Found gateways keep it (defaults to `None`)
+    pub timing_hook: Option<Arc<dyn TimingHook>>,
+    /// Shown each SOAP request sent by the found gateways and its response. Found gateways keep
+    /// it (defaults to `None`)
+    pub soap_hook: Option<Arc<dyn SoapHook>>,
 }
 
 impl Default for SearchOptions {
@@ -31,6 +81,173 @@ impl Default for SearchOptions {
             bind_addr: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 0)),
             broadcast_address: "239.255.255.250:1900".parse().unwrap(),
             timeout: Some(Duration::from_secs(10)),
+            search_target: messages::DEFAULT_SEARCH_TARGET.to_string(),
+            user_agent: Some(messages::DEFAULT_USER_AGENT.to_string()),
+            http_timeout: Some(Duration::from_secs(10)),
+            retransmissions: 2,
+            all_interfaces: false,
+            prefer_default_route: false,
+            reuse_address: false,
+            multicast_interface: None,
+            credentials: None,
+            timing_hook: None,
+            soap_hook: None,
+        }
+    }
+}
+
+impl SearchOptions {
+    /// Options of a search over IPv6, sent to the link-local multicast group `[ff02::c]:1900` from `[::]:0`.
+    ///
+    /// IGDv2 devices answer on IPv6. On machines with several interfaces, give the scope of the
+    /// group in `broadcast_address`, such as `[ff02::c%2]:1900`. The gateway must announce its
+    /// description at a global or unique local address, link-local addresses can't be used in urls.
+    pub fn ipv6() -> SearchOptions {
+        SearchOptions {
+            bind_addr: SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+            broadcast_address: messages::IPV6_SEARCH_ADDRESS.parse().unwrap(),
+            ..Default::default()
+        }
+    }
+
+    /// Create a builder starting from the default options.
+    pub fn builder() -> SearchOptionsBuilder {
+        SearchOptionsBuilder {
+            options: SearchOptions::default(),
         }
     }
+
+    // The options of the search from each local IPv4 interface, for `all_interfaces`.
+    pub(crate) fn per_interface(&self) -> std::io::Result<Vec<SearchOptions>> {
+        let interfaces = if_addrs::get_if_addrs()?;
+        Ok(interfaces
+            .into_iter()
+            .filter(|interface| !interface.is_loopback())
+            .filter_map(|interface| match interface.ip() {
+                IpAddr::V4(ip) => Some(SearchOptions {
+                    bind_addr: SocketAddr::new(IpAddr::V4(ip), self.bind_addr.port()),
+                    multicast_interface: Some(ip),
+                    all_interfaces: false,
+                    ..self.clone()
+                }),
+                IpAddr::V6(_) => None,
+            })
+            .collect())
+    }
+}
+
+/// Builder for `SearchOptions`, created with `SearchOptions::builder()`
+///
+/// Every option that is not set keeps its default value.
+pub struct SearchOptionsBuilder {
+    options: SearchOptions,
+}
+
+impl SearchOptionsBuilder {
+    /// Bind address for UDP socket
+    pub fn bind_addr(mut self, bind_addr: SocketAddr) -> Self {
+        self.options.bind_addr = bind_addr;
+        self
+    }
+
+    /// Broadcast address for discovery packets
+    pub fn broadcast_address(mut self, broadcast_address: SocketAddr) -> Self {
+        self.options.broadcast_address = broadcast_address;
+        self
+    }
+
+    /// Timeout for a search iteration
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.options.timeout = timeout;
+        self
+    }
+
+    /// Search target (`ST` header) of discovery packets
+    pub fn search_target<S: Into<String>>(mut self, search_target: S) -> Self {
+        self.options.search_target = search_target.into();
+        self
+    }
+
+    /// `User-Agent` header sent with every HTTP request to the gateway, `None` sends no header
+    pub fn user_agent(mut self, user_agent: Option<String>) -> Self {
+        self.options.user_agent = user_agent;
+        self
+    }
+
+    /// Timeout for each HTTP request made during the search and later by the gateway
+    pub fn http_timeout(mut self, http_timeout: Option<Duration>) -> Self {
+        self.options.http_timeout = http_timeout;
+        self
+    }
+
+    /// Number of times the discovery packet is resent
+    pub fn retransmissions(mut self, retransmissions: u32) -> Self {
+        self.options.retransmissions = retransmissions;
+        self
+    }
+
+    /// Make `search_gateways` search from every local IPv4 interface at once
+    pub fn all_interfaces(mut self, all_interfaces: bool) -> Self {
+        self.options.all_interfaces = all_interfaces;
+        self
+    }
+
+    /// Return first the gateways on the subnet of the default route from `search_gateways`
+    pub fn prefer_default_route(mut self, prefer_default_route: bool) -> Self {
+        self.options.prefer_default_route = prefer_default_route;
+        self
+    }
+
+    /// Set `SO_REUSEADDR`, and `SO_REUSEPORT` on unix, on the UDP socket
+    pub fn reuse_address(mut self, reuse_address: bool) -> Self {
+        self.options.reuse_address = reuse_address;
+        self
+    }
+
+    /// Local IPv4 address of the interface the discovery packets are sent out of
+    pub fn multicast_interface(mut self, multicast_interface: Option<Ipv4Addr>) -> Self {
+        self.options.multicast_interface = multicast_interface;
+        self
+    }
+
+    /// Username and password sent with HTTP basic authentication
+    pub fn credentials<U: Into<String>, P: Into<String>>(mut self, username: U, password: P) -> Self {
+        self.options.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Hook told how long each network operation took
+    pub fn timing_hook(mut self, hook: Arc<dyn TimingHook>) -> Self {
+        self.options.timing_hook = Some(hook);
+        self
+    }
+
+    /// Hook shown each SOAP request and its response
+    pub fn soap_hook(mut self, hook: Arc<dyn SoapHook>) -> Self {
+        self.options.soap_hook = Some(hook);
+        self
+    }
+
+    /// Create the `SearchOptions`.
+    pub fn build(self) -> SearchOptions {
+        self.options
+    }
+}
+
+#[test]
+fn test_per_interface() {
+    let options = SearchOptions::builder()
+        .bind_addr("0.0.0.0:1234".parse().unwrap())
+        .all_interfaces(true)
+        .build();
+    for options in options.per_interface().unwrap() {
+        assert!(options.bind_addr.is_ipv4());
+        assert!(!options.bind_addr.ip().is_loopback());
+        assert_eq!(options.bind_addr.port(), 1234);
+        assert!(!options.all_interfaces);
+        assert_eq!(
+            options.multicast_interface.map(IpAddr::V4),
+            Some(options.bind_addr.ip())
+        );
+    }
 }
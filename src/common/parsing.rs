@@ -1,95 +1,401 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::io;
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::{Duration, SystemTime};
 
-use url::Url;
+use url::{Host, Url};
 use xmltree::{self, Element};
 
+use crate::common::messages;
 use crate::errors::{
-    AddAnyPortError, AddPortError, GetExternalIpError, GetGenericPortMappingEntryError, RemovePortError, RequestError,
-    SearchError,
+    AddAnyPortError, AddPinholeError, AddPortError, GetExternalIpError, GetGenericPortMappingEntryError,
+    GetListOfPortMappingsError, RemovePortError, RequestError, SearchError,
 };
 use crate::PortMappingProtocol;
 
+// What a search reply or an announcement tells about the device that sent it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchResponse {
+    // Address and path of the device description, from the LOCATION header
+    pub addr: SocketAddr,
+    pub root_url: String,
+    // Operating system and product of the device, from the SERVER header
+    pub server: Option<String>,
+    // Unique service name of the device, from the USN header
+    pub usn: Option<String>,
+    // How long the reply stays valid, from the max-age of the CACHE-CONTROL header
+    pub max_age: Option<Duration>,
+}
+
 // Parse the result.
-pub fn parse_search_result(text: &str) -> Result<(SocketAddrV4, String), SearchError> {
-    use SearchError::InvalidResponse;
-
-    for line in text.lines() {
-        let line = line.trim();
-        if line.to_ascii_lowercase().starts_with("location:") {
-            if let Some(colon) = line.find(':') {
-                let url_text = &line[colon + 1..].trim();
-                let url = Url::parse(url_text).map_err(|_| InvalidResponse)?;
-                let addr: Ipv4Addr = url
-                    .host_str()
-                    .ok_or(InvalidResponse)
-                    .and_then(|s| s.parse().map_err(|_| InvalidResponse))?;
-                let port: u16 = url.port_or_known_default().ok_or(InvalidResponse)?;
-
-                return Ok((SocketAddrV4::new(addr, port), url.path().to_string()));
+pub fn parse_search_result(text: &str) -> Result<SearchResponse, SearchError> {
+    if !is_search_response(text.as_bytes()) {
+        return Err(SearchError::InvalidResponse(text.to_string()));
+    }
+    parse_ssdp_message(text)
+}
+
+fn parse_ssdp_message(text: &str) -> Result<SearchResponse, SearchError> {
+    let (addr, root_url) = parse_location(text)?;
+    Ok(SearchResponse {
+        addr,
+        root_url,
+        server: ssdp_header(text, "SERVER").filter(|server| !server.is_empty()),
+        usn: ssdp_header(text, "USN").filter(|usn| !usn.is_empty()),
+        max_age: ssdp_header(text, "CACHE-CONTROL").and_then(|cache_control| parse_max_age(&cache_control)),
+    })
+}
+
+// The max-age directive of a CACHE-CONTROL header, such as `max-age=1800`. Gateways vary in
+// spacing, case and quoting, and some send other directives along with it.
+fn parse_max_age(cache_control: &str) -> Option<Duration> {
+    cache_control.split(',').find_map(|directive| {
+        let (name, value) = directive.split_once('=')?;
+        if !name.trim().eq_ignore_ascii_case("max-age") {
+            return None;
+        }
+        let secs = value.trim().trim_matches('"').parse().ok()?;
+        Some(Duration::from_secs(secs))
+    })
+}
+
+// Whether a datagram is a reply to the search, whose status line is `HTTP/1.1 200 OK`, rather
+// than a `NOTIFY` announcement or an error, which may carry a LOCATION header too.
+pub fn is_search_response(data: &[u8]) -> bool {
+    let status_line = data.split(|&b| b == b'\n').next().unwrap_or_default();
+    let status_line = String::from_utf8_lossy(status_line);
+    let mut parts = status_line.split_whitespace();
+    matches!((parts.next(), parts.next()), (Some(version), Some("200")) if version.starts_with("HTTP/"))
+}
+
+// The address and path of the LOCATION header of an SSDP message.
+fn parse_location(text: &str) -> Result<(SocketAddr, String), SearchError> {
+    let invalid_response = || SearchError::InvalidResponse(text.to_string());
+
+    let location = ssdp_header(text, "LOCATION").ok_or_else(invalid_response)?;
+    let url = Url::parse(&location).map_err(|_| invalid_response())?;
+    let addr = match url.host() {
+        Some(Host::Ipv4(addr)) => IpAddr::V4(addr),
+        Some(Host::Ipv6(addr)) => IpAddr::V6(addr),
+        _ => return Err(invalid_response()),
+    };
+    let port: u16 = url.port_or_known_default().ok_or_else(invalid_response)?;
+
+    Ok((SocketAddr::new(addr, port), url.path().to_string()))
+}
+
+// The value of a header of an SSDP message, whose name is matched case-insensitively. The name
+// ends at the first colon, so the colons of the value are kept, and the lines folded under the
+// header, which start with whitespace, are joined to its value.
+fn ssdp_header(text: &str, name: &str) -> Option<String> {
+    let mut value: Option<String> = None;
+    for line in text.lines().skip(1) {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(ref mut value) = value {
+                let folded = line.trim();
+                if !value.is_empty() && !folded.is_empty() {
+                    value.push(' ');
+                }
+                value.push_str(folded);
             }
+            continue;
+        }
+        if value.is_some() {
+            break;
+        }
+        if let Some((header, header_value)) = line.split_once(':') {
+            if header.trim().eq_ignore_ascii_case(name) {
+                value = Some(header_value.trim().to_string());
+            }
+        }
+    }
+    value
+}
+
+// The location of the description announced by a NOTIFY of an Internet gateway device that is
+// alive, or `None` for other messages, such as `ssdp:byebye` or the announcements of other devices.
+#[cfg(feature = "aio")]
+pub fn parse_notify(text: &str) -> Result<Option<SearchResponse>, SearchError> {
+    if !text.starts_with("NOTIFY ") {
+        return Ok(None);
+    }
+    match (ssdp_header(text, "NT"), ssdp_header(text, "NTS")) {
+        (Some(nt), Some(nts)) if nt.contains("InternetGatewayDevice") && nts.eq_ignore_ascii_case("ssdp:alive") => {
+            parse_ssdp_message(text).map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+// Resolve a url from the device description against the address of the gateway. Most devices
+// give a path, but some give an absolute url including the scheme and host, which is used as is.
+pub fn resolve_url<A: fmt::Display>(addr: A, url: &str) -> String {
+    match Url::parse(&format!("http://{}/", addr)).and_then(|base| base.join(url)) {
+        Ok(url) => url.into(),
+        Err(_) => format!("http://{}{}", addr, url),
+    }
+}
+
+// Resolve a url against the url of the document it was found in, such as a redirected description.
+pub fn join_url(base: &str, url: &str) -> String {
+    match Url::parse(base).and_then(|base| base.join(url)) {
+        Ok(url) => url.into(),
+        Err(_) => url.to_string(),
+    }
+}
+
+// Whether two urls have the same scheme, host and port, so that credentials given for one may be
+// sent to the other.
+pub fn is_same_origin(a: &str, b: &str) -> bool {
+    match (Url::parse(a), Url::parse(b)) {
+        (Ok(a), Ok(b)) => a.origin() == b.origin(),
+        _ => false,
+    }
+}
+
+// What the search needs from the device description.
+#[derive(Debug)]
+pub struct DeviceDescription {
+    pub connection_services: Vec<ConnectionService>,
+    pub services: Services,
+    // WANCommonInterfaceConfig, a sibling of the connection service in the WANDevice. Devices
+    // without it are not an error, the traffic counters just can't be read.
+    pub common_interface_url: Option<String>,
+    // Layer3Forwarding of the root device, which tells which connection service is the active
+    // one on devices that have several.
+    pub layer3_forwarding_url: Option<String>,
+    pub info: GatewayInfo,
+}
+
+// Parse the device description fetched from `url`. When it was redirected to `final_url`, relative
+// urls are resolved against that rather than against the address of the gateway.
+pub fn parse_device_description(body: &[u8], url: &str, final_url: &str) -> Result<DeviceDescription, SearchError> {
+    let services = parse_services(body)?;
+    let control_url = |service_type: &str| services.get(service_type).map(|service| service.control_url.clone());
+    let mut description = DeviceDescription {
+        connection_services: parse_connection_services(body)?,
+        common_interface_url: control_url(messages::COMMON_INTERFACE_SERVICE_TYPE),
+        layer3_forwarding_url: control_url(messages::LAYER3_FORWARDING_SERVICE_TYPE),
+        services,
+        info: parse_gateway_info(body)?,
+    };
+    if final_url != url {
+        for service in &mut description.connection_services {
+            let (scpd_url, control_url, _) = &mut service.urls;
+            *scpd_url = join_url(final_url, scpd_url);
+            *control_url = join_url(final_url, control_url);
+            service.event_url = service.event_url.take().map(|url| join_url(final_url, &url));
         }
+        for service in description.services.values_mut() {
+            service.join(|url| join_url(final_url, &url));
+        }
+        let join = |control_url: Option<String>| control_url.map(|control_url| join_url(final_url, &control_url));
+        description.common_interface_url = join(description.common_interface_url.take());
+        description.layer3_forwarding_url = join(description.layer3_forwarding_url.take());
     }
-    Err(InvalidResponse)
+    Ok(description)
+}
+
+// The connection service that GetDefaultConnectionService answered with, given as
+// `<UDN>:WANConnectionDevice:1,<serviceId>`.
+pub fn find_default_connection_service<'a>(
+    services: &'a [ConnectionService],
+    default: &str,
+) -> Option<&'a ConnectionService> {
+    let (device, service_id) = default.split_once(',')?;
+    services.iter().find(|service| {
+        !service.udn.is_empty() && device.trim().starts_with(&service.udn) && service_id.trim() == service.service_id
+    })
 }
 
-pub fn parse_control_urls<R>(resp: R) -> Result<(String, String), SearchError>
+// Read the whole body of a response, so it can be kept in the error if it turns out to be invalid.
+fn read_body<R: io::Read>(mut resp: R) -> Result<Vec<u8>, SearchError> {
+    let mut body = Vec::new();
+    resp.read_to_end(&mut body)?;
+    Ok(body)
+}
+
+// Skip the UTF-8 byte order mark and the whitespace that some firmwares put before the XML declaration.
+fn xml_start(body: &[u8]) -> &[u8] {
+    let body = body.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(body);
+    let start = body.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(body.len());
+    &body[start..]
+}
+
+fn invalid_description(body: &[u8]) -> SearchError {
+    SearchError::InvalidResponse(String::from_utf8_lossy(body).into_owned())
+}
+
+// The control schema url, control url and service type of the connection service.
+pub type ControlUrls = (String, String, String);
+
+// The urls of the first connection service, the one used when the device does not tell otherwise.
+#[cfg(test)]
+fn parse_control_urls<R>(resp: R) -> Result<ControlUrls, SearchError>
 where
     R: io::Read,
 {
-    let root = Element::parse(resp)?;
+    Ok(parse_connection_services(resp)?.remove(0).urls)
+}
 
-    let mut urls = root.children.iter().filter_map(|child| {
-        let child = child.as_element()?;
-        if child.name == "device" {
-            Some(parse_device(child)?)
-        } else {
-            None
+// A WANIPConnection or WANPPPConnection service of the device description.
+#[derive(Clone, Debug)]
+pub struct ConnectionService {
+    pub urls: ControlUrls,
+    // UDN of the WANConnectionDevice and serviceId of the service, which GetDefaultConnectionService
+    // names the active service with.
+    pub udn: String,
+    pub service_id: String,
+    // Where GENA subscriptions to the state variables of the service are sent, if the device gives one.
+    pub event_url: Option<String>,
+}
+
+// Every connection service of the description in document order, at least one. Devices with
+// several WAN ports have a WANConnectionDevice for each.
+pub fn parse_connection_services<R>(resp: R) -> Result<Vec<ConnectionService>, SearchError>
+where
+    R: io::Read,
+{
+    let body = read_body(resp)?;
+    let root = Element::parse(xml_start(&body))?;
+
+    let mut services = Vec::new();
+    for device in children(Some(&root), "device") {
+        parse_device(device, &mut services);
+    }
+    if services.is_empty() {
+        return Err(invalid_description(&body));
+    }
+
+    for service in &mut services {
+        let (scpd_url, control_url, _) = &mut service.urls;
+        *scpd_url = join_url_base(&root, std::mem::take(scpd_url));
+        *control_url = join_url_base(&root, std::mem::take(control_url));
+        service.event_url = service.event_url.take().map(|url| join_url_base(&root, url));
+    }
+    Ok(services)
+}
+
+// Relative urls are resolved against URLBase when the device gives one, and against the
+// address of the gateway otherwise.
+fn join_url_base(root: &Element, url: String) -> String {
+    let url_base = root
+        .get_child("URLBase")
+        .and_then(|url_base| url_base.get_text())
+        .and_then(|url_base| Url::parse(url_base.trim()).ok());
+    match url_base.and_then(|url_base| url_base.join(&url).ok()) {
+        Some(joined) => joined.into(),
+        None => url,
+    }
+}
+
+/// Urls of a service of the device description
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ServiceUrls {
+    /// Url the actions of the service are sent to
+    pub control_url: String,
+    /// Url of the description of the actions of the service
+    pub scpd_url: String,
+    /// Url GENA subscriptions to the state variables of the service are sent to, if the device
+    /// advertises one
+    pub event_url: Option<String>,
+}
+
+impl ServiceUrls {
+    fn join(&mut self, join: impl Fn(String) -> String) {
+        self.control_url = join(std::mem::take(&mut self.control_url));
+        self.scpd_url = join(std::mem::take(&mut self.scpd_url));
+        self.event_url = self.event_url.take().map(join);
+    }
+}
+
+/// The services of the device description by service type, such as
+/// `urn:schemas-upnp-org:service:WANCommonInterfaceConfig:1`
+pub type Services = HashMap<String, ServiceUrls>;
+
+// Every service of the description and its embedded devices. When several services have the
+// same type, such as the connection services of devices with several WAN ports, the first one in
+// document order is kept.
+pub fn parse_services<R>(resp: R) -> Result<Services, SearchError>
+where
+    R: io::Read,
+{
+    let body = read_body(resp)?;
+    let root = Element::parse(xml_start(&body))?;
+
+    let mut services = Services::new();
+    for device in children(Some(&root), "device") {
+        collect_services(device, &mut services);
+    }
+    for service in services.values_mut() {
+        service.join(|url| join_url_base(&root, url));
+    }
+    Ok(services)
+}
+
+fn collect_services(device: &Element, services: &mut Services) {
+    let text = |element: Option<&Element>| {
+        element
+            .and_then(|element| element.get_text())
+            .map(|text| text.trim().to_string())
+            .unwrap_or_default()
+    };
+    for service in children(device.get_child("serviceList"), "service") {
+        let service_type = text(service.get_child("serviceType"));
+        let control_url = text(service.get_child("controlURL"));
+        if service_type.is_empty() || control_url.is_empty() || services.contains_key(&service_type) {
+            continue;
         }
-    });
+        let event_url = Some(text(service.get_child("eventSubURL"))).filter(|url| !url.is_empty());
+        services.insert(
+            service_type,
+            ServiceUrls {
+                control_url,
+                scpd_url: text(service.get_child("SCPDURL")),
+                event_url,
+            },
+        );
+    }
+    for device in children(device.get_child("deviceList"), "device") {
+        collect_services(device, services);
+    }
+}
 
-    urls.next().ok_or(SearchError::InvalidResponse)
-}
-
-fn parse_device(device: &Element) -> Option<(String, String)> {
-    let services = device
-        .get_child("serviceList")
-        .map(|service_list| {
-            service_list
-                .children
-                .iter()
-                .filter_map(|child| {
-                    let child = child.as_element()?;
-                    if child.name == "service" {
-                        parse_service(child)
-                    } else {
-                        None
-                    }
-                })
-                .next()
-        })
-        .flatten();
-    let devices = device.get_child("deviceList").map(parse_device_list).flatten();
-    services.or(devices)
+// The child elements with the given name of an element that may be missing, such as `serviceList`.
+fn children<'a>(element: Option<&'a Element>, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+    element
+        .into_iter()
+        .flat_map(|element| element.children.iter())
+        .filter_map(|child| child.as_element())
+        .filter(move |child| child.name == name)
 }
 
-fn parse_device_list(device_list: &Element) -> Option<(String, String)> {
-    device_list
-        .children
-        .iter()
-        .filter_map(|child| {
-            let child = child.as_element()?;
-            if child.name == "device" {
-                parse_device(child)
-            } else {
-                None
-            }
-        })
-        .next()
+// Collect the connection services of a device, then those of its embedded devices.
+fn parse_device(device: &Element, services: &mut Vec<ConnectionService>) {
+    let text = |element: Option<&Element>| {
+        element
+            .and_then(|element| element.get_text())
+            .map(|text| text.trim().to_string())
+            .unwrap_or_default()
+    };
+    for service in children(device.get_child("serviceList"), "service") {
+        if let Some(urls) = parse_service(service) {
+            services.push(ConnectionService {
+                urls,
+                udn: text(device.get_child("UDN")),
+                service_id: text(service.get_child("serviceId")),
+                event_url: Some(text(service.get_child("eventSubURL"))).filter(|url| !url.is_empty()),
+            });
+        }
+    }
+    for device in children(device.get_child("deviceList"), "device") {
+        parse_device(device, services);
+    }
 }
 
-fn parse_service(service: &Element) -> Option<(String, String)> {
+fn parse_service(service: &Element) -> Option<(String, String, String)> {
     let service_type = service.get_child("serviceType")?;
     let service_type = service_type
         .get_text()
@@ -97,6 +403,7 @@ fn parse_service(service: &Element) -> Option<(String, String)> {
         .unwrap_or_else(|| "".into());
     if [
         "urn:schemas-upnp-org:service:WANPPPConnection:1",
+        "urn:schemas-upnp-org:service:WANPPPConnection:2",
         "urn:schemas-upnp-org:service:WANIPConnection:1",
         "urn:schemas-upnp-org:service:WANIPConnection:2",
     ]
@@ -111,6 +418,7 @@ fn parse_service(service: &Element) -> Option<(String, String)> {
                     .get_text()
                     .map(|s| s.into_owned())
                     .unwrap_or_else(|| "".into()),
+                service_type,
             ))
         } else {
             None
@@ -120,11 +428,57 @@ fn parse_service(service: &Element) -> Option<(String, String)> {
     }
 }
 
+/// Information about the gateway, taken from its device description
+///
+/// Many devices leave some of these fields empty.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GatewayInfo {
+    /// Short name of the device, meant to be shown to users
+    pub friendly_name: String,
+    /// Manufacturer of the device
+    pub manufacturer: String,
+    /// Model name of the device
+    pub model_name: String,
+    /// Model number of the device
+    pub model_number: String,
+    /// Unique device name, usually of the form `uuid:...`
+    pub udn: String,
+    /// URL of the device's web interface, if it advertises one
+    pub presentation_url: Option<String>,
+}
+
+pub fn parse_gateway_info<R>(resp: R) -> Result<GatewayInfo, SearchError>
+where
+    R: io::Read,
+{
+    let body = read_body(resp)?;
+    let root = Element::parse(xml_start(&body))?;
+    let device = root.get_child("device").ok_or_else(|| invalid_description(&body))?;
+
+    let text = |name: &str| {
+        device
+            .get_child(name)
+            .and_then(|e| e.get_text())
+            .map(|t| t.trim().to_string())
+    };
+
+    Ok(GatewayInfo {
+        friendly_name: text("friendlyName").unwrap_or_default(),
+        manufacturer: text("manufacturer").unwrap_or_default(),
+        model_name: text("modelName").unwrap_or_default(),
+        model_number: text("modelNumber").unwrap_or_default(),
+        udn: text("UDN").unwrap_or_default(),
+        presentation_url: text("presentationURL").filter(|url| !url.is_empty()),
+    })
+}
+
 pub fn parse_schemas<R>(resp: R) -> Result<HashMap<String, Vec<String>>, SearchError>
 where
     R: io::Read,
 {
-    let root = Element::parse(resp)?;
+    let body = read_body(resp)?;
+    let root = Element::parse(xml_start(&body))?;
 
     let mut schema = root.children.iter().filter_map(|child| {
         let child = child.as_element()?;
@@ -135,7 +489,7 @@ where
         }
     });
 
-    schema.next().ok_or(SearchError::InvalidResponse)
+    schema.next().ok_or_else(|| invalid_description(&body))
 }
 
 fn parse_action_list(action_list: &Element) -> Option<HashMap<String, Vec<String>>> {
@@ -187,6 +541,13 @@ fn parse_argument(action: &Element) -> Option<String> {
     }
 }
 
+fn invalid_response(body: String, reason: impl Into<String>) -> RequestError {
+    RequestError::InvalidResponse {
+        body,
+        reason: reason.into(),
+    }
+}
+
 pub struct RequestReponse {
     text: String,
     xml: xmltree::Element,
@@ -195,24 +556,24 @@ pub struct RequestReponse {
 pub type RequestResult = Result<RequestReponse, RequestError>;
 
 pub fn parse_response(text: String, ok: &str) -> RequestResult {
-    let mut xml = match xmltree::Element::parse(text.as_bytes()) {
+    let mut xml = match xmltree::Element::parse(xml_start(text.as_bytes())) {
         Ok(xml) => xml,
-        Err(..) => return Err(RequestError::InvalidResponse(text)),
+        Err(e) => return Err(invalid_response(text, format!("invalid XML: {}", e))),
     };
     let body = match xml.get_mut_child("Body") {
         Some(body) => body,
-        None => return Err(RequestError::InvalidResponse(text)),
+        None => return Err(invalid_response(text, "missing Body")),
     };
     if let Some(ok) = body.take_child(ok) {
         return Ok(RequestReponse { text, xml: ok });
     }
-    let upnp_error = match body
-        .get_child("Fault")
-        .and_then(|e| e.get_child("detail"))
-        .and_then(|e| e.get_child("UPnPError"))
-    {
+    let fault = match body.get_child("Fault") {
+        Some(fault) => fault,
+        None => return Err(invalid_response(text, format!("missing {} or Fault", ok))),
+    };
+    let upnp_error = match fault.get_child("detail").and_then(|e| e.get_child("UPnPError")) {
         Some(upnp_error) => upnp_error,
-        None => return Err(RequestError::InvalidResponse(text)),
+        None => return Err(parse_soap_fault(fault).unwrap_or_else(|| invalid_response(text, "missing UPnPError"))),
     };
 
     match (
@@ -221,26 +582,68 @@ pub fn parse_response(text: String, ok: &str) -> RequestResult {
     ) {
         (Some(e), Some(d)) => match (e.get_text().as_ref(), d.get_text().as_ref()) {
             (Some(et), Some(dt)) => match et.parse::<u16>() {
+                // Invalid Action, the gateway does not implement it.
+                Ok(401) => Err(RequestError::UnsupportedAction(
+                    ok.trim_end_matches("Response").to_string(),
+                )),
                 Ok(en) => Err(RequestError::ErrorCode(en, From::from(&dt[..]))),
-                Err(..) => Err(RequestError::InvalidResponse(text)),
+                Err(..) => Err(invalid_response(text, "invalid errorCode")),
             },
-            _ => Err(RequestError::InvalidResponse(text)),
+            _ => Err(invalid_response(text, "empty errorCode or errorDescription")),
         },
-        _ => Err(RequestError::InvalidResponse(text)),
+        _ => Err(invalid_response(text, "missing errorCode or errorDescription")),
+    }
+}
+
+// The element of a successful response, for actions that are not wrapped by the crate.
+pub fn parse_action_response(result: RequestResult) -> Result<Element, RequestError> {
+    result.map(|resp| resp.xml)
+}
+
+// A fault without UPnPError, reported by its faultcode and faultstring when it has them.
+fn parse_soap_fault(fault: &Element) -> Option<RequestError> {
+    let text = |name: &str| {
+        fault
+            .get_child(name)
+            .and_then(|e| e.get_text())
+            .map(|t| t.trim().to_string())
+    };
+    match (text("faultcode"), text("faultstring")) {
+        (None, None) => None,
+        (code, string) => Some(RequestError::SoapFault {
+            code: code.unwrap_or_default(),
+            string: string.unwrap_or_default(),
+        }),
     }
 }
 
 pub fn parse_get_external_ip_response(result: RequestResult) -> Result<Ipv4Addr, GetExternalIpError> {
+    match parse_external_ip(result)? {
+        (IpAddr::V4(ipv4_addr), _) => Ok(ipv4_addr),
+        (IpAddr::V6(_), text) => Err(GetExternalIpError::RequestError(invalid_response(
+            text,
+            "NewExternalIPAddress is an IPv6 address",
+        ))),
+    }
+}
+
+pub fn parse_get_external_ip_addr_response(result: RequestResult) -> Result<IpAddr, GetExternalIpError> {
+    parse_external_ip(result).map(|(ip_addr, _)| ip_addr)
+}
+
+// The external address along with the response it was read from.
+fn parse_external_ip(result: RequestResult) -> Result<(IpAddr, String), GetExternalIpError> {
     match result {
         Ok(resp) => match resp
             .xml
             .get_child("NewExternalIPAddress")
             .and_then(|e| e.get_text())
-            .and_then(|t| t.parse::<Ipv4Addr>().ok())
+            .and_then(|t| t.trim().parse::<IpAddr>().ok())
         {
-            Some(ipv4_addr) => Ok(ipv4_addr),
-            None => Err(GetExternalIpError::RequestError(RequestError::InvalidResponse(
+            Some(ip_addr) => Ok((ip_addr, resp.text)),
+            None => Err(GetExternalIpError::RequestError(invalid_response(
                 resp.text,
+                "missing or invalid NewExternalIPAddress",
             ))),
         },
         Err(RequestError::ErrorCode(606, _)) => Err(GetExternalIpError::ActionNotAuthorized),
@@ -248,6 +651,171 @@ pub fn parse_get_external_ip_response(result: RequestResult) -> Result<Ipv4Addr,
     }
 }
 
+/// Status of the gateway's WAN connection as returned by GetStatusInfo
+#[derive(Clone, Debug)]
+pub struct StatusInfo {
+    /// Connection status, such as `Connected` or `Disconnected`
+    pub connection_status: String,
+    /// Cause of the last connection failure, `ERROR_NONE` if there was none
+    pub last_connection_error: String,
+    /// Time in seconds since the connection was established
+    pub uptime: u32,
+}
+
+pub fn parse_get_status_info_response(result: RequestResult) -> Result<StatusInfo, RequestError> {
+    let resp = result?;
+    let text = |name: &str| {
+        resp.xml
+            .get_child(name)
+            .and_then(|e| e.get_text())
+            .ok_or_else(|| invalid_response(resp.text.clone(), format!("missing {}", name)))
+    };
+    let connection_status = text("NewConnectionStatus")?.into_owned();
+    let last_connection_error = text("NewLastConnectionError")?.into_owned();
+    let uptime = text("NewUptime")?
+        .parse::<u32>()
+        .map_err(|_| invalid_response(resp.text.clone(), "invalid NewUptime"))?;
+    Ok(StatusInfo {
+        connection_status,
+        last_connection_error,
+        uptime,
+    })
+}
+
+/// Type of the gateway's WAN connection as returned by GetConnectionTypeInfo
+#[derive(Clone, Debug)]
+pub struct ConnectionTypeInfo {
+    /// Type the connection is set to, such as `IP_Routed` or `IP_Bridged`
+    pub connection_type: String,
+    /// Types the connection can be set to
+    pub possible_connection_types: Vec<String>,
+}
+
+pub fn parse_get_connection_type_info_response(result: RequestResult) -> Result<ConnectionTypeInfo, RequestError> {
+    let resp = result?;
+    let text = |name: &str| {
+        resp.xml
+            .get_child(name)
+            .and_then(|e| e.get_text())
+            .ok_or_else(|| invalid_response(resp.text.clone(), format!("missing {}", name)))
+    };
+    let connection_type = text("NewConnectionType")?.trim().to_string();
+    // The possible types are a comma separated list, which some gateways leave empty.
+    let possible_connection_types = resp
+        .xml
+        .get_child("NewPossibleConnectionTypes")
+        .and_then(|e| e.get_text())
+        .map(|types| {
+            types
+                .split(',')
+                .map(str::trim)
+                .filter(|t| !t.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok(ConnectionTypeInfo {
+        connection_type,
+        possible_connection_types,
+    })
+}
+
+/// Whether the gateway translates addresses, as returned by GetNATRSIPStatus
+#[derive(Clone, Debug)]
+pub struct NatRsipStatus {
+    /// Whether the gateway supports Realm-Specific IP
+    pub rsip_available: bool,
+    /// Whether the gateway performs NAT. When it does not, such as in bridge mode, port mappings
+    /// have no effect
+    pub nat_enabled: bool,
+}
+
+pub fn parse_get_nat_rsip_status_response(result: RequestResult) -> Result<NatRsipStatus, RequestError> {
+    let resp = result?;
+    let flag = |name: &str| match resp.xml.get_child(name).and_then(|e| e.get_text()).as_deref() {
+        Some("0") => Ok(false),
+        Some("1") => Ok(true),
+        _ => Err(invalid_response(
+            resp.text.clone(),
+            format!("missing or invalid {}", name),
+        )),
+    };
+    Ok(NatRsipStatus {
+        rsip_available: flag("NewRSIPAvailable")?,
+        nat_enabled: flag("NewNATEnabled")?,
+    })
+}
+
+// Read a traffic counter of the WANCommonInterfaceConfig service, such as `NewTotalBytesSent`.
+pub fn parse_total_response(result: RequestResult, name: &str) -> Result<u64, RequestError> {
+    let resp = result?;
+    match resp
+        .xml
+        .get_child(name)
+        .and_then(|e| e.get_text())
+        .and_then(|t| t.trim().parse::<u64>().ok())
+    {
+        Some(total) => Ok(total),
+        None => Err(invalid_response(resp.text, format!("missing or invalid {}", name))),
+    }
+}
+
+pub fn parse_default_connection_service_response(result: RequestResult) -> Result<String, RequestError> {
+    let resp = result?;
+    match resp
+        .xml
+        .get_child("NewDefaultConnectionService")
+        .and_then(|e| e.get_text())
+    {
+        Some(default) => Ok(default.trim().to_string()),
+        None => Err(invalid_response(resp.text, "missing NewDefaultConnectionService")),
+    }
+}
+
+/// Properties of the gateway's WAN link as returned by GetCommonLinkProperties
+#[derive(Clone, Debug)]
+pub struct LinkProperties {
+    /// Type of the WAN access, such as `DSL`, `Cable` or `Ethernet`
+    pub access_type: String,
+    /// Maximum upstream bitrate of the physical link, in bits per second
+    pub upstream_max_bit_rate: u32,
+    /// Maximum downstream bitrate of the physical link, in bits per second
+    pub downstream_max_bit_rate: u32,
+    /// Status of the physical link, such as `Up` or `Down`
+    pub physical_link_status: String,
+}
+
+pub fn parse_get_link_properties_response(result: RequestResult) -> Result<LinkProperties, RequestError> {
+    let resp = result?;
+    let text = |name: &str| {
+        resp.xml
+            .get_child(name)
+            .and_then(|e| e.get_text())
+            .ok_or_else(|| invalid_response(resp.text.clone(), format!("missing {}", name)))
+    };
+    let bit_rate = |name: &str| {
+        text(name)?
+            .trim()
+            .parse::<u32>()
+            .map_err(|_| invalid_response(resp.text.clone(), format!("invalid {}", name)))
+    };
+    Ok(LinkProperties {
+        access_type: text("NewWANAccessType")?.into_owned(),
+        upstream_max_bit_rate: bit_rate("NewLayer1UpstreamMaxBitRate")?,
+        downstream_max_bit_rate: bit_rate("NewLayer1DownstreamMaxBitRate")?,
+        physical_link_status: text("NewPhysicalLinkStatus")?.into_owned(),
+    })
+}
+
+pub fn parse_router_time(date: Option<&str>) -> Result<SystemTime, RequestError> {
+    match date {
+        Some(date) => {
+            httpdate::parse_http_date(date).map_err(|_| invalid_response(date.to_string(), "invalid Date header"))
+        }
+        None => Err(invalid_response(String::new(), "gateway sent no Date header")),
+    }
+}
+
 pub fn parse_add_any_port_mapping_response(result: RequestResult) -> Result<u16, AddAnyPortError> {
     match result {
         Ok(resp) => {
@@ -258,13 +826,22 @@ pub fn parse_add_any_port_mapping_response(result: RequestResult) -> Result<u16,
                 .and_then(|t| t.parse::<u16>().ok())
             {
                 Some(port) => Ok(port),
-                None => Err(AddAnyPortError::RequestError(RequestError::InvalidResponse(resp.text))),
+                None => Err(AddAnyPortError::RequestError(invalid_response(
+                    resp.text,
+                    "missing or invalid NewReservedPort",
+                ))),
             }
         }
         Err(err) => Err(match err {
-            RequestError::ErrorCode(605, _) => AddAnyPortError::DescriptionTooLong,
-            RequestError::ErrorCode(606, _) => AddAnyPortError::ActionNotAuthorized,
-            RequestError::ErrorCode(728, _) => AddAnyPortError::NoPortsAvailable,
+            RequestError::ErrorCode(605, description) => AddAnyPortError::DescriptionTooLong(Some(description)),
+            RequestError::ErrorCode(606, description) => AddAnyPortError::ActionNotAuthorized(Some(description)),
+            RequestError::ErrorCode(715, description) => {
+                AddAnyPortError::WildcardNotPermittedInRemoteHost(Some(description))
+            }
+            RequestError::ErrorCode(728, description) => AddAnyPortError::NoPortsAvailable(Some(description)),
+            RequestError::ErrorCode(729, description) => {
+                AddAnyPortError::ConflictWithOtherMechanisms(Some(description))
+            }
             e => AddAnyPortError::RequestError(e),
         }),
     }
@@ -273,34 +850,131 @@ pub fn parse_add_any_port_mapping_response(result: RequestResult) -> Result<u16,
 pub fn convert_add_random_port_mapping_error(error: RequestError) -> Option<AddAnyPortError> {
     match error {
         RequestError::ErrorCode(724, _) => None,
-        RequestError::ErrorCode(605, _) => Some(AddAnyPortError::DescriptionTooLong),
-        RequestError::ErrorCode(606, _) => Some(AddAnyPortError::ActionNotAuthorized),
-        RequestError::ErrorCode(718, _) => Some(AddAnyPortError::NoPortsAvailable),
-        RequestError::ErrorCode(725, _) => Some(AddAnyPortError::OnlyPermanentLeasesSupported),
+        RequestError::ErrorCode(605, description) => Some(AddAnyPortError::DescriptionTooLong(Some(description))),
+        RequestError::ErrorCode(606, description) => Some(AddAnyPortError::ActionNotAuthorized(Some(description))),
+        RequestError::ErrorCode(715, description) => {
+            Some(AddAnyPortError::WildcardNotPermittedInRemoteHost(Some(description)))
+        }
+        RequestError::ErrorCode(718, description) => Some(AddAnyPortError::NoPortsAvailable(Some(description))),
+        RequestError::ErrorCode(725, description) => {
+            Some(AddAnyPortError::OnlyPermanentLeasesSupported(Some(description)))
+        }
+        RequestError::ErrorCode(727, description) => {
+            Some(AddAnyPortError::ExternalPortOnlySupportsWildcard(Some(description)))
+        }
+        RequestError::ErrorCode(729, description) => {
+            Some(AddAnyPortError::ConflictWithOtherMechanisms(Some(description)))
+        }
         e => Some(AddAnyPortError::RequestError(e)),
     }
 }
 
 pub fn convert_add_same_port_mapping_error(error: RequestError) -> AddAnyPortError {
     match error {
-        RequestError::ErrorCode(606, _) => AddAnyPortError::ActionNotAuthorized,
-        RequestError::ErrorCode(718, _) => AddAnyPortError::ExternalPortInUse,
-        RequestError::ErrorCode(725, _) => AddAnyPortError::OnlyPermanentLeasesSupported,
+        RequestError::ErrorCode(606, description) => AddAnyPortError::ActionNotAuthorized(Some(description)),
+        RequestError::ErrorCode(715, description) => {
+            AddAnyPortError::WildcardNotPermittedInRemoteHost(Some(description))
+        }
+        RequestError::ErrorCode(718, description) => AddAnyPortError::ExternalPortInUse(Some(description)),
+        RequestError::ErrorCode(725, description) => AddAnyPortError::OnlyPermanentLeasesSupported(Some(description)),
+        RequestError::ErrorCode(727, description) => {
+            AddAnyPortError::ExternalPortOnlySupportsWildcard(Some(description))
+        }
+        RequestError::ErrorCode(729, description) => AddAnyPortError::ConflictWithOtherMechanisms(Some(description)),
         e => AddAnyPortError::RequestError(e),
     }
 }
 
 pub fn convert_add_port_error(err: RequestError) -> AddPortError {
     match err {
-        RequestError::ErrorCode(605, _) => AddPortError::DescriptionTooLong,
-        RequestError::ErrorCode(606, _) => AddPortError::ActionNotAuthorized,
-        RequestError::ErrorCode(718, _) => AddPortError::PortInUse,
-        RequestError::ErrorCode(724, _) => AddPortError::SamePortValuesRequired,
-        RequestError::ErrorCode(725, _) => AddPortError::OnlyPermanentLeasesSupported,
+        RequestError::ErrorCode(605, description) => AddPortError::DescriptionTooLong(Some(description)),
+        RequestError::ErrorCode(606, description) => AddPortError::ActionNotAuthorized(Some(description)),
+        RequestError::ErrorCode(715, description) => AddPortError::WildcardNotPermittedInRemoteHost(Some(description)),
+        RequestError::ErrorCode(716, description) => {
+            AddPortError::WildcardNotPermittedInExternalPort(Some(description))
+        }
+        RequestError::ErrorCode(718, description) => AddPortError::PortInUse(Some(description)),
+        RequestError::ErrorCode(724, description) => AddPortError::SamePortValuesRequired(Some(description)),
+        RequestError::ErrorCode(725, description) => AddPortError::OnlyPermanentLeasesSupported(Some(description)),
+        RequestError::ErrorCode(726, description) => AddPortError::RemoteHostOnlySupportsWildcard(Some(description)),
+        RequestError::ErrorCode(727, description) => AddPortError::ExternalPortOnlySupportsWildcard(Some(description)),
+        RequestError::ErrorCode(729, description) => AddPortError::ConflictWithOtherMechanisms(Some(description)),
         e => AddPortError::RequestError(e),
     }
 }
 
+// The UniqueID the gateway gave to the new pinhole.
+pub fn parse_add_pinhole_response(result: RequestResult) -> Result<u16, AddPinholeError> {
+    let resp = result?;
+    match resp
+        .xml
+        .get_child("UniqueID")
+        .and_then(|e| e.get_text())
+        .and_then(|t| t.trim().parse::<u16>().ok())
+    {
+        Some(unique_id) => Ok(unique_id),
+        None => Err(invalid_response(resp.text, "missing or invalid UniqueID").into()),
+    }
+}
+
+// Whether GetSpecificPortMappingEntry found a mapping; NoSuchEntryInArray means there is none.
+pub fn parse_port_mapping_exists_response(result: RequestResult) -> Result<bool, RequestError> {
+    match result {
+        Ok(_) => Ok(true),
+        Err(RequestError::ErrorCode(714, _)) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// A port mapping along with the lease the gateway granted for it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MappedPort {
+    /// The external port of the mapping
+    pub external_port: u16,
+    /// The lease duration in seconds granted by the gateway, which may be shorter than the one
+    /// requested. A value of 0 is infinite.
+    pub lease_duration: u32,
+}
+
+/// An external address reserved on the gateway, as returned by `reserve_any_address`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MappedAddress {
+    /// The external address, made of the external IP of the gateway and the mapped port
+    pub addr: SocketAddrV4,
+    /// The protocol of the mapping
+    pub protocol: PortMappingProtocol,
+    /// The lease duration in seconds granted by the gateway, which may be shorter than the one
+    /// requested. A value of 0 is infinite.
+    pub lease_duration: u32,
+}
+
+// The remaining lease duration of the mapping found by GetSpecificPortMappingEntry.
+pub fn parse_specific_port_mapping_lease(result: RequestResult) -> Result<u32, RequestError> {
+    let resp = result?;
+    resp.xml
+        .get_child("NewLeaseDuration")
+        .and_then(|e| e.get_text())
+        .and_then(|t| t.trim().parse::<u32>().ok())
+        .ok_or_else(|| invalid_response(resp.text.clone(), "missing or invalid NewLeaseDuration"))
+}
+
+// The internal address of the mapping found by GetSpecificPortMappingEntry, if there is one.
+pub fn parse_specific_port_mapping_target(result: RequestResult) -> Result<Option<SocketAddrV4>, RequestError> {
+    let resp = match result {
+        Ok(resp) => resp,
+        Err(RequestError::ErrorCode(714, _)) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let field = |name: &str| resp.xml.get_child(name).and_then(|e| e.get_text());
+    let client = field("NewInternalClient")
+        .and_then(|t| t.parse::<Ipv4Addr>().ok())
+        .ok_or_else(|| invalid_response(resp.text.clone(), "missing or invalid NewInternalClient"))?;
+    let port = field("NewInternalPort")
+        .and_then(|t| t.parse::<u16>().ok())
+        .ok_or_else(|| invalid_response(resp.text.clone(), "missing or invalid NewInternalPort"))?;
+    Ok(Some(SocketAddrV4::new(client, port)))
+}
+
 pub fn parse_delete_port_mapping_response(result: RequestResult) -> Result<(), RemovePortError> {
     match result {
         Ok(_) => Ok(()),
@@ -312,7 +986,21 @@ pub fn parse_delete_port_mapping_response(result: RequestResult) -> Result<(), R
     }
 }
 
+pub fn parse_delete_port_mapping_range_response(result: RequestResult) -> Result<(), RemovePortError> {
+    match result {
+        Ok(_) => Ok(()),
+        Err(err) => Err(match err {
+            RequestError::ErrorCode(606, _) => RemovePortError::ActionNotAuthorized,
+            RequestError::ErrorCode(730, _) => RemovePortError::NoSuchPortMapping,
+            RequestError::ErrorCode(733, _) => RemovePortError::InconsistentParameters,
+            e => RemovePortError::RequestError(e),
+        }),
+    }
+}
+
 /// One port mapping entry as returned by GetGenericPortMappingEntry
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PortMappingEntry {
     /// The remote host for which the mapping is valid
     /// Can be an IP address or a host name
@@ -334,12 +1022,33 @@ pub struct PortMappingEntry {
     pub lease_duration: u32,
 }
 
+impl PortMappingEntry {
+    /// The lease duration of the mapping, or `None` if it is permanent (a `lease_duration` of 0).
+    pub fn lease(&self) -> Option<Duration> {
+        match self.lease_duration {
+            0 => None,
+            secs => Some(Duration::from_secs(u64::from(secs))),
+        }
+    }
+
+    /// The remote host parsed as an IP address, or `None` if the mapping is valid for any host
+    /// (an empty `remote_host`) or the gateway gave a host name, which `remote_host` still holds.
+    pub fn remote_host_ip(&self) -> Option<IpAddr> {
+        parse_host_ip(&self.remote_host)
+    }
+
+    /// The internal client parsed as an IP address, or `None` if the gateway gave a host name,
+    /// which `internal_client` still holds.
+    pub fn internal_client_ip(&self) -> Option<IpAddr> {
+        parse_host_ip(&self.internal_client)
+    }
+}
+
 pub fn parse_get_generic_port_mapping_entry(
     result: RequestResult,
 ) -> Result<PortMappingEntry, GetGenericPortMappingEntryError> {
-    let response = result?;
-    let xml = response.xml;
-    let make_err = |msg: String| || GetGenericPortMappingEntryError::RequestError(RequestError::InvalidResponse(msg));
+    let RequestReponse { text, xml } = result?;
+    let make_err = |msg: String| || GetGenericPortMappingEntryError::RequestError(invalid_response(text.clone(), msg));
     let extract_field = |field: &str| {
         xml.get_child(field)
             .ok_or_else(make_err(format!("{} is missing", field)))
@@ -355,11 +1064,7 @@ pub fn parse_get_generic_port_mapping_entry(
     let protocol = match extract_field("NewProtocol")?.get_text() {
         Some(std::borrow::Cow::Borrowed("UDP")) => PortMappingProtocol::UDP,
         Some(std::borrow::Cow::Borrowed("TCP")) => PortMappingProtocol::TCP,
-        _ => {
-            return Err(GetGenericPortMappingEntryError::RequestError(
-                RequestError::InvalidResponse("Field NewProtocol is invalid".into()),
-            ))
-        }
+        _ => return Err(make_err("Field NewProtocol is invalid".into())()),
     };
     let internal_port = extract_field("NewInternalPort")?
         .get_text()
@@ -376,11 +1081,7 @@ pub fn parse_get_generic_port_mapping_entry(
     {
         0 => false,
         1 => true,
-        _ => {
-            return Err(GetGenericPortMappingEntryError::RequestError(
-                RequestError::InvalidResponse("Field NewEnabled is invalid".into()),
-            ))
-        }
+        _ => return Err(make_err("Field NewEnabled is invalid".into())()),
     };
     let port_mapping_description = extract_field("NewPortMappingDescription")?
         .get_text()
@@ -402,31 +1103,519 @@ pub fn parse_get_generic_port_mapping_entry(
     })
 }
 
-#[test]
-fn test_parse_search_result_case_insensitivity() {
-    assert!(parse_search_result("location:http://0.0.0.0:0/control_url").is_ok());
-    assert!(parse_search_result("LOCATION:http://0.0.0.0:0/control_url").is_ok());
+// An address given as a host, which is either an IP address or a host name.
+fn parse_host_ip(host: &str) -> Option<IpAddr> {
+    host.trim().parse().ok()
 }
 
-#[test]
-fn test_parse_search_result_ok() {
-    let result = parse_search_result("location:http://0.0.0.0:0/control_url").unwrap();
-    assert_eq!(result.0.ip(), &Ipv4Addr::new(0, 0, 0, 0));
-    assert_eq!(result.0.port(), 0);
-    assert_eq!(&result.1[..], "/control_url");
+pub fn parse_get_list_of_port_mappings_response(
+    result: RequestResult,
+) -> Result<Vec<PortMappingEntry>, GetListOfPortMappingsError> {
+    let response = match result {
+        Ok(response) => response,
+        // PortMappingNotFound, there are no mappings in the range
+        Err(RequestError::ErrorCode(730, _)) => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let listing = match response.xml.get_child("NewPortListing").and_then(|e| e.get_text()) {
+        Some(listing) => listing.into_owned(),
+        None => return Ok(Vec::new()),
+    };
+    let invalid = |reason: &str| GetListOfPortMappingsError::RequestError(invalid_response(listing.clone(), reason));
+    let list = Element::parse(listing.as_bytes()).map_err(|_| invalid("invalid NewPortListing"))?;
+
+    list.children
+        .iter()
+        .filter_map(|child| child.as_element())
+        .filter(|child| child.name == "PortMappingEntry")
+        .map(|entry| parse_port_listing_entry(entry).ok_or_else(|| invalid("invalid PortMappingEntry")))
+        .collect()
 }
 
-#[test]
-fn test_parse_search_result_fail() {
-    assert!(parse_search_result("content-type:http://0.0.0.0:0/control_url").is_err());
+fn parse_port_listing_entry(entry: &Element) -> Option<PortMappingEntry> {
+    let text = |name: &str| entry.get_child(name).and_then(|e| e.get_text());
+    let protocol = match text("NewProtocol")?.as_ref() {
+        "TCP" => PortMappingProtocol::TCP,
+        "UDP" => PortMappingProtocol::UDP,
+        _ => return None,
+    };
+    let enabled = match text("NewEnabled")?.as_ref() {
+        "0" => false,
+        "1" => true,
+        _ => return None,
+    };
+    Some(PortMappingEntry {
+        remote_host: text("NewRemoteHost").map(|t| t.into_owned()).unwrap_or_default(),
+        external_port: text("NewExternalPort")?.parse().ok()?,
+        protocol,
+        internal_port: text("NewInternalPort")?.parse().ok()?,
+        internal_client: text("NewInternalClient")?.into_owned(),
+        enabled,
+        port_mapping_description: text("NewDescription").map(|t| t.into_owned()).unwrap_or_default(),
+        lease_duration: text("NewLeaseTime")?.parse().ok()?,
+    })
+}
+
+// The SID and TIMEOUT headers of the answer to a SUBSCRIBE. Gateways that grant an infinite
+// subscription, or don't say, are renewed at the requested timeout anyway.
+pub fn parse_subscribe_response(
+    sid: Option<&str>,
+    timeout: Option<&str>,
+    requested: Duration,
+) -> Result<(String, Duration), RequestError> {
+    let sid = match sid.map(str::trim) {
+        Some(sid) if !sid.is_empty() => sid.to_string(),
+        _ => return Err(invalid_response(String::new(), "missing SID header")),
+    };
+    let timeout = timeout
+        .map(str::trim)
+        .and_then(|timeout| {
+            let unit = timeout.get(..7)?;
+            unit.eq_ignore_ascii_case("Second-").then(|| &timeout[7..])
+        })
+        .and_then(|seconds| seconds.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(requested);
+    Ok((sid, timeout))
+}
+
+// The state variables and their new values of the `propertyset` sent with a GENA NOTIFY.
+pub fn parse_property_set(text: &str) -> Result<HashMap<String, String>, RequestError> {
+    let root = Element::parse(xml_start(text.as_bytes()))
+        .map_err(|e| invalid_response(text.to_string(), format!("invalid XML: {}", e)))?;
+    if root.name != "propertyset" {
+        return Err(invalid_response(text.to_string(), "missing propertyset"));
+    }
+    let variables = children(Some(&root), "property")
+        .flat_map(|property| property.children.iter().filter_map(|child| child.as_element()))
+        .map(|variable| {
+            let value = variable.get_text().map(|t| t.trim().to_string()).unwrap_or_default();
+            (variable.name.clone(), value)
+        })
+        .collect();
+    Ok(variables)
 }
 
 #[test]
-fn test_parse_device1() {
-    let text = r#"<?xml version="1.0" encoding="UTF-8"?>
-<root xmlns="urn:schemas-upnp-org:device-1-0">
-   <specVersion>
-      <major>1</major>
+fn test_parse_get_generic_port_mapping_entry() {
+    let response = |internal_client: &str| {
+        format!(
+            r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+<s:Body>
+<u:GetGenericPortMappingEntryResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewRemoteHost></NewRemoteHost>
+<NewExternalPort>1234</NewExternalPort>
+<NewProtocol>UDP</NewProtocol>
+<NewInternalPort>4321</NewInternalPort>
+<NewInternalClient>{}</NewInternalClient>
+<NewEnabled>1</NewEnabled>
+<NewPortMappingDescription>game server</NewPortMappingDescription>
+<NewLeaseDuration>3600</NewLeaseDuration>
+</u:GetGenericPortMappingEntryResponse>
+</s:Body>
+</s:Envelope>"#,
+            internal_client
+        )
+    };
+    let parse = |internal_client: &str| {
+        parse_get_generic_port_mapping_entry(parse_response(
+            response(internal_client),
+            "GetGenericPortMappingEntryResponse",
+        ))
+        .unwrap()
+    };
+
+    let entry = parse("192.168.0.2");
+    assert_eq!(entry.external_port, 1234);
+    assert_eq!(entry.protocol, PortMappingProtocol::UDP);
+    assert_eq!(entry.internal_port, 4321);
+    assert_eq!(entry.internal_client, "192.168.0.2");
+    assert_eq!(
+        entry.internal_client_ip(),
+        Some(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2)))
+    );
+    assert_eq!(entry.port_mapping_description, "game server");
+    assert_eq!(entry.lease_duration, 3600);
+
+    let entry = parse("fe80::2");
+    assert_eq!(entry.internal_client_ip(), Some("fe80::2".parse().unwrap()));
+
+    // A host name is kept as it is.
+    let entry = parse("nas.lan");
+    assert_eq!(entry.internal_client, "nas.lan");
+    assert_eq!(entry.internal_client_ip(), None);
+}
+
+#[test]
+fn test_port_mapping_entry_accessors() {
+    let entry = |remote_host: &str, lease_duration| PortMappingEntry {
+        remote_host: remote_host.to_string(),
+        external_port: 1234,
+        protocol: PortMappingProtocol::TCP,
+        internal_port: 4321,
+        internal_client: "192.168.0.2".to_string(),
+        enabled: true,
+        port_mapping_description: String::new(),
+        lease_duration,
+    };
+
+    assert_eq!(entry("", 3600).lease(), Some(Duration::from_secs(3600)));
+    assert_eq!(entry("", 0).lease(), None);
+
+    // Any remote host.
+    assert_eq!(entry("", 0).remote_host_ip(), None);
+    assert_eq!(
+        entry(" 203.0.113.9 ", 0).remote_host_ip(),
+        Some(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 9)))
+    );
+    let host_name = entry("peer.example.com", 0);
+    assert_eq!(host_name.remote_host_ip(), None);
+    assert_eq!(host_name.remote_host, "peer.example.com");
+}
+
+#[test]
+fn test_parse_get_list_of_port_mappings_response() {
+    let text = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+<s:Body>
+<u:GetListOfPortMappingsResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:2">
+<NewPortListing>&lt;?xml version="1.0" encoding="UTF-8"?&gt;
+&lt;p:PortMappingList xmlns:p="urn:schemas-upnp-org:gw:WANIPConnection"&gt;
+&lt;p:PortMappingEntry&gt;
+&lt;p:NewRemoteHost&gt;&lt;/p:NewRemoteHost&gt;
+&lt;p:NewExternalPort&gt;1234&lt;/p:NewExternalPort&gt;
+&lt;p:NewProtocol&gt;TCP&lt;/p:NewProtocol&gt;
+&lt;p:NewInternalPort&gt;4321&lt;/p:NewInternalPort&gt;
+&lt;p:NewInternalClient&gt;192.168.0.2&lt;/p:NewInternalClient&gt;
+&lt;p:NewEnabled&gt;1&lt;/p:NewEnabled&gt;
+&lt;p:NewDescription&gt;game server&lt;/p:NewDescription&gt;
+&lt;p:NewLeaseTime&gt;120&lt;/p:NewLeaseTime&gt;
+&lt;/p:PortMappingEntry&gt;
+&lt;p:PortMappingEntry&gt;
+&lt;p:NewRemoteHost&gt;&lt;/p:NewRemoteHost&gt;
+&lt;p:NewExternalPort&gt;5678&lt;/p:NewExternalPort&gt;
+&lt;p:NewProtocol&gt;UDP&lt;/p:NewProtocol&gt;
+&lt;p:NewInternalPort&gt;5678&lt;/p:NewInternalPort&gt;
+&lt;p:NewInternalClient&gt;192.168.0.3&lt;/p:NewInternalClient&gt;
+&lt;p:NewEnabled&gt;0&lt;/p:NewEnabled&gt;
+&lt;p:NewDescription&gt;&lt;/p:NewDescription&gt;
+&lt;p:NewLeaseTime&gt;0&lt;/p:NewLeaseTime&gt;
+&lt;/p:PortMappingEntry&gt;
+&lt;/p:PortMappingList&gt;</NewPortListing>
+</u:GetListOfPortMappingsResponse>
+</s:Body>
+</s:Envelope>"#;
+
+    let entries =
+        parse_get_list_of_port_mappings_response(parse_response(text.to_string(), "GetListOfPortMappingsResponse"))
+            .unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].external_port, 1234);
+    assert_eq!(entries[0].protocol, PortMappingProtocol::TCP);
+    assert_eq!(entries[0].internal_port, 4321);
+    assert_eq!(entries[0].internal_client, "192.168.0.2");
+    assert_eq!(
+        entries[0].internal_client_ip(),
+        Some(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2)))
+    );
+    assert!(entries[0].enabled);
+    assert_eq!(entries[0].port_mapping_description, "game server");
+    assert_eq!(entries[0].lease_duration, 120);
+    assert_eq!(entries[1].protocol, PortMappingProtocol::UDP);
+    assert!(!entries[1].enabled);
+}
+
+#[test]
+fn test_parse_search_result_case_insensitivity() {
+    assert!(parse_search_result("HTTP/1.1 200 OK\r\nlocation:http://0.0.0.0:0/control_url").is_ok());
+    assert!(parse_search_result("HTTP/1.1 200 OK\r\nLOCATION:http://0.0.0.0:0/control_url").is_ok());
+}
+
+#[test]
+fn test_parse_search_result_ok() {
+    let result = parse_search_result("HTTP/1.1 200 OK\r\nlocation:http://0.0.0.0:0/control_url").unwrap();
+    assert_eq!(result.addr.ip(), IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)));
+    assert_eq!(result.addr.port(), 0);
+    assert_eq!(&result.root_url[..], "/control_url");
+    assert_eq!(result.server, None);
+    assert_eq!(result.usn, None);
+}
+
+#[test]
+fn test_parse_search_result_ipv6() {
+    let result = parse_search_result("HTTP/1.1 200 OK\r\nlocation:http://[2001:db8::1]:5000/rootDesc.xml").unwrap();
+    assert_eq!(result.addr, "[2001:db8::1]:5000".parse::<SocketAddr>().unwrap());
+    assert_eq!(result.root_url, "/rootDesc.xml");
+    assert_eq!(
+        resolve_url(result.addr, &result.root_url),
+        "http://[2001:db8::1]:5000/rootDesc.xml"
+    );
+}
+
+#[test]
+fn test_parse_search_result_header_variants() {
+    let expected: SocketAddr = "[::1]:1900".parse().unwrap();
+    for location in &[
+        "LOCATION: http://[::1]:1900/rootDesc.xml",
+        "Location:http://[::1]:1900/rootDesc.xml",
+        "LOCATION :  http://[::1]:1900/rootDesc.xml  ",
+        "LOCATION:\r\n http://[::1]:1900/rootDesc.xml",
+        "LOCATION:\r\n\thttp://[::1]:1900/rootDesc.xml",
+    ] {
+        let text = format!(
+            "HTTP/1.1 200 OK\r\nST: upnp:rootdevice\r\n{}\r\nUSN: uuid:1\r\n\r\n",
+            location
+        );
+        let result = parse_search_result(&text).unwrap();
+        assert_eq!(
+            (result.addr, result.root_url.as_str()),
+            (expected, "/rootDesc.xml"),
+            "{}",
+            location
+        );
+    }
+    let text = "HTTP/1.1 200 OK\r\nLOCATION: http://192.168.1.1:5000/rootDesc.xml\r\n";
+    assert_eq!(
+        parse_search_result(text).unwrap().addr,
+        "192.168.1.1:5000".parse().unwrap()
+    );
+}
+
+#[test]
+fn test_parse_search_result_server_and_usn() {
+    let text =
+        "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\nST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\
+                USN: uuid:fc4ec57e-b051-11db-88f8-0060085db3f6::urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\
+                EXT:\r\nSERVER: Linux/3.14 UPnP/1.0 MiniUPnPd/2.1\r\n\
+                LOCATION: http://192.168.1.1:5000/rootDesc.xml\r\n\r\n";
+    let result = parse_search_result(text).unwrap();
+    assert_eq!(result.server.as_deref(), Some("Linux/3.14 UPnP/1.0 MiniUPnPd/2.1"));
+    assert_eq!(
+        result.usn.as_deref(),
+        Some("uuid:fc4ec57e-b051-11db-88f8-0060085db3f6::urn:schemas-upnp-org:device:InternetGatewayDevice:1")
+    );
+}
+
+#[test]
+fn test_parse_max_age() {
+    assert_eq!(parse_max_age("max-age=1800"), Some(Duration::from_secs(1800)));
+    assert_eq!(parse_max_age("MAX-AGE = 120"), Some(Duration::from_secs(120)));
+    assert_eq!(
+        parse_max_age("no-cache=\"Ext\", max-age=\"300\""),
+        Some(Duration::from_secs(300))
+    );
+    assert_eq!(parse_max_age("no-cache"), None);
+    assert_eq!(parse_max_age("max-age=forever"), None);
+
+    let text =
+        "HTTP/1.1 200 OK\r\nCache-Control: max-age=1800\r\nLOCATION: http://192.168.1.1:5000/rootDesc.xml\r\n\r\n";
+    assert_eq!(
+        parse_search_result(text).unwrap().max_age,
+        Some(Duration::from_secs(1800))
+    );
+}
+
+#[test]
+fn test_parse_search_result_fail() {
+    assert!(parse_search_result("HTTP/1.1 200 OK\r\ncontent-type:http://0.0.0.0:0/control_url").is_err());
+}
+
+#[test]
+fn test_parse_search_result_rejects_notify() {
+    let reply = "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\nST: upnp:rootdevice\r\n\
+                 LOCATION: http://192.168.1.1:5000/rootDesc.xml\r\n\r\n";
+    assert!(is_search_response(reply.as_bytes()));
+    assert_eq!(parse_search_result(reply).unwrap().root_url, "/rootDesc.xml");
+
+    let notify = "NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nNTS: ssdp:byebye\r\n\
+                  LOCATION: http://192.168.1.1:5000/rootDesc.xml\r\n\r\n";
+    assert!(!is_search_response(notify.as_bytes()));
+    assert!(parse_search_result(notify).is_err());
+    assert!(!is_search_response(b"HTTP/1.1 404 Not Found\r\n\r\n"));
+}
+
+#[test]
+fn test_parse_get_status_info_response() {
+    let text = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+<s:Body>
+<u:GetStatusInfoResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewConnectionStatus>Connected</NewConnectionStatus>
+<NewLastConnectionError>ERROR_NONE</NewLastConnectionError>
+<NewUptime>3600</NewUptime>
+</u:GetStatusInfoResponse>
+</s:Body>
+</s:Envelope>"#;
+
+    let info = parse_get_status_info_response(parse_response(text.to_string(), "GetStatusInfoResponse")).unwrap();
+    assert_eq!(info.connection_status, "Connected");
+    assert_eq!(info.last_connection_error, "ERROR_NONE");
+    assert_eq!(info.uptime, 3600);
+}
+
+#[test]
+fn test_parse_get_connection_type_info_response() {
+    let text = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+<s:Body>
+<u:GetConnectionTypeInfoResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewConnectionType>IP_Routed</NewConnectionType>
+<NewPossibleConnectionTypes>IP_Routed, IP_Bridged</NewPossibleConnectionTypes>
+</u:GetConnectionTypeInfoResponse>
+</s:Body>
+</s:Envelope>"#;
+
+    let info =
+        parse_get_connection_type_info_response(parse_response(text.to_string(), "GetConnectionTypeInfoResponse"))
+            .unwrap();
+    assert_eq!(info.connection_type, "IP_Routed");
+    assert_eq!(info.possible_connection_types, vec!["IP_Routed", "IP_Bridged"]);
+
+    let text = text.replace("IP_Routed, IP_Bridged", "");
+    let info = parse_get_connection_type_info_response(parse_response(text, "GetConnectionTypeInfoResponse")).unwrap();
+    assert!(info.possible_connection_types.is_empty());
+}
+
+#[test]
+fn test_parse_get_nat_rsip_status_response() {
+    let text = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+<s:Body>
+<u:GetNATRSIPStatusResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewRSIPAvailable>0</NewRSIPAvailable>
+<NewNATEnabled>1</NewNATEnabled>
+</u:GetNATRSIPStatusResponse>
+</s:Body>
+</s:Envelope>"#;
+
+    let status =
+        parse_get_nat_rsip_status_response(parse_response(text.to_string(), "GetNATRSIPStatusResponse")).unwrap();
+    assert!(!status.rsip_available);
+    assert!(status.nat_enabled);
+
+    let text = text.replace("<NewNATEnabled>1</NewNATEnabled>", "<NewNATEnabled>yes</NewNATEnabled>");
+    match parse_get_nat_rsip_status_response(parse_response(text, "GetNATRSIPStatusResponse")) {
+        Err(RequestError::InvalidResponse { ref reason, .. }) => assert_eq!(reason, "missing or invalid NewNATEnabled"),
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn test_parse_get_external_ip_response_reports_missing_field() {
+    let text = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+<s:Body>
+<u:GetExternalIPAddressResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+</u:GetExternalIPAddressResponse>
+</s:Body>
+</s:Envelope>"#;
+
+    match parse_get_external_ip_response(parse_response(text.to_string(), "GetExternalIPAddressResponse")) {
+        Err(GetExternalIpError::RequestError(RequestError::InvalidResponse { body, reason })) => {
+            assert_eq!(body, text);
+            assert_eq!(reason, "missing or invalid NewExternalIPAddress");
+        }
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn test_parse_get_external_ip_addr_response() {
+    let response = |ip: &str| {
+        format!(
+            r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+<s:Body>
+<u:GetExternalIPAddressResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:2">
+<NewExternalIPAddress>{}</NewExternalIPAddress>
+</u:GetExternalIPAddressResponse>
+</s:Body>
+</s:Envelope>"#,
+            ip
+        )
+    };
+    let parse = |ip: &str| parse_response(response(ip), "GetExternalIPAddressResponse");
+
+    let ipv4: Ipv4Addr = "203.0.113.7".parse().unwrap();
+    let ipv6: std::net::Ipv6Addr = "2001:db8::7".parse().unwrap();
+    assert_eq!(parse_get_external_ip_addr_response(parse("203.0.113.7")).unwrap(), ipv4);
+    assert_eq!(parse_get_external_ip_addr_response(parse("2001:db8::7")).unwrap(), ipv6);
+    assert_eq!(parse_get_external_ip_response(parse("203.0.113.7")).unwrap(), ipv4);
+    assert!(matches!(
+        parse_get_external_ip_response(parse("2001:db8::7")),
+        Err(GetExternalIpError::RequestError(RequestError::InvalidResponse { .. }))
+    ));
+}
+
+#[test]
+fn test_parse_specific_port_mapping_lease() {
+    let text = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+<s:Body>
+<u:GetSpecificPortMappingEntryResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewInternalPort>8080</NewInternalPort>
+<NewInternalClient>192.168.0.2</NewInternalClient>
+<NewEnabled>1</NewEnabled>
+<NewPortMappingDescription>test</NewPortMappingDescription>
+<NewLeaseDuration>3600</NewLeaseDuration>
+</u:GetSpecificPortMappingEntryResponse>
+</s:Body>
+</s:Envelope>"#;
+
+    let result = parse_response(text.to_string(), "GetSpecificPortMappingEntryResponse");
+    assert_eq!(parse_specific_port_mapping_lease(result).unwrap(), 3600);
+}
+
+#[test]
+fn test_parse_router_time() {
+    let time = parse_router_time(Some("Sun, 06 Nov 1994 08:49:37 GMT")).unwrap();
+    assert_eq!(time, SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_777));
+    match parse_router_time(None) {
+        Err(RequestError::InvalidResponse { reason, .. }) => assert_eq!(reason, "gateway sent no Date header"),
+        result => panic!("unexpected result: {:?}", result),
+    }
+    assert!(matches!(
+        parse_router_time(Some("yesterday")),
+        Err(RequestError::InvalidResponse { .. })
+    ));
+}
+
+#[test]
+fn test_parse_search_result_keeps_invalid_response() {
+    let text = "HTTP/1.1 200 OK\r\nST:ssdp:all\r\n\r\n";
+    match parse_search_result(text) {
+        Err(SearchError::InvalidResponse(body)) => assert_eq!(body, text),
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn test_resolve_url() {
+    let addr: SocketAddrV4 = "192.168.1.1:5000".parse().unwrap();
+    assert_eq!(resolve_url(addr, "/ctl/IPConn"), "http://192.168.1.1:5000/ctl/IPConn");
+    assert_eq!(
+        resolve_url(addr, "http://192.168.1.1:5001/ctl/IPConn"),
+        "http://192.168.1.1:5001/ctl/IPConn"
+    );
+}
+
+#[test]
+fn test_is_same_origin() {
+    let url = "http://192.168.1.1:5000/rootDesc.xml";
+    assert!(is_same_origin(url, "http://192.168.1.1:5000/desc/root.xml"));
+    assert!(!is_same_origin(url, "http://192.168.1.1:5001/rootDesc.xml"));
+    assert!(!is_same_origin(url, "http://192.168.1.2:5000/rootDesc.xml"));
+    assert!(!is_same_origin(url, "https://192.168.1.1:5000/rootDesc.xml"));
+    assert!(is_same_origin("http://router/", "http://router:80/"));
+}
+
+#[test]
+fn test_parse_device1() {
+    let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+   <specVersion>
+      <major>1</major>
       <minor>0</minor>
    </specVersion>
    <device>
@@ -500,9 +1689,34 @@ fn test_parse_device1() {
    </device>
 </root>"#;
 
-    let (control_schema_url, control_url) = parse_control_urls(text.as_bytes()).unwrap();
+    let (control_schema_url, control_url, service_type) = parse_control_urls(text.as_bytes()).unwrap();
     assert_eq!(control_url, "/ctl/IPConn");
     assert_eq!(control_schema_url, "/WANIPCn.xml");
+    assert_eq!(service_type, "urn:schemas-upnp-org:service:WANIPConnection:1");
+}
+
+#[test]
+fn test_parse_gateway_info() {
+    let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+   <device>
+      <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+      <friendlyName>FRITZ!Box 7430</friendlyName>
+      <manufacturer>AVM Berlin</manufacturer>
+      <modelName>FRITZ!Box 7430</modelName>
+      <modelNumber>avm</modelNumber>
+      <UDN>uuid:00000000-0000-0000-0000-000000000000</UDN>
+      <presentationURL>http://fritz.box</presentationURL>
+   </device>
+</root>"#;
+
+    let info = parse_gateway_info(text.as_bytes()).unwrap();
+    assert_eq!(info.friendly_name, "FRITZ!Box 7430");
+    assert_eq!(info.manufacturer, "AVM Berlin");
+    assert_eq!(info.model_name, "FRITZ!Box 7430");
+    assert_eq!(info.model_number, "avm");
+    assert_eq!(info.udn, "uuid:00000000-0000-0000-0000-000000000000");
+    assert_eq!(info.presentation_url.as_deref(), Some("http://fritz.box"));
 }
 
 #[test]
@@ -608,7 +1822,7 @@ fn test_parse_device2() {
     "#;
     let result = parse_control_urls(text.as_bytes());
     assert!(result.is_ok());
-    let (control_schema_url, control_url) = result.unwrap();
+    let (control_schema_url, control_url, _) = result.unwrap();
     assert_eq!(control_url, "/igdupnp/control/WANIPConn1");
     assert_eq!(control_schema_url, "/igdconnSCPD.xml");
 }
@@ -695,7 +1909,521 @@ fn test_parse_device3() {
 </device>
 </root>"#;
 
-    let (control_schema_url, control_url) = parse_control_urls(text.as_bytes()).unwrap();
+    let (control_schema_url, control_url, _) = parse_control_urls(text.as_bytes()).unwrap();
     assert_eq!(control_url, "/upnp/control/WANIPConn1");
     assert_eq!(control_schema_url, "/332b484d/wanipconnSCPD.xml");
 }
+
+#[test]
+fn test_parse_device_ppp() {
+    let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+   <device>
+      <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+      <deviceList>
+         <device>
+            <deviceType>urn:schemas-upnp-org:device:WANDevice:1</deviceType>
+            <deviceList>
+               <device>
+                  <deviceType>urn:schemas-upnp-org:device:WANConnectionDevice:1</deviceType>
+                  <serviceList>
+                     <service>
+                        <serviceType>urn:schemas-upnp-org:service:WANPPPConnection:1</serviceType>
+                        <serviceId>urn:upnp-org:serviceId:WANPPPConn1</serviceId>
+                        <controlURL>/ctl/PPPConn</controlURL>
+                        <eventSubURL>/evt/PPPConn</eventSubURL>
+                        <SCPDURL>/WANPPPCn.xml</SCPDURL>
+                     </service>
+                  </serviceList>
+               </device>
+            </deviceList>
+         </device>
+      </deviceList>
+   </device>
+</root>"#;
+
+    let (control_schema_url, control_url, service_type) = parse_control_urls(text.as_bytes()).unwrap();
+    assert_eq!(control_url, "/ctl/PPPConn");
+    assert_eq!(control_schema_url, "/WANPPPCn.xml");
+    assert_eq!(service_type, "urn:schemas-upnp-org:service:WANPPPConnection:1");
+}
+
+#[test]
+fn test_parse_device_igd2() {
+    let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0" configId="1337">
+   <specVersion>
+      <major>1</major>
+      <minor>1</minor>
+   </specVersion>
+   <device>
+      <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:2</deviceType>
+      <friendlyName>OpenWRT router</friendlyName>
+      <UDN>uuid:11111111-2222-3333-4444-555555555555</UDN>
+      <serviceList>
+         <service>
+            <serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>
+            <serviceId>urn:upnp-org:serviceId:L3Forwarding1</serviceId>
+            <controlURL>/ctl/L3F</controlURL>
+            <eventSubURL>/evt/L3F</eventSubURL>
+            <SCPDURL>/L3F.xml</SCPDURL>
+         </service>
+      </serviceList>
+      <deviceList>
+         <device>
+            <deviceType>urn:schemas-upnp-org:device:WANDevice:2</deviceType>
+            <deviceList>
+               <device>
+                  <deviceType>urn:schemas-upnp-org:device:WANConnectionDevice:2</deviceType>
+                  <serviceList>
+                     <service>
+                        <serviceType>urn:schemas-upnp-org:service:WANIPConnection:2</serviceType>
+                        <serviceId>urn:upnp-org:serviceId:WANIPConn1</serviceId>
+                        <controlURL>/ctl/IPConn</controlURL>
+                        <eventSubURL>/evt/IPConn</eventSubURL>
+                        <SCPDURL>/WANIPCn.xml</SCPDURL>
+                     </service>
+                     <service>
+                        <serviceType>urn:schemas-upnp-org:service:WANIPv6FirewallControl:1</serviceType>
+                        <serviceId>urn:upnp-org:serviceId:WANIPv6FC1</serviceId>
+                        <controlURL>/ctl/IP6FCtl</controlURL>
+                        <eventSubURL>/evt/IP6FCtl</eventSubURL>
+                        <SCPDURL>/WANIP6FC.xml</SCPDURL>
+                     </service>
+                  </serviceList>
+               </device>
+            </deviceList>
+         </device>
+      </deviceList>
+   </device>
+</root>"#;
+
+    let (control_schema_url, control_url, service_type) = parse_control_urls(text.as_bytes()).unwrap();
+    assert_eq!(control_url, "/ctl/IPConn");
+    assert_eq!(control_schema_url, "/WANIPCn.xml");
+    assert_eq!(service_type, "urn:schemas-upnp-org:service:WANIPConnection:2");
+}
+
+#[test]
+fn test_parse_device_url_base() {
+    let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+   <specVersion>
+      <major>1</major>
+      <minor>0</minor>
+   </specVersion>
+   <URLBase>http://192.168.1.1:5001/</URLBase>
+   <device>
+      <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+      <deviceList>
+         <device>
+            <deviceType>urn:schemas-upnp-org:device:WANDevice:1</deviceType>
+            <deviceList>
+               <device>
+                  <deviceType>urn:schemas-upnp-org:device:WANConnectionDevice:1</deviceType>
+                  <serviceList>
+                     <service>
+                        <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+                        <serviceId>urn:upnp-org:serviceId:WANIPConn1</serviceId>
+                        <controlURL>/ctl/IPConn</controlURL>
+                        <eventSubURL>/evt/IPConn</eventSubURL>
+                        <SCPDURL>WANIPCn.xml</SCPDURL>
+                     </service>
+                  </serviceList>
+               </device>
+            </deviceList>
+         </device>
+      </deviceList>
+   </device>
+</root>"#;
+
+    let (control_schema_url, control_url, _) = parse_control_urls(text.as_bytes()).unwrap();
+    assert_eq!(control_url, "http://192.168.1.1:5001/ctl/IPConn");
+    assert_eq!(control_schema_url, "http://192.168.1.1:5001/WANIPCn.xml");
+    let services = parse_connection_services(text.as_bytes()).unwrap();
+    assert_eq!(
+        services[0].event_url.as_deref(),
+        Some("http://192.168.1.1:5001/evt/IPConn")
+    );
+
+    // The LOCATION of the search response points at another port, URLBase takes precedence.
+    let addr: SocketAddrV4 = "192.168.1.1:5000".parse().unwrap();
+    assert_eq!(resolve_url(addr, &control_url), "http://192.168.1.1:5001/ctl/IPConn");
+}
+
+#[test]
+fn test_parse_common_interface_url() {
+    let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+   <device>
+      <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+      <deviceList>
+         <device>
+            <deviceType>urn:schemas-upnp-org:device:WANDevice:1</deviceType>
+            <serviceList>
+               <service>
+                  <serviceType>urn:schemas-upnp-org:service:WANCommonInterfaceConfig:1</serviceType>
+                  <serviceId>urn:upnp-org:serviceId:WANCommonIFC1</serviceId>
+                  <controlURL>/ctl/CmnIfCfg</controlURL>
+                  <eventSubURL>/evt/CmnIfCfg</eventSubURL>
+                  <SCPDURL>/WANCfg.xml</SCPDURL>
+               </service>
+            </serviceList>
+            <deviceList>
+               <device>
+                  <deviceType>urn:schemas-upnp-org:device:WANConnectionDevice:1</deviceType>
+                  <serviceList>
+                     <service>
+                        <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+                        <serviceId>urn:upnp-org:serviceId:WANIPConn1</serviceId>
+                        <controlURL>/ctl/IPConn</controlURL>
+                        <eventSubURL>/evt/IPConn</eventSubURL>
+                        <SCPDURL>/WANIPCn.xml</SCPDURL>
+                     </service>
+                  </serviceList>
+               </device>
+            </deviceList>
+         </device>
+      </deviceList>
+   </device>
+</root>"#;
+
+    let (_, control_url, _) = parse_control_urls(text.as_bytes()).unwrap();
+    assert_eq!(control_url, "/ctl/IPConn");
+    let services = parse_services(text.as_bytes()).unwrap();
+    let common_interface = &services[messages::COMMON_INTERFACE_SERVICE_TYPE];
+    assert_eq!(common_interface.control_url, "/ctl/CmnIfCfg");
+    assert_eq!(common_interface.event_url.as_deref(), Some("/evt/CmnIfCfg"));
+
+    let without = text.replace("WANCommonInterfaceConfig", "Layer3Forwarding");
+    let services = parse_services(without.as_bytes()).unwrap();
+    assert!(!services.contains_key(messages::COMMON_INTERFACE_SERVICE_TYPE));
+}
+
+#[test]
+fn test_parse_total_response() {
+    let text = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+   <s:Body>
+      <u:GetTotalBytesSentResponse xmlns:u="urn:schemas-upnp-org:service:WANCommonInterfaceConfig:1">
+         <NewTotalBytesSent>4294967295</NewTotalBytesSent>
+      </u:GetTotalBytesSentResponse>
+   </s:Body>
+</s:Envelope>"#;
+
+    let result = parse_response(text.to_string(), "GetTotalBytesSentResponse");
+    assert_eq!(parse_total_response(result, "NewTotalBytesSent").unwrap(), 4294967295);
+
+    let result = parse_response(text.to_string(), "GetTotalBytesSentResponse");
+    match parse_total_response(result, "NewTotalBytesReceived") {
+        Err(RequestError::InvalidResponse { reason, .. }) => {
+            assert_eq!(reason, "missing or invalid NewTotalBytesReceived")
+        }
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn test_parse_get_link_properties_response() {
+    let text = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+   <s:Body>
+      <u:GetCommonLinkPropertiesResponse xmlns:u="urn:schemas-upnp-org:service:WANCommonInterfaceConfig:1">
+         <NewWANAccessType>Cable</NewWANAccessType>
+         <NewLayer1UpstreamMaxBitRate>20000000</NewLayer1UpstreamMaxBitRate>
+         <NewLayer1DownstreamMaxBitRate>200000000</NewLayer1DownstreamMaxBitRate>
+         <NewPhysicalLinkStatus>Up</NewPhysicalLinkStatus>
+      </u:GetCommonLinkPropertiesResponse>
+   </s:Body>
+</s:Envelope>"#;
+
+    let result = parse_response(text.to_string(), "GetCommonLinkPropertiesResponse");
+    let properties = parse_get_link_properties_response(result).unwrap();
+    assert_eq!(properties.access_type, "Cable");
+    assert_eq!(properties.upstream_max_bit_rate, 20_000_000);
+    assert_eq!(properties.downstream_max_bit_rate, 200_000_000);
+    assert_eq!(properties.physical_link_status, "Up");
+}
+
+#[test]
+fn test_convert_add_port_error_codes() {
+    let error = |code| RequestError::ErrorCode(code, "Error".to_string());
+    assert!(matches!(
+        convert_add_port_error(error(716)),
+        AddPortError::WildcardNotPermittedInExternalPort(_)
+    ));
+    assert!(matches!(
+        convert_add_port_error(error(729)),
+        AddPortError::ConflictWithOtherMechanisms(_)
+    ));
+    assert!(matches!(
+        convert_add_random_port_mapping_error(error(727)),
+        Some(AddAnyPortError::ExternalPortOnlySupportsWildcard(_))
+    ));
+    assert!(matches!(
+        convert_add_same_port_mapping_error(error(715)),
+        AddAnyPortError::WildcardNotPermittedInRemoteHost(_)
+    ));
+    assert!(matches!(
+        parse_add_any_port_mapping_response(Err(error(729))),
+        Err(AddAnyPortError::ConflictWithOtherMechanisms(_))
+    ));
+    assert!(matches!(
+        convert_add_port_error(error(501)),
+        AddPortError::RequestError(RequestError::ErrorCode(501, _))
+    ));
+}
+
+#[test]
+fn test_parse_response_soap_fault() {
+    let text = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+   <s:Body>
+      <s:Fault>
+         <faultcode>s:Client</faultcode>
+         <faultstring>UPnPError</faultstring>
+      </s:Fault>
+   </s:Body>
+</s:Envelope>"#;
+
+    match parse_response(text.to_string(), "GetExternalIPAddressResponse") {
+        Err(RequestError::SoapFault { code, string }) => {
+            assert_eq!(code, "s:Client");
+            assert_eq!(string, "UPnPError");
+        }
+        _ => panic!("expected a SOAP fault"),
+    }
+
+    let text = text
+        .replace("<faultcode>s:Client</faultcode>", "")
+        .replace("<faultstring>UPnPError</faultstring>", "");
+    match parse_response(text, "GetExternalIPAddressResponse") {
+        Err(RequestError::InvalidResponse { reason, .. }) => assert_eq!(reason, "missing UPnPError"),
+        _ => panic!("expected an invalid response"),
+    }
+}
+
+#[test]
+fn test_parse_response_invalid_action() {
+    let text = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+   <s:Body>
+      <s:Fault>
+         <faultcode>s:Client</faultcode>
+         <faultstring>UPnPError</faultstring>
+         <detail>
+            <UPnPError xmlns="urn:schemas-upnp-org:control-1-0">
+               <errorCode>401</errorCode>
+               <errorDescription>Invalid Action</errorDescription>
+            </UPnPError>
+         </detail>
+      </s:Fault>
+   </s:Body>
+</s:Envelope>"#;
+
+    match parse_response(text.to_string(), "GetStatusInfoResponse") {
+        Err(RequestError::UnsupportedAction(action)) => assert_eq!(action, "GetStatusInfo"),
+        r => panic!("unexpected result: {:?}", r.map(|resp| resp.text)),
+    }
+}
+
+#[test]
+fn test_parse_response_namespace_prefixes() {
+    // Elements are matched by their local name, whatever the prefix of their namespace.
+    let text = r#"<?xml version="1.0"?>
+<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://schemas.xmlsoap.org/soap/envelope/" SOAP-ENV:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+   <SOAP-ENV:Body>
+      <m:GetExternalIPAddressResponse xmlns:m="urn:schemas-upnp-org:service:WANIPConnection:1">
+         <NewExternalIPAddress>1.2.3.4</NewExternalIPAddress>
+      </m:GetExternalIPAddressResponse>
+   </SOAP-ENV:Body>
+</SOAP-ENV:Envelope>"#;
+    let result = parse_response(text.to_string(), "GetExternalIPAddressResponse");
+    assert_eq!(
+        parse_get_external_ip_response(result).unwrap(),
+        Ipv4Addr::new(1, 2, 3, 4)
+    );
+
+    let text = r#"<?xml version="1.0"?>
+<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://schemas.xmlsoap.org/soap/envelope/">
+   <SOAP-ENV:Body>
+      <SOAP-ENV:Fault>
+         <faultcode>SOAP-ENV:Client</faultcode>
+         <faultstring>UPnPError</faultstring>
+         <detail>
+            <u:UPnPError xmlns:u="urn:schemas-upnp-org:control-1-0">
+               <u:errorCode>718</u:errorCode>
+               <u:errorDescription>ConflictInMappingEntry</u:errorDescription>
+            </u:UPnPError>
+         </detail>
+      </SOAP-ENV:Fault>
+   </SOAP-ENV:Body>
+</SOAP-ENV:Envelope>"#;
+    match parse_response(text.to_string(), "AddPortMappingResponse") {
+        Err(RequestError::ErrorCode(718, description)) => assert_eq!(description, "ConflictInMappingEntry"),
+        _ => panic!("expected error code 718"),
+    }
+}
+
+#[test]
+fn test_parse_control_urls_bom() {
+    let text = "\u{feff}\n<?xml version=\"1.0\"?>\n<root xmlns=\"urn:schemas-upnp-org:device-1-0\"><device><serviceList><service>\
+                <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>\
+                <controlURL>/ctl/IPConn</controlURL><SCPDURL>/WANIPCn.xml</SCPDURL>\
+                </service></serviceList></device></root>";
+    let (control_schema_url, control_url, _) = parse_control_urls(text.as_bytes()).unwrap();
+    assert_eq!(control_url, "/ctl/IPConn");
+    assert_eq!(control_schema_url, "/WANIPCn.xml");
+}
+
+#[test]
+fn test_parse_response_bom() {
+    let text =
+        "\u{feff}<?xml version=\"1.0\"?>\n<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\"><s:Body>\
+                <u:GetExternalIPAddressResponse xmlns:u=\"urn:schemas-upnp-org:service:WANIPConnection:1\">\
+                <NewExternalIPAddress>1.2.3.4</NewExternalIPAddress>\
+                </u:GetExternalIPAddressResponse></s:Body></s:Envelope>";
+    let result = parse_response(text.to_string(), "GetExternalIPAddressResponse");
+    assert_eq!(
+        parse_get_external_ip_response(result).unwrap(),
+        Ipv4Addr::new(1, 2, 3, 4)
+    );
+}
+
+#[test]
+fn test_parse_connection_services_multi_wan() {
+    let text = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+   <device>
+      <UDN>uuid:root</UDN>
+      <serviceList>
+         <service>
+            <serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>
+            <serviceId>urn:upnp-org:serviceId:L3Forwarding1</serviceId>
+            <controlURL>/ctl/L3F</controlURL>
+            <SCPDURL>/L3F.xml</SCPDURL>
+         </service>
+      </serviceList>
+      <deviceList>
+         <device>
+            <UDN>uuid:wan-1</UDN>
+            <serviceList>
+               <service>
+                  <serviceType>urn:schemas-upnp-org:service:WANPPPConnection:1</serviceType>
+                  <serviceId>urn:upnp-org:serviceId:WANPPPConn1</serviceId>
+                  <controlURL>/ctl/PPPConn</controlURL>
+                  <SCPDURL>/WANPPPCn.xml</SCPDURL>
+               </service>
+            </serviceList>
+         </device>
+         <device>
+            <UDN>uuid:wan-2</UDN>
+            <serviceList>
+               <service>
+                  <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+                  <serviceId>urn:upnp-org:serviceId:WANIPConn1</serviceId>
+                  <controlURL>/ctl/IPConn</controlURL>
+                  <SCPDURL>/WANIPCn.xml</SCPDURL>
+               </service>
+            </serviceList>
+         </device>
+      </deviceList>
+   </device>
+</root>"#;
+
+    let services = parse_connection_services(text.as_bytes()).unwrap();
+    let control_urls: Vec<&str> = services.iter().map(|service| service.urls.1.as_str()).collect();
+    assert_eq!(control_urls, vec!["/ctl/PPPConn", "/ctl/IPConn"]);
+    assert_eq!(services[1].udn, "uuid:wan-2");
+    assert_eq!(services[1].service_id, "urn:upnp-org:serviceId:WANIPConn1");
+    let services_by_type = parse_services(text.as_bytes()).unwrap();
+    assert_eq!(
+        services_by_type[messages::LAYER3_FORWARDING_SERVICE_TYPE].control_url,
+        "/ctl/L3F"
+    );
+    // The first connection service of each type is kept.
+    assert_eq!(
+        services_by_type["urn:schemas-upnp-org:service:WANPPPConnection:1"].control_url,
+        "/ctl/PPPConn"
+    );
+
+    let default = find_default_connection_service(
+        &services,
+        "uuid:wan-2:WANConnectionDevice:1,urn:upnp-org:serviceId:WANIPConn1",
+    );
+    assert_eq!(default.map(|service| service.urls.1.as_str()), Some("/ctl/IPConn"));
+    assert!(find_default_connection_service(
+        &services,
+        "uuid:wan-3:WANConnectionDevice:1,urn:upnp-org:serviceId:WANIPConn1"
+    )
+    .is_none());
+    assert!(find_default_connection_service(&services, "").is_none());
+}
+
+#[cfg(feature = "aio")]
+#[test]
+fn test_parse_notify() {
+    let notify = "NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nCACHE-CONTROL: max-age=120\r\n\
+                  LOCATION: http://192.168.1.1:5000/rootDesc.xml\r\n\
+                  NT: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\nNTS: ssdp:alive\r\n\
+                  USN: uuid:00000000-0000-0000-0000-000000000000::urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\r\n";
+    assert_eq!(
+        parse_notify(notify).unwrap(),
+        Some(SearchResponse {
+            addr: "192.168.1.1:5000".parse().unwrap(),
+            root_url: "/rootDesc.xml".to_string(),
+            server: None,
+            usn: Some(
+                "uuid:00000000-0000-0000-0000-000000000000::urn:schemas-upnp-org:device:InternetGatewayDevice:1"
+                    .to_string()
+            ),
+            max_age: Some(Duration::from_secs(120)),
+        })
+    );
+    assert_eq!(
+        parse_notify(&notify.replace("ssdp:alive", "ssdp:byebye")).unwrap(),
+        None
+    );
+    assert_eq!(
+        parse_notify(&notify.replace("InternetGatewayDevice", "MediaServer")).unwrap(),
+        None
+    );
+    assert_eq!(parse_notify("HTTP/1.1 200 OK\r\n\r\n").unwrap(), None);
+    assert!(parse_notify(&notify.replace("http://192.168.1.1:5000", "nowhere")).is_err());
+}
+
+#[test]
+fn test_parse_subscribe_response() {
+    let requested = Duration::from_secs(1800);
+    let (sid, timeout) = parse_subscribe_response(Some(" uuid:1234 "), Some("Second-300"), requested).unwrap();
+    assert_eq!(sid, "uuid:1234");
+    assert_eq!(timeout, Duration::from_secs(300));
+    let (_, timeout) = parse_subscribe_response(Some("uuid:1234"), Some("Second-infinite"), requested).unwrap();
+    assert_eq!(timeout, requested);
+    let (_, timeout) = parse_subscribe_response(Some("uuid:1234"), None, requested).unwrap();
+    assert_eq!(timeout, requested);
+    assert!(matches!(
+        parse_subscribe_response(None, Some("Second-300"), requested),
+        Err(RequestError::InvalidResponse { .. })
+    ));
+}
+
+#[test]
+fn test_parse_property_set() {
+    let text = r#"<?xml version="1.0"?>
+<e:propertyset xmlns:e="urn:schemas-upnp-org:event-1-0">
+<e:property><ExternalIPAddress>203.0.113.1</ExternalIPAddress></e:property>
+<e:property><ConnectionStatus>Connected</ConnectionStatus></e:property>
+<e:property><PortMappingNumberOfEntries></PortMappingNumberOfEntries></e:property>
+</e:propertyset>"#;
+
+    let variables = parse_property_set(text).unwrap();
+    assert_eq!(variables.len(), 3);
+    assert_eq!(variables["ExternalIPAddress"], "203.0.113.1");
+    assert_eq!(variables["ConnectionStatus"], "Connected");
+    assert_eq!(variables["PortMappingNumberOfEntries"], "");
+    assert!(matches!(
+        parse_property_set("<root/>"),
+        Err(RequestError::InvalidResponse { .. })
+    ));
+}
@@ -0,0 +1,63 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use rand::{self, Rng};
+
+/// Picks the external ports `add_any_port` tries when the gateway does not choose one itself.
+///
+/// The default is `RandomPortPicker`. Another picker can be set on the `port_picker` field of the
+/// gateway, to try a fixed sequence of ports in tests for instance.
+pub trait PortPicker: fmt::Debug + Send + Sync {
+    /// Return the next port to try, within the `port_range` of the gateway.
+    fn next_port(&self, range: &RangeInclusive<u16>) -> u16;
+}
+
+/// Port picker that draws each port at random from the range.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RandomPortPicker;
+
+impl PortPicker for RandomPortPicker {
+    fn next_port(&self, range: &RangeInclusive<u16>) -> u16 {
+        random_port(range)
+    }
+}
+
+// A random port of the range, or its start if the range is empty.
+pub fn random_port(range: &RangeInclusive<u16>) -> u16 {
+    if range.is_empty() {
+        return *range.start();
+    }
+    rand::thread_rng().gen_range(range.clone())
+}
+
+// Port picker that returns the given ports in order, ignoring the range.
+#[cfg(all(test, feature = "aio"))]
+#[derive(Debug)]
+pub(crate) struct SequencePortPicker(std::sync::Mutex<std::collections::VecDeque<u16>>);
+
+#[cfg(all(test, feature = "aio"))]
+impl SequencePortPicker {
+    pub(crate) fn new(ports: Vec<u16>) -> SequencePortPicker {
+        SequencePortPicker(std::sync::Mutex::new(ports.into()))
+    }
+}
+
+#[cfg(all(test, feature = "aio"))]
+impl PortPicker for SequencePortPicker {
+    fn next_port(&self, _range: &RangeInclusive<u16>) -> u16 {
+        self.0.lock().unwrap().pop_front().expect("no port left")
+    }
+}
+
+#[test]
+fn test_random_port() {
+    let default_range = super::DEFAULT_PORT_RANGE;
+    for _ in 0..100 {
+        assert!(default_range.contains(&RandomPortPicker.next_port(&default_range)));
+        assert!((40_000..=40_010).contains(&random_port(&(40_000..=40_010))));
+    }
+    assert_eq!(random_port(&(65_535..=65_535)), 65_535);
+    #[allow(clippy::reversed_empty_ranges)]
+    let empty = 40_000..=39_999;
+    assert_eq!(random_port(&empty), 40_000);
+}
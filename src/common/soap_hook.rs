@@ -0,0 +1,15 @@
+use std::fmt;
+
+use crate::errors::RequestError;
+
+/// Shown every SOAP request sent to a gateway along with its response, to see the exact XML a
+/// misbehaving gateway exchanges.
+///
+/// Set it with `SearchOptions::soap_hook`; the gateways found by the search keep it in their
+/// `soap_hook` field. It is called once per request sent, so a request that is retried after the
+/// gateway reset the connection is shown again.
+pub trait SoapHook: fmt::Debug + Send + Sync {
+    /// Called once the response to `action` arrived or sending it failed. `request` is the SOAP
+    /// envelope that was sent and `response` the body the gateway answered, which may be a fault.
+    fn on_exchange(&self, action: &str, request: &str, response: Result<&str, &RequestError>);
+}
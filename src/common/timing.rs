@@ -0,0 +1,57 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Told how long each network operation took, to find out which ones are slow.
+///
+/// Set it with `SearchOptions::timing_hook`; the gateways found by the search keep it in their
+/// `timing_hook` field. The operations are `search`, `fetch_description`, `get_router_time`, and
+/// the name of each action sent to the gateway, such as `AddPortMapping`. Retries of an action are
+/// part of its time.
+pub trait TimingHook: fmt::Debug + Send + Sync {
+    /// Called once `operation` finished, whether it succeeded or not.
+    fn on_timing(&self, operation: &str, elapsed: Duration);
+}
+
+// Reports the time from its creation to its drop to the hook, if there is one. Without a hook the
+// clock is not read at all.
+pub struct Timing<'a> {
+    started: Option<(&'a dyn TimingHook, &'a str, Instant)>,
+}
+
+impl<'a> Timing<'a> {
+    pub fn start(hook: &'a Option<Arc<dyn TimingHook>>, operation: &'a str) -> Timing<'a> {
+        Timing {
+            started: hook.as_deref().map(|hook| (hook, operation, Instant::now())),
+        }
+    }
+}
+
+impl Drop for Timing<'_> {
+    fn drop(&mut self) {
+        if let Some((hook, operation, start)) = self.started {
+            hook.on_timing(operation, start.elapsed());
+        }
+    }
+}
+
+#[test]
+fn test_timing() {
+    #[derive(Debug, Default)]
+    struct RecordingHook(std::sync::Mutex<Vec<String>>);
+
+    impl TimingHook for RecordingHook {
+        fn on_timing(&self, operation: &str, _: Duration) {
+            self.0.lock().unwrap().push(operation.to_string());
+        }
+    }
+
+    let recording = Arc::new(RecordingHook::default());
+    let hook: Option<Arc<dyn TimingHook>> = Some(recording.clone());
+    {
+        let _timing = Timing::start(&hook, "AddPortMapping");
+        assert!(recording.0.lock().unwrap().is_empty());
+    }
+    drop(Timing::start(&None, "search"));
+    assert_eq!(*recording.0.lock().unwrap(), vec!["AddPortMapping"]);
+}
@@ -16,11 +16,28 @@ pub enum RequestError {
     /// IO Error
     IoError(io::Error),
     /// The response from the gateway could not be parsed.
-    InvalidResponse(String),
+    InvalidResponse {
+        /// The response as received from the gateway
+        body: String,
+        /// What is wrong with it, such as `missing NewExternalIPAddress`
+        reason: String,
+    },
     /// The gateway returned an unhandled error code and description.
     ErrorCode(u16, String),
+    /// The gateway returned a SOAP fault without the UPnP error code, as some non-compliant
+    /// gateways do.
+    SoapFault {
+        /// The `faultcode`, such as `s:Client`
+        code: String,
+        /// The `faultstring`, which describes the fault
+        string: String,
+    },
     /// Action is not supported by the gateway
     UnsupportedAction(String),
+    /// The gateway answered with an HTTP status code other than success or a SOAP fault
+    HttpStatus(u16),
+    /// The gateway did not answer before the timeout of the request
+    Timeout,
     /// When using the aio feature.
     #[cfg(feature = "aio")]
     HyperError(hyper::Error),
@@ -36,16 +53,28 @@ pub enum RequestError {
 
 impl From<attohttpc::Error> for RequestError {
     fn from(err: attohttpc::Error) -> RequestError {
-        RequestError::AttoHttpError(err)
+        match err.kind() {
+            attohttpc::ErrorKind::Io(e) if is_timeout(e) => RequestError::Timeout,
+            _ => RequestError::AttoHttpError(err),
+        }
     }
 }
 
 impl From<io::Error> for RequestError {
     fn from(err: io::Error) -> RequestError {
-        RequestError::IoError(err)
+        if is_timeout(&err) {
+            RequestError::Timeout
+        } else {
+            RequestError::IoError(err)
+        }
     }
 }
 
+// A socket read timeout is reported as `WouldBlock` on some platforms.
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock)
+}
+
 #[cfg(feature = "aio")]
 impl From<http::Error> for RequestError {
     fn from(err: http::Error) -> RequestError {
@@ -56,7 +85,11 @@ impl From<http::Error> for RequestError {
 #[cfg(feature = "aio")]
 impl From<hyper::Error> for RequestError {
     fn from(err: hyper::Error) -> RequestError {
-        RequestError::HyperError(err)
+        if err.is_timeout() {
+            RequestError::Timeout
+        } else {
+            RequestError::HyperError(err)
+        }
     }
 }
 
@@ -70,7 +103,7 @@ impl From<FromUtf8Error> for RequestError {
 #[cfg(feature = "aio")]
 impl From<Elapsed> for RequestError {
     fn from(_err: Elapsed) -> RequestError {
-        RequestError::IoError(io::Error::new(io::ErrorKind::TimedOut, "timer failed"))
+        RequestError::Timeout
     }
 }
 
@@ -78,10 +111,15 @@ impl fmt::Display for RequestError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             RequestError::AttoHttpError(ref e) => write!(f, "HTTP error {}", e),
-            RequestError::InvalidResponse(ref e) => write!(f, "Invalid response from gateway: {}", e),
+            RequestError::InvalidResponse { ref body, ref reason } => {
+                write!(f, "Invalid response from gateway, {}: {}", reason, body)
+            }
             RequestError::IoError(ref e) => write!(f, "IO error. {}", e),
             RequestError::ErrorCode(n, ref e) => write!(f, "Gateway response error {}: {}", n, e),
+            RequestError::SoapFault { ref code, ref string } => write!(f, "Gateway SOAP fault {}: {}", code, string),
             RequestError::UnsupportedAction(ref e) => write!(f, "Gateway does not support action: {}", e),
+            RequestError::HttpStatus(status) => write!(f, "Gateway answered with HTTP status {}", status),
+            RequestError::Timeout => write!(f, "Request to the gateway timed out"),
             #[cfg(feature = "aio")]
             RequestError::HyperError(ref e) => write!(f, "Hyper Error: {}", e),
             #[cfg(feature = "aio")]
@@ -96,10 +134,13 @@ impl std::error::Error for RequestError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match *self {
             RequestError::AttoHttpError(ref e) => Some(e),
-            RequestError::InvalidResponse(..) => None,
+            RequestError::InvalidResponse { .. } => None,
             RequestError::IoError(ref e) => Some(e),
             RequestError::ErrorCode(..) => None,
+            RequestError::SoapFault { .. } => None,
             RequestError::UnsupportedAction(..) => None,
+            RequestError::HttpStatus(..) => None,
+            RequestError::Timeout => None,
             #[cfg(feature = "aio")]
             RequestError::HyperError(ref e) => Some(e),
             #[cfg(feature = "aio")]
@@ -126,26 +167,44 @@ pub enum RemovePortError {
     ActionNotAuthorized,
     /// No such port mapping.
     NoSuchPortMapping,
+    /// The start port of the range is greater than the end port.
+    InconsistentParameters,
     /// Some other error occured performing the request.
     RequestError(RequestError),
 }
 
 /// Errors returned by `Gateway::add_any_port` and `Gateway::get_any_address`
+///
+/// The variants holding an `Option<String>` hold the `errorDescription` of the UPnP error the
+/// gateway answered with, or `None` when the crate found out the error itself.
 #[derive(Debug)]
 pub enum AddAnyPortError {
     /// The client is not authorized to perform the operation.
-    ActionNotAuthorized,
+    ActionNotAuthorized(Option<String>),
     /// Can not add a mapping for local port 0.
     InternalPortZeroInvalid,
+    /// Can not add a mapping for local address 0.0.0.0.
+    InternalClientUnspecified,
+    /// Can not add a mapping for a loopback, multicast or broadcast local address.
+    InternalClientInvalid,
     /// The gateway does not have any free ports.
-    NoPortsAvailable,
+    NoPortsAvailable(Option<String>),
     /// The gateway can only map internal ports to same-numbered external ports
     /// and this external port is in use.
-    ExternalPortInUse,
+    ExternalPortInUse(Option<String>),
     /// The gateway only supports permanent leases (ie. a `lease_duration` of 0).
-    OnlyPermanentLeasesSupported,
+    OnlyPermanentLeasesSupported(Option<String>),
     /// The description was too long for the gateway to handle.
-    DescriptionTooLong,
+    DescriptionTooLong(Option<String>),
+    /// The lease duration is longer than the one week UPnP allows.
+    LeaseDurationTooLong,
+    /// The gateway requires a specific remote host instead of any host.
+    WildcardNotPermittedInRemoteHost(Option<String>),
+    /// The gateway only supports mappings for any external port, so it can't pick one.
+    ExternalPortOnlySupportsWildcard(Option<String>),
+    /// The mapping conflicts with one made by another mechanism than UPnP, such as a static
+    /// forward configured on the gateway.
+    ConflictWithOtherMechanisms(Option<String>),
     /// Some other error occured performing the request.
     RequestError(RequestError),
 }
@@ -159,29 +218,50 @@ impl From<RequestError> for AddAnyPortError {
 impl From<GetExternalIpError> for AddAnyPortError {
     fn from(err: GetExternalIpError) -> AddAnyPortError {
         match err {
-            GetExternalIpError::ActionNotAuthorized => AddAnyPortError::ActionNotAuthorized,
+            GetExternalIpError::ActionNotAuthorized => AddAnyPortError::ActionNotAuthorized(None),
             GetExternalIpError::RequestError(e) => AddAnyPortError::RequestError(e),
         }
     }
 }
 
 /// Errors returned by `Gateway::add_port`
+///
+/// The variants holding an `Option<String>` hold the `errorDescription` of the UPnP error the
+/// gateway answered with, which often tells more than the code, such as which of its rules the
+/// gateway applied. It is `None` when the crate found out the error itself.
 #[derive(Debug)]
 pub enum AddPortError {
     /// The client is not authorized to perform the operation.
-    ActionNotAuthorized,
+    ActionNotAuthorized(Option<String>),
     /// Can not add a mapping for local port 0.
     InternalPortZeroInvalid,
+    /// Can not add a mapping for local address 0.0.0.0.
+    InternalClientUnspecified,
+    /// Can not add a mapping for a loopback, multicast or broadcast local address.
+    InternalClientInvalid,
     /// External port number 0 (any port) is considered invalid by the gateway.
     ExternalPortZeroInvalid,
     /// The requested mapping conflicts with a mapping assigned to another client.
-    PortInUse,
+    PortInUse(Option<String>),
     /// The gateway requires that the requested internal and external ports are the same.
-    SamePortValuesRequired,
+    SamePortValuesRequired(Option<String>),
     /// The gateway only supports permanent leases (ie. a `lease_duration` of 0).
-    OnlyPermanentLeasesSupported,
+    OnlyPermanentLeasesSupported(Option<String>),
     /// The description was too long for the gateway to handle.
-    DescriptionTooLong,
+    DescriptionTooLong(Option<String>),
+    /// The gateway requires a specific remote host instead of any host.
+    WildcardNotPermittedInRemoteHost(Option<String>),
+    /// The gateway only supports mappings for any remote host.
+    RemoteHostOnlySupportsWildcard(Option<String>),
+    /// The gateway only supports mappings for any external port.
+    ExternalPortOnlySupportsWildcard(Option<String>),
+    /// The gateway does not accept mappings for any external port, a specific one is required.
+    WildcardNotPermittedInExternalPort(Option<String>),
+    /// The mapping conflicts with one made by another mechanism than UPnP, such as a static
+    /// forward configured on the gateway.
+    ConflictWithOtherMechanisms(Option<String>),
+    /// The lease duration is longer than the one week UPnP allows.
+    LeaseDurationTooLong,
     /// Some other error occured performing the request.
     RequestError(RequestError),
 }
@@ -203,7 +283,10 @@ impl From<io::Error> for GetExternalIpError {
 
 impl std::error::Error for GetExternalIpError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        None
+        match *self {
+            GetExternalIpError::RequestError(ref e) => Some(e),
+            _ => None,
+        }
     }
 }
 
@@ -212,6 +295,7 @@ impl fmt::Display for RemovePortError {
         match *self {
             RemovePortError::ActionNotAuthorized => write!(f, "The client is not authorized to remove the port"),
             RemovePortError::NoSuchPortMapping => write!(f, "The port was not mapped"),
+            RemovePortError::InconsistentParameters => write!(f, "The start port is greater than the end port"),
             RemovePortError::RequestError(ref e) => write!(f, "Request error. {}", e),
         }
     }
@@ -219,78 +303,183 @@ impl fmt::Display for RemovePortError {
 
 impl std::error::Error for RemovePortError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        None
+        match *self {
+            RemovePortError::RequestError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl AddAnyPortError {
+    /// The `errorDescription` of the UPnP error the gateway answered with, if there was one.
+    pub fn error_description(&self) -> Option<&str> {
+        match *self {
+            AddAnyPortError::DescriptionTooLong(ref description)
+            | AddAnyPortError::ActionNotAuthorized(ref description)
+            | AddAnyPortError::WildcardNotPermittedInRemoteHost(ref description)
+            | AddAnyPortError::NoPortsAvailable(ref description)
+            | AddAnyPortError::ConflictWithOtherMechanisms(ref description)
+            | AddAnyPortError::ExternalPortInUse(ref description)
+            | AddAnyPortError::OnlyPermanentLeasesSupported(ref description)
+            | AddAnyPortError::ExternalPortOnlySupportsWildcard(ref description) => description.as_deref(),
+            _ => None,
+        }
     }
 }
 
 impl fmt::Display for AddAnyPortError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            AddAnyPortError::ActionNotAuthorized => {
+            AddAnyPortError::ActionNotAuthorized(_) => {
                 write!(f, "The client is not authorized to remove the port")
             }
             AddAnyPortError::InternalPortZeroInvalid => {
                 write!(f, "Can not add a mapping for local port 0")
             }
-            AddAnyPortError::NoPortsAvailable => {
+            AddAnyPortError::InternalClientUnspecified => {
+                write!(f, "Can not add a mapping for local address 0.0.0.0")
+            }
+            AddAnyPortError::InternalClientInvalid => {
+                write!(
+                    f,
+                    "Can not add a mapping for a loopback, multicast or broadcast local address"
+                )
+            }
+            AddAnyPortError::NoPortsAvailable(_) => {
                 write!(f, "The gateway does not have any free ports")
             }
-            AddAnyPortError::OnlyPermanentLeasesSupported => {
+            AddAnyPortError::OnlyPermanentLeasesSupported(_) => {
                 write!(
                     f,
                     "The gateway only supports permanent leases (ie. a `lease_duration` of 0),"
                 )
             }
-            AddAnyPortError::ExternalPortInUse => {
+            AddAnyPortError::ExternalPortInUse(_) => {
                 write!(
                     f,
                     "The gateway can only map internal ports to same-numbered external ports and this external port is in use."
                 )
             }
-            AddAnyPortError::DescriptionTooLong => {
+            AddAnyPortError::DescriptionTooLong(_) => {
                 write!(f, "The description was too long for the gateway to handle.")
             }
+            AddAnyPortError::LeaseDurationTooLong => {
+                write!(f, "The lease duration is longer than the maximum of one week.")
+            }
+            AddAnyPortError::WildcardNotPermittedInRemoteHost(_) => write!(
+                f,
+                "The gateway does not accept mappings for any remote host, a specific one is required."
+            ),
+            AddAnyPortError::ExternalPortOnlySupportsWildcard(_) => write!(
+                f,
+                "The gateway only accepts mappings for any external port, not a specific one."
+            ),
+            AddAnyPortError::ConflictWithOtherMechanisms(_) => {
+                write!(f, "The mapping conflicts with one made by another mechanism than UPnP.")
+            }
             AddAnyPortError::RequestError(ref e) => write!(f, "Request error. {}", e),
+        }?;
+        match self.error_description() {
+            Some(description) => write!(f, " ({})", description),
+            None => Ok(()),
         }
     }
 }
 
 impl std::error::Error for AddAnyPortError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        None
+        match *self {
+            AddAnyPortError::RequestError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl AddPortError {
+    /// The `errorDescription` of the UPnP error the gateway answered with, if there was one.
+    pub fn error_description(&self) -> Option<&str> {
+        match *self {
+            AddPortError::DescriptionTooLong(ref description)
+            | AddPortError::ActionNotAuthorized(ref description)
+            | AddPortError::WildcardNotPermittedInRemoteHost(ref description)
+            | AddPortError::WildcardNotPermittedInExternalPort(ref description)
+            | AddPortError::PortInUse(ref description)
+            | AddPortError::SamePortValuesRequired(ref description)
+            | AddPortError::OnlyPermanentLeasesSupported(ref description)
+            | AddPortError::RemoteHostOnlySupportsWildcard(ref description)
+            | AddPortError::ExternalPortOnlySupportsWildcard(ref description)
+            | AddPortError::ConflictWithOtherMechanisms(ref description) => description.as_deref(),
+            _ => None,
+        }
     }
 }
 
 impl fmt::Display for AddPortError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            AddPortError::ActionNotAuthorized => write!(f, "The client is not authorized to map this port."),
+            AddPortError::ActionNotAuthorized(_) => write!(f, "The client is not authorized to map this port."),
             AddPortError::InternalPortZeroInvalid => write!(f, "Can not add a mapping for local port 0"),
+            AddPortError::InternalClientUnspecified => write!(f, "Can not add a mapping for local address 0.0.0.0"),
+            AddPortError::InternalClientInvalid => {
+                write!(
+                    f,
+                    "Can not add a mapping for a loopback, multicast or broadcast local address"
+                )
+            }
             AddPortError::ExternalPortZeroInvalid => write!(
                 f,
                 "External port number 0 (any port) is considered invalid by the gateway."
             ),
-            AddPortError::PortInUse => write!(
+            AddPortError::PortInUse(_) => write!(
                 f,
                 "The requested mapping conflicts with a mapping assigned to another client."
             ),
-            AddPortError::SamePortValuesRequired => write!(
+            AddPortError::SamePortValuesRequired(_) => write!(
                 f,
                 "The gateway requires that the requested internal and external ports are the same."
             ),
-            AddPortError::OnlyPermanentLeasesSupported => write!(
+            AddPortError::OnlyPermanentLeasesSupported(_) => write!(
                 f,
                 "The gateway only supports permanent leases (ie. a `lease_duration` of 0),"
             ),
-            AddPortError::DescriptionTooLong => write!(f, "The description was too long for the gateway to handle."),
+            AddPortError::DescriptionTooLong(_) => write!(f, "The description was too long for the gateway to handle."),
+            AddPortError::WildcardNotPermittedInRemoteHost(_) => write!(
+                f,
+                "The gateway does not accept mappings for any remote host, a specific one is required."
+            ),
+            AddPortError::RemoteHostOnlySupportsWildcard(_) => write!(
+                f,
+                "The gateway only accepts mappings for any remote host, not a specific one."
+            ),
+            AddPortError::ExternalPortOnlySupportsWildcard(_) => write!(
+                f,
+                "The gateway only accepts mappings for any external port, not a specific one."
+            ),
+            AddPortError::WildcardNotPermittedInExternalPort(_) => write!(
+                f,
+                "The gateway does not accept mappings for any external port, a specific one is required."
+            ),
+            AddPortError::ConflictWithOtherMechanisms(_) => {
+                write!(f, "The mapping conflicts with one made by another mechanism than UPnP.")
+            }
+            AddPortError::LeaseDurationTooLong => {
+                write!(f, "The lease duration is longer than the maximum of one week.")
+            }
             AddPortError::RequestError(ref e) => write!(f, "Request error. {}", e),
+        }?;
+        match self.error_description() {
+            Some(description) => write!(f, " ({})", description),
+            None => Ok(()),
         }
     }
 }
 
 impl std::error::Error for AddPortError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        None
+        match *self {
+            AddPortError::RequestError(ref e) => Some(e),
+            _ => None,
+        }
     }
 }
 
@@ -299,20 +488,27 @@ impl std::error::Error for AddPortError {
 pub enum SearchError {
     /// Http/Hyper error
     HttpError(attohttpc::Error),
-    /// Unable to process the response
-    InvalidResponse,
+    /// Unable to process the response, which is kept as is
+    InvalidResponse(String),
     /// IO Error
     IoError(io::Error),
     /// UTF-8 decoding error
     Utf8Error(str::Utf8Error),
     /// XML processing error
     XmlError(xmltree::ParseError),
+    /// The gateway answered the description request with an HTTP status code other than success
+    HttpStatus(u16),
+    /// No gateway answered before the timeout of the search
+    Timeout,
     /// When using the aio feature.
     #[cfg(feature = "aio")]
     HyperError(hyper::Error),
     /// Error parsing URI
     #[cfg(feature = "aio")]
     InvalidUri(hyper::http::uri::InvalidUri),
+    /// Error building the HTTP request, such as an invalid header
+    #[cfg(feature = "aio")]
+    InvalidRequest(http::Error),
 }
 
 impl From<attohttpc::Error> for SearchError {
@@ -346,6 +542,13 @@ impl From<hyper::Error> for SearchError {
     }
 }
 
+#[cfg(feature = "aio")]
+impl From<http::Error> for SearchError {
+    fn from(err: http::Error) -> SearchError {
+        SearchError::InvalidRequest(err)
+    }
+}
+
 #[cfg(feature = "aio")]
 impl From<hyper::http::uri::InvalidUri> for SearchError {
     fn from(err: hyper::http::uri::InvalidUri) -> SearchError {
@@ -355,7 +558,7 @@ impl From<hyper::http::uri::InvalidUri> for SearchError {
 #[cfg(feature = "aio")]
 impl From<Elapsed> for SearchError {
     fn from(_err: Elapsed) -> SearchError {
-        SearchError::IoError(io::Error::new(io::ErrorKind::TimedOut, "search timed out"))
+        SearchError::Timeout
     }
 }
 
@@ -363,14 +566,18 @@ impl fmt::Display for SearchError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             SearchError::HttpError(ref e) => write!(f, "HTTP error {}", e),
-            SearchError::InvalidResponse => write!(f, "Invalid response"),
+            SearchError::InvalidResponse(ref body) => write!(f, "Invalid response: {}", body),
             SearchError::IoError(ref e) => write!(f, "IO error: {}", e),
             SearchError::Utf8Error(ref e) => write!(f, "UTF-8 error: {}", e),
             SearchError::XmlError(ref e) => write!(f, "XML error: {}", e),
+            SearchError::HttpStatus(status) => write!(f, "Gateway answered with HTTP status {}", status),
+            SearchError::Timeout => write!(f, "Search timed out"),
             #[cfg(feature = "aio")]
             SearchError::HyperError(ref e) => write!(f, "Hyper Error: {}", e),
             #[cfg(feature = "aio")]
             SearchError::InvalidUri(ref e) => write!(f, "InvalidUri Error: {}", e),
+            #[cfg(feature = "aio")]
+            SearchError::InvalidRequest(ref e) => write!(f, "Invalid request: {}", e),
         }
     }
 }
@@ -379,14 +586,18 @@ impl error::Error for SearchError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match *self {
             SearchError::HttpError(ref e) => Some(e),
-            SearchError::InvalidResponse => None,
+            SearchError::InvalidResponse(..) => None,
             SearchError::IoError(ref e) => Some(e),
             SearchError::Utf8Error(ref e) => Some(e),
             SearchError::XmlError(ref e) => Some(e),
+            SearchError::HttpStatus(..) => None,
+            SearchError::Timeout => None,
             #[cfg(feature = "aio")]
             SearchError::HyperError(ref e) => Some(e),
             #[cfg(feature = "aio")]
             SearchError::InvalidUri(ref e) => Some(e),
+            #[cfg(feature = "aio")]
+            SearchError::InvalidRequest(ref e) => Some(e),
         }
     }
 }
@@ -405,10 +616,8 @@ pub enum GetGenericPortMappingEntryError {
 impl From<RequestError> for GetGenericPortMappingEntryError {
     fn from(err: RequestError) -> GetGenericPortMappingEntryError {
         match err {
-            RequestError::ErrorCode(code, _) if code == 606 => GetGenericPortMappingEntryError::ActionNotAuthorized,
-            RequestError::ErrorCode(code, _) if code == 713 => {
-                GetGenericPortMappingEntryError::SpecifiedArrayIndexInvalid
-            }
+            RequestError::ErrorCode(606, _) => GetGenericPortMappingEntryError::ActionNotAuthorized,
+            RequestError::ErrorCode(713, _) => GetGenericPortMappingEntryError::SpecifiedArrayIndexInvalid,
             other => GetGenericPortMappingEntryError::RequestError(other),
         }
     }
@@ -428,7 +637,151 @@ impl fmt::Display for GetGenericPortMappingEntryError {
     }
 }
 
-impl std::error::Error for GetGenericPortMappingEntryError {}
+impl std::error::Error for GetGenericPortMappingEntryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            GetGenericPortMappingEntryError::ActionNotAuthorized => None,
+            GetGenericPortMappingEntryError::SpecifiedArrayIndexInvalid => None,
+            GetGenericPortMappingEntryError::RequestError(ref e) => Some(e),
+        }
+    }
+}
+
+impl From<GetGenericPortMappingEntryError> for RemovePortError {
+    fn from(err: GetGenericPortMappingEntryError) -> RemovePortError {
+        match err {
+            GetGenericPortMappingEntryError::ActionNotAuthorized => RemovePortError::ActionNotAuthorized,
+            GetGenericPortMappingEntryError::SpecifiedArrayIndexInvalid => {
+                RemovePortError::RequestError(RequestError::ErrorCode(713, "SpecifiedArrayIndexInvalid".to_string()))
+            }
+            GetGenericPortMappingEntryError::RequestError(e) => RemovePortError::RequestError(e),
+        }
+    }
+}
+
+/// Errors returned by `Gateway::get_list_of_port_mappings`
+#[derive(Debug)]
+pub enum GetListOfPortMappingsError {
+    /// The client is not authorized to perform the operation.
+    ActionNotAuthorized,
+    /// The port range or number of entries is invalid.
+    InconsistentParameters,
+    /// Some other error occured performing the request.
+    RequestError(RequestError),
+}
+
+impl From<RequestError> for GetListOfPortMappingsError {
+    fn from(err: RequestError) -> GetListOfPortMappingsError {
+        match err {
+            RequestError::ErrorCode(606, _) => GetListOfPortMappingsError::ActionNotAuthorized,
+            RequestError::ErrorCode(733, _) => GetListOfPortMappingsError::InconsistentParameters,
+            other => GetListOfPortMappingsError::RequestError(other),
+        }
+    }
+}
+
+impl fmt::Display for GetListOfPortMappingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GetListOfPortMappingsError::ActionNotAuthorized => {
+                write!(f, "The client is not authorized to look up port mappings.")
+            }
+            GetListOfPortMappingsError::InconsistentParameters => {
+                write!(f, "The port range or number of entries is invalid.")
+            }
+            GetListOfPortMappingsError::RequestError(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for GetListOfPortMappingsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            GetListOfPortMappingsError::ActionNotAuthorized => None,
+            GetListOfPortMappingsError::InconsistentParameters => None,
+            GetListOfPortMappingsError::RequestError(ref e) => Some(e),
+        }
+    }
+}
+
+/// Errors returned by `Gateway::add_pinhole`
+#[derive(Debug)]
+pub enum AddPinholeError {
+    /// The client is not authorized to perform the operation.
+    ActionNotAuthorized,
+    /// The lease duration is 0 or longer than the one day UPnP allows for pinholes.
+    LeaseDurationInvalid,
+    /// The gateway has no room left for another pinhole.
+    NoPinholesAvailable,
+    /// The IPv6 firewall of the gateway is disabled, so every inbound flow is already allowed.
+    FirewallDisabled,
+    /// The gateway does not let clients open pinholes, or not this one.
+    InboundPinholeNotAllowed,
+    /// The gateway does not support pinholes for the protocol.
+    ProtocolNotSupported,
+    /// The gateway requires a specific internal port instead of any port.
+    InternalPortWildcardingNotAllowed,
+    /// Some other error occured performing the request.
+    RequestError(RequestError),
+}
+
+impl From<RequestError> for AddPinholeError {
+    fn from(err: RequestError) -> AddPinholeError {
+        match err {
+            RequestError::ErrorCode(606, _) => AddPinholeError::ActionNotAuthorized,
+            RequestError::ErrorCode(701, _) => AddPinholeError::NoPinholesAvailable,
+            RequestError::ErrorCode(702, _) => AddPinholeError::FirewallDisabled,
+            RequestError::ErrorCode(703, _) => AddPinholeError::InboundPinholeNotAllowed,
+            RequestError::ErrorCode(705, _) => AddPinholeError::ProtocolNotSupported,
+            RequestError::ErrorCode(706, _) => AddPinholeError::InternalPortWildcardingNotAllowed,
+            other => AddPinholeError::RequestError(other),
+        }
+    }
+}
+
+impl fmt::Display for AddPinholeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AddPinholeError::ActionNotAuthorized => write!(f, "The client is not authorized to open a pinhole."),
+            AddPinholeError::LeaseDurationInvalid => {
+                write!(f, "The lease duration of a pinhole must be from 1 second to one day.")
+            }
+            AddPinholeError::NoPinholesAvailable => write!(f, "The gateway has no room left for another pinhole."),
+            AddPinholeError::FirewallDisabled => write!(f, "The IPv6 firewall of the gateway is disabled."),
+            AddPinholeError::InboundPinholeNotAllowed => {
+                write!(f, "The gateway does not allow opening this pinhole.")
+            }
+            AddPinholeError::ProtocolNotSupported => {
+                write!(f, "The gateway does not support pinholes for this protocol.")
+            }
+            AddPinholeError::InternalPortWildcardingNotAllowed => {
+                write!(f, "The gateway requires a specific internal port for pinholes.")
+            }
+            AddPinholeError::RequestError(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for AddPinholeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            AddPinholeError::RequestError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Error returned when parsing a `PortMappingProtocol` from a string that is neither TCP nor UDP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseProtocolError(pub String);
+
+impl fmt::Display for ParseProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid port mapping protocol {:?}, expected TCP or UDP", self.0)
+    }
+}
+
+impl std::error::Error for ParseProtocolError {}
 
 /// An error type that emcompasses all possible errors.
 #[derive(Debug)]
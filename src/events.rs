@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::common::{self, parsing};
+use crate::errors::RequestError;
+use crate::gateway::Gateway;
+
+// How long the gateway gets to send a whole NOTIFY once it is connected.
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(5);
+// Largest NOTIFY accepted, the events of the connection service are a few hundred bytes.
+const MAX_NOTIFY_LEN: usize = 64 * 1024;
+
+/// New values of state variables of the connection service, sent by the gateway to an
+/// `EventSubscription`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    /// Sequence number of the event, 0 for the first event, which holds the current values
+    pub seq: u32,
+    /// The state variables and their new values by name, such as `ExternalIPAddress` or
+    /// `ConnectionStatus`
+    pub variables: HashMap<String, String>,
+}
+
+impl Event {
+    /// The new external IP address, if it is part of the event.
+    pub fn external_ip(&self) -> Option<Ipv4Addr> {
+        self.variables.get("ExternalIPAddress")?.parse().ok()
+    }
+}
+
+/// A GENA subscription to the events of the connection service, which is cancelled when this
+/// value is dropped.
+///
+/// Created by `Gateway::subscribe`. The events are received on `events()`, such as a change of
+/// the external IP address. The gateway ends the subscription after `timeout()` unless it is
+/// renewed, call `renew` before that. Errors while unsubscribing on drop are logged; call
+/// `unsubscribe` to handle them instead.
+#[derive(Debug)]
+pub struct EventSubscription {
+    gateway: Gateway,
+    url: String,
+    sid: String,
+    timeout: Duration,
+    callback_addr: SocketAddr,
+    events: Receiver<Event>,
+    stopped: Arc<AtomicBool>,
+    unsubscribed: bool,
+}
+
+impl EventSubscription {
+    pub(crate) fn new(
+        gateway: Gateway,
+        url: String,
+        local_addr: SocketAddr,
+        timeout: Duration,
+    ) -> Result<EventSubscription, RequestError> {
+        let listener = TcpListener::bind(local_addr)?;
+        let callback_addr = listener.local_addr()?;
+        let (sender, events) = mpsc::channel();
+        let stopped = Arc::new(AtomicBool::new(false));
+        {
+            let stopped = stopped.clone();
+            thread::Builder::new()
+                .name("igd-events".to_string())
+                .spawn(move || listen(listener, sender, stopped))?;
+        }
+
+        // The listener is stopped by dropping the subscription if subscribing fails.
+        let mut subscription = EventSubscription {
+            gateway,
+            url,
+            sid: String::new(),
+            timeout,
+            callback_addr,
+            events,
+            stopped,
+            unsubscribed: true,
+        };
+        let response = subscription.send(
+            b"SUBSCRIBE",
+            &[
+                ("CALLBACK", format!("<http://{}/>", callback_addr)),
+                ("NT", "upnp:event".to_string()),
+                ("TIMEOUT", format!("Second-{}", timeout.as_secs())),
+            ],
+        )?;
+        let (sid, timeout) = parse_subscribe_response(&response, timeout)?;
+        subscription.sid = sid;
+        subscription.timeout = timeout;
+        subscription.unsubscribed = false;
+        Ok(subscription)
+    }
+
+    /// The subscription identifier the gateway assigned.
+    pub fn sid(&self) -> &str {
+        &self.sid
+    }
+
+    /// How long the gateway keeps the subscription, as granted by the last `renew`.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// The address the events are received on.
+    pub fn callback_addr(&self) -> SocketAddr {
+        self.callback_addr
+    }
+
+    /// The events sent by the gateway, in the order they arrive.
+    pub fn events(&self) -> &Receiver<Event> {
+        &self.events
+    }
+
+    /// The gateway the subscription was sent to.
+    pub fn gateway(&self) -> &Gateway {
+        &self.gateway
+    }
+
+    /// Extend the subscription by `timeout` and return the timeout the gateway granted.
+    pub fn renew(&mut self, timeout: Duration) -> Result<Duration, RequestError> {
+        let response = self.send(
+            b"SUBSCRIBE",
+            &[
+                ("SID", self.sid.clone()),
+                ("TIMEOUT", format!("Second-{}", timeout.as_secs())),
+            ],
+        )?;
+        let (_, timeout) = parse_subscribe_response(&response, timeout)?;
+        self.timeout = timeout;
+        Ok(timeout)
+    }
+
+    /// Cancel the subscription now and return the result.
+    pub fn unsubscribe(mut self) -> Result<(), RequestError> {
+        self.unsubscribed = true;
+        self.send(b"UNSUBSCRIBE", &[("SID", self.sid.clone())]).map(|_| ())
+    }
+
+    fn send(&self, method: &[u8], headers: &[(&'static str, String)]) -> Result<attohttpc::Response, RequestError> {
+        let method = attohttpc::Method::from_bytes(method).expect("valid method");
+        let mut request = attohttpc::RequestBuilder::try_new(method, &self.url)?;
+        for (name, value) in headers {
+            request = request.header(*name, value.as_str());
+        }
+        let mut request = common::add_headers(request, &self.gateway.request_headers())?;
+        if let Some(timeout) = self.gateway.http_timeout {
+            request = request.timeout(timeout);
+        }
+        let response = request.send()?;
+        if !response.is_success() {
+            return Err(RequestError::HttpStatus(response.status().as_u16()));
+        }
+        Ok(response)
+    }
+}
+
+impl Drop for EventSubscription {
+    fn drop(&mut self) {
+        if !self.unsubscribed {
+            if let Err(e) = self.send(b"UNSUBSCRIBE", &[("SID", self.sid.clone())]) {
+                warn!(
+                    "Failed to unsubscribe on drop. error: {}, url: {}, sid: {}",
+                    e, self.url, self.sid
+                );
+            }
+        }
+        // Wake the listener up so it sees that it is stopped.
+        self.stopped.store(true, Ordering::SeqCst);
+        let _ = TcpStream::connect_timeout(&wake_addr(self.callback_addr), NOTIFY_TIMEOUT);
+    }
+}
+
+fn parse_subscribe_response(
+    response: &attohttpc::Response,
+    requested: Duration,
+) -> Result<(String, Duration), RequestError> {
+    let header = |name: &str| response.headers().get(name).and_then(|value| value.to_str().ok());
+    parsing::parse_subscribe_response(header("SID"), header("TIMEOUT"), requested)
+}
+
+// A listener bound to the unspecified address is reached over loopback.
+fn wake_addr(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V4(addr) if addr.ip().is_unspecified() => (Ipv4Addr::LOCALHOST, addr.port()).into(),
+        SocketAddr::V6(addr) if addr.ip().is_unspecified() => (std::net::Ipv6Addr::LOCALHOST, addr.port()).into(),
+        addr => addr,
+    }
+}
+
+fn listen(listener: TcpListener, events: Sender<Event>, stopped: Arc<AtomicBool>) {
+    for stream in listener.incoming() {
+        if stopped.load(Ordering::SeqCst) {
+            return;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                debug!("Error has occurred while accepting an event connection. error: {}", e);
+                continue;
+            }
+        };
+        match receive_notify(stream) {
+            Ok(Some(event)) => {
+                if events.send(event).is_err() {
+                    return;
+                }
+            }
+            Ok(None) => {}
+            Err(e) => debug!("Error has occurred while receiving an event. error: {}", e),
+        }
+    }
+}
+
+// Read one NOTIFY from the gateway and answer it. Requests that are not events are refused.
+fn receive_notify(mut stream: TcpStream) -> io::Result<Option<Event>> {
+    stream.set_read_timeout(Some(NOTIFY_TIMEOUT))?;
+    stream.set_write_timeout(Some(NOTIFY_TIMEOUT))?;
+
+    let mut request = Vec::new();
+    let mut buf = [0u8; 4096];
+    let (head, body) = loop {
+        if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+            let head = String::from_utf8_lossy(&request[..end]).into_owned();
+            break (head, request[end + 4..].to_vec());
+        }
+        if request.len() > MAX_NOTIFY_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "event headers too long"));
+        }
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            return Ok(None);
+        }
+        request.extend_from_slice(&buf[..n]);
+    };
+
+    let event = parse_notify(&head, body, &mut stream);
+    let status = match event {
+        Ok(_) => "200 OK",
+        Err(_) => "400 Bad Request",
+    };
+    // Written at once, a gateway may hang up as soon as the first bytes of the answer arrive.
+    let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+    stream.write_all(response.as_bytes())?;
+    let _ = stream.shutdown(Shutdown::Both);
+    event.map(Some)
+}
+
+fn parse_notify(head: &str, mut body: Vec<u8>, stream: &mut TcpStream) -> io::Result<Event> {
+    let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason.to_string());
+    if !head.starts_with("NOTIFY ") {
+        return Err(invalid("not a NOTIFY request"));
+    }
+    let header = |name: &str| {
+        head.lines().skip(1).find_map(|line| {
+            let (header, value) = line.split_once(':')?;
+            if header.trim().eq_ignore_ascii_case(name) {
+                Some(value.trim())
+            } else {
+                None
+            }
+        })
+    };
+    let content_length: usize = match header("Content-Length") {
+        Some(len) => len.parse().map_err(|_| invalid("invalid Content-Length"))?,
+        None => return Err(invalid("missing Content-Length")),
+    };
+    if content_length > MAX_NOTIFY_LEN {
+        return Err(invalid("event body too long"));
+    }
+    if body.len() < content_length {
+        let read = body.len();
+        body.resize(content_length, 0);
+        stream.read_exact(&mut body[read..])?;
+    }
+    body.truncate(content_length);
+
+    let seq = header("SEQ").and_then(|seq| seq.parse().ok()).unwrap_or(0);
+    let variables = parsing::parse_property_set(&String::from_utf8_lossy(&body))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    Ok(Event { seq, variables })
+}
+
+#[test]
+fn test_event_external_ip() {
+    let mut variables = HashMap::new();
+    variables.insert("ExternalIPAddress".to_string(), "203.0.113.1".to_string());
+    let event = Event { seq: 0, variables };
+    assert_eq!(event.external_ip(), Some(Ipv4Addr::new(203, 0, 113, 1)));
+
+    let event = Event {
+        seq: 1,
+        variables: HashMap::new(),
+    };
+    assert_eq!(event.external_ip(), None);
+}
+
+#[test]
+fn test_wake_addr() {
+    let addr: SocketAddr = "0.0.0.0:1234".parse().unwrap();
+    assert_eq!(wake_addr(addr), "127.0.0.1:1234".parse().unwrap());
+    let addr: SocketAddr = "192.168.1.2:1234".parse().unwrap();
+    assert_eq!(wake_addr(addr), addr);
+}
@@ -1,16 +1,33 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4};
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::common::{self, messages, parsing, parsing::RequestResult};
-use crate::errors::{self, AddAnyPortError, AddPortError, GetExternalIpError, RemovePortError, RequestError};
+use xmltree::Element;
+
+use crate::common::{
+    self, mapping_set::Mapping, mapping_set::MappingSet, messages, parsing, parsing::GatewayInfo,
+    parsing::MappedAddress, parsing::MappedPort, parsing::RequestResult, parsing::ServiceUrls, parsing::Services,
+    PortPicker, SearchOptions, SoapHook, Timing, TimingHook,
+};
+use crate::errors::{
+    self, AddAnyPortError, AddPinholeError, AddPortError, GetExternalIpError, RemovePortError, RequestError,
+};
+use crate::events::EventSubscription;
+use crate::lease::PortMappingLease;
 use crate::PortMappingProtocol;
 
 /// This structure represents a gateway found by the search functions.
+///
+/// With the `serde` feature, it can be persisted and restored later without searching again.
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Gateway {
-    /// Socket address of the gateway
-    pub addr: SocketAddrV4,
+    /// Socket address of the gateway, IPv6 for gateways found by a search over IPv6
+    pub addr: SocketAddr,
     /// Root url of the device
     pub root_url: String,
     /// Control url of the device
@@ -19,35 +36,481 @@ pub struct Gateway {
     pub control_schema_url: String,
     /// Control schema for all actions
     pub control_schema: HashMap<String, Vec<String>>,
+    /// Type of the connection service the control url belongs to,
+    /// such as `urn:schemas-upnp-org:service:WANIPConnection:1`
+    pub service_type: String,
+    /// Number of times a request is resent when the gateway resets the connection (defaults to 1)
+    pub connection_retries: u32,
+    /// External ports `add_any_port` picks from at random when the gateway does not choose one
+    /// itself (defaults to `32768..=65535`)
+    pub port_range: RangeInclusive<u16>,
+    /// Picks the external ports of `port_range` to try (defaults to `RandomPortPicker`)
+    #[cfg_attr(feature = "serde", serde(skip, default = "common::default_port_picker"))]
+    pub port_picker: Arc<dyn PortPicker>,
+    /// Extra headers sent with every HTTP request to the gateway,
+    /// such as the `User-Agent` configured in `SearchOptions`
+    pub headers: Vec<(String, String)>,
+    /// Timeout of each HTTP request to the gateway, `None` waits for the gateway indefinitely
+    /// (defaults to the `http_timeout` of the `SearchOptions`)
+    pub http_timeout: Option<Duration>,
+    /// Control url of the `WANCommonInterfaceConfig` service, which provides the traffic counters,
+    /// or `None` if the device does not advertise it
+    pub common_interface_url: Option<String>,
+    /// Event subscription url of the connection service, which `subscribe` sends to,
+    /// or `None` if the device does not advertise it
+    pub event_url: Option<String>,
+    /// Urls of every service of the device description by service type, including the ones the
+    /// crate does not use (empty for gateways created with `from_url`)
+    pub services: Services,
+    /// Information from the device description, such as the friendly name
+    /// (empty for gateways created with `from_url`)
+    pub info: GatewayInfo,
+    /// `SERVER` header of the search reply, which names the operating system and product of the
+    /// gateway (`None` for gateways created with `from_url`)
+    pub server: Option<String>,
+    /// `USN` header of the search reply, the unique service name of the device, which stays the
+    /// same when it is reached over another interface (`None` for gateways created with `from_url`)
+    pub usn: Option<String>,
+    /// How long the search reply is valid, from its `CACHE-CONTROL: max-age`. The gateway announces
+    /// itself again before then, search again once it has passed to notice changes
+    /// (`None` for gateways created with `from_url` or whose reply had no valid max-age)
+    pub max_age: Option<Duration>,
+    /// Username and password sent with HTTP basic authentication, from the `SearchOptions`.
+    /// They are not serialized, set them again on a restored gateway
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub credentials: Option<(String, String)>,
+    /// Told how long each action sent to the gateway took, from the `SearchOptions`.
+    /// It is not serialized, set it again on a restored gateway
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub timing_hook: Option<Arc<dyn TimingHook>>,
+    /// Shown each SOAP request sent to the gateway and its response, from the `SearchOptions`.
+    /// It is not serialized, set it again on a restored gateway
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub soap_hook: Option<Arc<dyn SoapHook>>,
 }
 
 impl Gateway {
-    fn perform_request(&self, header: &str, body: &str, ok: &str) -> RequestResult {
-        let url = format!("http://{}{}", self.addr, self.control_url);
+    fn perform_request(&self, action: &str, body: &str, ok: &str) -> RequestResult {
+        self.perform_request_until(action, body, ok, None)
+    }
+
+    fn perform_request_until(&self, action: &str, body: &str, ok: &str, deadline: Option<Instant>) -> RequestResult {
+        let url = self.to_string();
+        self.perform_service_request(&url, &self.service_type, action, body, ok, deadline)
+    }
 
-        let response = attohttpc::post(&url)
-            .header("SOAPAction", header)
-            .header("Content-Type", "text/xml")
-            .text(body)
-            .send()?;
+    // Send an action of the WANCommonInterfaceConfig service, which has a control url of its own.
+    fn perform_common_interface_request(&self, action: &str) -> RequestResult {
+        let url = match self.common_interface_url {
+            Some(ref common_interface_url) => parsing::resolve_url(self.addr, common_interface_url),
+            None => return Err(RequestError::UnsupportedAction(action.to_string())),
+        };
+        self.perform_service_request(
+            &url,
+            messages::COMMON_INTERFACE_SERVICE_TYPE,
+            action,
+            &messages::format_common_interface_message(action),
+            &format!("{}Response", action),
+            None,
+        )
+    }
 
-        parsing::parse_response(response.text()?, ok)
+    // Send an action of the WANIPv6FirewallControl service, which has a control url of its own.
+    fn perform_firewall_request(&self, action: &str, body: &str) -> RequestResult {
+        let url = match self.services.get(messages::WAN_IPV6_FIREWALL_CONTROL_SERVICE_TYPE) {
+            Some(service) => parsing::resolve_url(self.addr, &service.control_url),
+            None => return Err(RequestError::UnsupportedAction(action.to_string())),
+        };
+        self.perform_service_request(
+            &url,
+            messages::WAN_IPV6_FIREWALL_CONTROL_SERVICE_TYPE,
+            action,
+            body,
+            &format!("{}Response", action),
+            None,
+        )
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "igd.soap_action", level = "debug", skip_all, fields(gateway = %self.addr, action = %action))
+    )]
+    fn perform_service_request(
+        &self,
+        url: &str,
+        service_type: &str,
+        action: &str,
+        body: &str,
+        ok: &str,
+        deadline: Option<Instant>,
+    ) -> RequestResult {
+        let _timing = Timing::start(&self.timing_hook, action);
+        let mut retries = self.connection_retries;
+        loop {
+            let result = self.send_request(url, service_type, action, body, deadline);
+            if let Some(ref hook) = self.soap_hook {
+                hook.on_exchange(action, body, result.as_deref());
+            }
+            match result {
+                Err(ref e) if retries > 0 && common::is_connection_reset(e) => {
+                    debug!("Gateway reset the connection, retrying request. error: {}", e);
+                    retries -= 1;
+                }
+                result => return parsing::parse_response(result?, ok),
+            }
+        }
+    }
+
+    // Each request opens a connection of its own. attohttpc sends every request, including those
+    // of an `attohttpc::Session`, with `Connection: close`, so there is no connection to keep
+    // between the requests of a gateway; the aio gateway shares one hyper client instead.
+    fn send_request(
+        &self,
+        url: &str,
+        service_type: &str,
+        action: &str,
+        body: &str,
+        deadline: Option<Instant>,
+    ) -> Result<String, RequestError> {
+        let request = attohttpc::post(url)
+            .header("SOAPAction", messages::format_soap_action(service_type, action))
+            .header("Content-Type", "text/xml");
+        let mut request = common::add_headers(request, &self.request_headers())?;
+        let remaining = match deadline {
+            Some(deadline) => Some(common::remaining(deadline)?),
+            None => None,
+        };
+        if let Some(timeout) = common::earliest_timeout(remaining, self.http_timeout) {
+            request = request.timeout(timeout);
+        }
+        let response = request.text(body).send()?;
+        common::check_soap_status(response.status().as_u16())?;
+
+        Ok(response.text()?)
+    }
+
+    /// Create a gateway from an already known control url, without searching for it.
+    ///
+    /// This is meant for control urls that were cached from an earlier search or configured by
+    /// hand. The service type defaults to `urn:schemas-upnp-org:service:WANIPConnection:1` and the
+    /// control schema to the arguments of that service; set the public fields to change them.
+    /// The other settings are the same as for a gateway found with the default `SearchOptions`.
+    pub fn from_url<A: Into<SocketAddr>>(addr: A, control_url: String) -> Gateway {
+        let options = SearchOptions::default();
+        Gateway {
+            addr: addr.into(),
+            root_url: "/".to_string(),
+            control_url,
+            control_schema_url: String::new(),
+            control_schema: messages::default_control_schema(),
+            service_type: messages::DEFAULT_SERVICE_TYPE.to_string(),
+            connection_retries: common::DEFAULT_CONNECTION_RETRIES,
+            port_range: common::DEFAULT_PORT_RANGE,
+            port_picker: common::default_port_picker(),
+            headers: common::request_headers(&options),
+            http_timeout: options.http_timeout,
+            common_interface_url: None,
+            event_url: None,
+            services: Services::new(),
+            info: GatewayInfo::default(),
+            server: None,
+            usn: None,
+            max_age: None,
+            credentials: None,
+            timing_hook: None,
+            soap_hook: None,
+        }
+    }
+
+    // Control the active one of several connection services, such as one per WAN port. The
+    // Layer3Forwarding service knows which one it is. Without it, the first service that reports
+    // itself `Connected` is picked. The first service is kept when nothing tells them apart.
+    pub(crate) fn select_connection_service(
+        &mut self,
+        services: &[parsing::ConnectionService],
+        layer3_forwarding_url: Option<&str>,
+    ) {
+        if services.len() < 2 {
+            return;
+        }
+        let service = layer3_forwarding_url
+            .and_then(|url| self.find_default_connection_service(services, url))
+            .or_else(|| self.find_connected_service(services));
+        if let Some(service) = service {
+            let (control_schema_url, control_url, service_type) = service.urls.clone();
+            self.control_schema_url = control_schema_url;
+            self.control_url = control_url;
+            self.service_type = service_type;
+            self.event_url = service.event_url.clone();
+            self.services.insert(
+                self.service_type.clone(),
+                ServiceUrls {
+                    control_url: self.control_url.clone(),
+                    scpd_url: self.control_schema_url.clone(),
+                    event_url: self.event_url.clone(),
+                },
+            );
+        }
+    }
+
+    fn find_default_connection_service<'a>(
+        &self,
+        services: &'a [parsing::ConnectionService],
+        url: &str,
+    ) -> Option<&'a parsing::ConnectionService> {
+        let action = messages::GET_DEFAULT_CONNECTION_SERVICE_ACTION;
+        let result = parsing::parse_default_connection_service_response(self.perform_service_request(
+            &parsing::resolve_url(self.addr, url),
+            messages::LAYER3_FORWARDING_SERVICE_TYPE,
+            action,
+            &messages::format_action_message(messages::LAYER3_FORWARDING_SERVICE_TYPE, action, &[]),
+            &format!("{}Response", action),
+            None,
+        ));
+        match result {
+            Ok(default) => {
+                let service = parsing::find_default_connection_service(services, &default);
+                if service.is_none() {
+                    debug!("Default connection service {} is not in the description", default);
+                }
+                service
+            }
+            Err(e) => {
+                debug!("Could not get the default connection service. error: {}", e);
+                None
+            }
+        }
+    }
+
+    fn find_connected_service<'a>(
+        &self,
+        services: &'a [parsing::ConnectionService],
+    ) -> Option<&'a parsing::ConnectionService> {
+        for service in services {
+            let (_, control_url, service_type) = &service.urls;
+            let result = parsing::parse_get_status_info_response(self.perform_service_request(
+                &parsing::resolve_url(self.addr, control_url),
+                service_type,
+                messages::GET_STATUS_INFO_ACTION,
+                &messages::format_get_status_info_message(service_type),
+                "GetStatusInfoResponse",
+                None,
+            ));
+            match result {
+                Ok(status) if status.connection_status == "Connected" => return Some(service),
+                Ok(status) => debug!("Connection service {} is {}", control_url, status.connection_status),
+                Err(e) => debug!("Could not get the status of {}. error: {}", control_url, e),
+            }
+        }
+        None
+    }
+
+    // Headers of every request, with the credentials.
+    pub(crate) fn request_headers(&self) -> Vec<(String, String)> {
+        common::with_credentials(&self.headers, &self.credentials)
+    }
+
+    /// Control url of the service of the given type, such as
+    /// `urn:schemas-upnp-org:service:Layer3Forwarding:1`, or `None` if the device does not
+    /// advertise it.
+    pub fn control_url_for(&self, service_type: &str) -> Option<&str> {
+        self.services
+            .get(service_type)
+            .map(|service| service.control_url.as_str())
+    }
+
+    /// Whether the gateway advertises the action in the control schema of its service, such as
+    /// `AddAnyPortMapping`, which only IGDv2 devices have.
+    ///
+    /// The schema is fetched once during the search. Gateways created with `from_url` assume the
+    /// actions of `WANIPConnection:1`.
+    pub fn supports_action(&self, name: &str) -> bool {
+        self.control_schema.contains_key(name)
+    }
+
+    /// Names of the actions the gateway advertises in the control schema of its service, sorted.
+    ///
+    /// Gateways also answer actions they do not implement with `RequestError::UnsupportedAction`,
+    /// but checking first avoids the request.
+    pub fn supported_actions(&self) -> Vec<&str> {
+        let mut actions: Vec<&str> = self.control_schema.keys().map(String::as_str).collect();
+        actions.sort_unstable();
+        actions
+    }
+
+    /// The IPv4 address of this host on the route to the gateway, which is the `local_addr` to
+    /// give to `add_port` and `add_any_port` to receive the traffic on this host.
+    ///
+    /// This connects a UDP socket to the gateway and reads back the address the OS chose for it;
+    /// nothing is sent. Gateways reached over IPv6 return an error.
+    pub fn local_ip(&self) -> io::Result<Ipv4Addr> {
+        common::local_ip(self.addr)
+    }
+
+    /// Short name of the device, meant to be shown to users, or an empty string if the device
+    /// does not give one.
+    pub fn friendly_name(&self) -> &str {
+        &self.info.friendly_name
+    }
+
+    /// Manufacturer of the device, or an empty string if the device does not give one.
+    pub fn manufacturer(&self) -> &str {
+        &self.info.manufacturer
+    }
+
+    /// Model name of the device, or an empty string if the device does not give one.
+    pub fn model_name(&self) -> &str {
+        &self.info.model_name
+    }
+
+    /// Model number of the device, or an empty string if the device does not give one.
+    pub fn model_number(&self) -> &str {
+        &self.info.model_number
+    }
+
+    /// The type of the connection service used for all actions,
+    /// such as `urn:schemas-upnp-org:service:WANIPConnection:1`.
+    pub fn service_type(&self) -> &str {
+        &self.service_type
     }
 
     /// Get the external IP address of the gateway.
     pub fn get_external_ip(&self) -> Result<Ipv4Addr, GetExternalIpError> {
         parsing::parse_get_external_ip_response(self.perform_request(
-            messages::GET_EXTERNAL_IP_HEADER,
-            &messages::format_get_external_ip_message(),
+            messages::GET_EXTERNAL_IP_ACTION,
+            &messages::format_get_external_ip_message(&self.service_type),
+            "GetExternalIPAddressResponse",
+        ))
+    }
+
+    /// Get the external IP address of the gateway, which may be an IPv6 address on dual-stack IGDv2 devices.
+    ///
+    /// `get_external_ip` returns an error for IPv6 addresses.
+    pub fn get_external_ip_addr(&self) -> Result<IpAddr, GetExternalIpError> {
+        parsing::parse_get_external_ip_addr_response(self.perform_request(
+            messages::GET_EXTERNAL_IP_ACTION,
+            &messages::format_get_external_ip_message(&self.service_type),
             "GetExternalIPAddressResponse",
         ))
     }
 
+    /// Get the status of the gateway's WAN connection and how long it has been up.
+    pub fn get_status_info(&self) -> Result<parsing::StatusInfo, RequestError> {
+        parsing::parse_get_status_info_response(self.perform_request(
+            messages::GET_STATUS_INFO_ACTION,
+            &messages::format_get_status_info_message(&self.service_type),
+            "GetStatusInfoResponse",
+        ))
+    }
+
+    /// Get the type of the gateway's WAN connection, such as `IP_Routed`, and the types it can be
+    /// set to.
+    pub fn get_connection_type_info(&self) -> Result<parsing::ConnectionTypeInfo, RequestError> {
+        parsing::parse_get_connection_type_info_response(self.perform_request(
+            messages::GET_CONNECTION_TYPE_INFO_ACTION,
+            &messages::format_get_connection_type_info_message(&self.service_type),
+            "GetConnectionTypeInfoResponse",
+        ))
+    }
+
+    /// Get whether the gateway performs NAT. When it does not, such as a router in bridge mode,
+    /// port mappings have no effect.
+    pub fn get_nat_rsip_status(&self) -> Result<parsing::NatRsipStatus, RequestError> {
+        parsing::parse_get_nat_rsip_status_response(self.perform_request(
+            messages::GET_NAT_RSIP_STATUS_ACTION,
+            &messages::format_get_nat_rsip_status_message(&self.service_type),
+            "GetNATRSIPStatusResponse",
+        ))
+    }
+
+    /// Get the total number of bytes sent on the WAN interface.
+    ///
+    /// This and the other traffic counters come from the `WANCommonInterfaceConfig` service.
+    /// Gateways that do not advertise it return `RequestError::UnsupportedAction`. The counters
+    /// are 32 bits on most gateways and wrap around.
+    pub fn get_total_bytes_sent(&self) -> Result<u64, RequestError> {
+        parsing::parse_total_response(
+            self.perform_common_interface_request(messages::GET_TOTAL_BYTES_SENT_ACTION),
+            "NewTotalBytesSent",
+        )
+    }
+
+    /// Get the total number of bytes received on the WAN interface.
+    pub fn get_total_bytes_received(&self) -> Result<u64, RequestError> {
+        parsing::parse_total_response(
+            self.perform_common_interface_request(messages::GET_TOTAL_BYTES_RECEIVED_ACTION),
+            "NewTotalBytesReceived",
+        )
+    }
+
+    /// Get the total number of packets sent on the WAN interface.
+    pub fn get_total_packets_sent(&self) -> Result<u64, RequestError> {
+        parsing::parse_total_response(
+            self.perform_common_interface_request(messages::GET_TOTAL_PACKETS_SENT_ACTION),
+            "NewTotalPacketsSent",
+        )
+    }
+
+    /// Get the total number of packets received on the WAN interface.
+    pub fn get_total_packets_received(&self) -> Result<u64, RequestError> {
+        parsing::parse_total_response(
+            self.perform_common_interface_request(messages::GET_TOTAL_PACKETS_RECEIVED_ACTION),
+            "NewTotalPacketsReceived",
+        )
+    }
+
+    /// Get the type, nominal speed and status of the WAN link.
+    ///
+    /// Like the traffic counters, this comes from the `WANCommonInterfaceConfig` service.
+    pub fn get_link_properties(&self) -> Result<parsing::LinkProperties, RequestError> {
+        parsing::parse_get_link_properties_response(
+            self.perform_common_interface_request(messages::GET_COMMON_LINK_PROPERTIES_ACTION),
+        )
+    }
+
+    /// Invoke any action of the connection service, with the given arguments in order.
+    ///
+    /// This is an escape hatch for the actions the crate does not wrap, such as `ForceTermination`,
+    /// `RequestConnection` or vendor extensions. The argument values are XML-escaped. On success,
+    /// the `<ActionResponse>` element is returned, with the output arguments as its children.
+    /// UPnP errors are returned as `RequestError::ErrorCode`.
+    pub fn action(&self, action_name: &str, arguments: &[(&str, &str)]) -> Result<Element, RequestError> {
+        parsing::parse_action_response(self.perform_request(
+            action_name,
+            &messages::format_action_message(&self.service_type, action_name, arguments),
+            &format!("{}Response", action_name),
+        ))
+    }
+
+    /// Get the current time according to the gateway.
+    ///
+    /// IGD does not define an action for this, so this is not an action: the time is taken from
+    /// the `Date` header of the HTTP response of the server that serves the device description.
+    /// Gateways whose HTTP server omits the header return `RequestError::InvalidResponse`.
+    pub fn get_router_time(&self) -> Result<SystemTime, RequestError> {
+        let _timing = Timing::start(&self.timing_hook, "get_router_time");
+        let url = parsing::resolve_url(self.addr, &self.root_url);
+        let mut retries = self.connection_retries;
+        let response = loop {
+            let mut request = common::add_headers(attohttpc::get(&url), &self.request_headers())?;
+            if let Some(timeout) = self.http_timeout {
+                request = request.timeout(timeout);
+            }
+            match request.send().map_err(RequestError::from) {
+                Err(ref e) if retries > 0 && common::is_connection_reset(e) => {
+                    debug!("Gateway reset the connection, retrying request. error: {}", e);
+                    retries -= 1;
+                }
+                result => break result?,
+            }
+        };
+        parsing::parse_router_time(response.headers().get("Date").and_then(|date| date.to_str().ok()))
+    }
+
     /// Get an external socket address with our external ip and any port. This is a convenience
     /// function that calls `get_external_ip` followed by `add_any_port`
     ///
-    /// The local_addr is the address where the traffic is sent to.
-    /// The lease_duration parameter is in seconds. A value of 0 is infinite.
+    /// See `add_port` for `local_addr` and `lease_duration`.
     ///
     /// # Returns
     ///
@@ -64,10 +527,48 @@ impl Gateway {
         Ok(SocketAddrV4::new(ip, port))
     }
 
+    /// Get an external socket address with our external ip and any port, forwarded to
+    /// `local_port` on this host.
+    ///
+    /// This is `get_any_address` with the local address found by `local_ip`, for callers that
+    /// don't know the LAN address of this host. That address is the source address the OS picks
+    /// for the route to the gateway, found without enumerating the network interfaces; give the
+    /// address explicitly to `get_any_address` when another one should receive the traffic.
+    pub fn get_any_address_for_local_port(
+        &self,
+        protocol: PortMappingProtocol,
+        local_port: u16,
+        lease_duration: u32,
+        description: &str,
+    ) -> Result<SocketAddrV4, AddAnyPortError> {
+        let local_addr = SocketAddrV4::new(self.local_ip().map_err(RequestError::from)?, local_port);
+        self.get_any_address(protocol, local_addr, lease_duration, description)
+    }
+
+    /// Reserve an external socket address with our external ip and any port, along with the
+    /// protocol and the lease the gateway granted.
+    ///
+    /// This is like `get_any_address`, except that the mapping is added with `add_any_port_mapped`,
+    /// which asks the gateway for the granted lease with one more request.
+    pub fn reserve_any_address(
+        &self,
+        protocol: PortMappingProtocol,
+        local_addr: SocketAddrV4,
+        lease_duration: u32,
+        description: &str,
+    ) -> Result<MappedAddress, AddAnyPortError> {
+        let ip = self.get_external_ip()?;
+        let mapped = self.add_any_port_mapped(protocol, local_addr, lease_duration, description)?;
+        Ok(MappedAddress {
+            addr: SocketAddrV4::new(ip, mapped.external_port),
+            protocol,
+            lease_duration: mapped.lease_duration,
+        })
+    }
+
     /// Add a port mapping.with any external port.
     ///
-    /// The local_addr is the address where the traffic is sent to.
-    /// The lease_duration parameter is in seconds. A value of 0 is infinite.
+    /// See `add_port` for `local_addr` and `lease_duration`.
     ///
     /// # Returns
     ///
@@ -78,6 +579,87 @@ impl Gateway {
         local_addr: SocketAddrV4,
         lease_duration: u32,
         description: &str,
+    ) -> Result<u16, AddAnyPortError> {
+        self.add_any_port_until(protocol, local_addr, lease_duration, description, None)
+    }
+
+    /// Add a port mapping with any external port, forwarded to `local_port` on this host.
+    ///
+    /// This is `add_any_port` with the local address found by `local_ip`, like
+    /// `get_any_address_for_local_port`.
+    pub fn add_any_port_for_local_port(
+        &self,
+        protocol: PortMappingProtocol,
+        local_port: u16,
+        lease_duration: u32,
+        description: &str,
+    ) -> Result<u16, AddAnyPortError> {
+        let local_addr = SocketAddrV4::new(self.local_ip().map_err(RequestError::from)?, local_port);
+        self.add_any_port(protocol, local_addr, lease_duration, description)
+    }
+
+    /// Add a port mapping with any external port, for a lease given as a `Duration`.
+    ///
+    /// This behaves like `add_any_port`. Partial seconds are rounded up, `Duration::from_secs(0)`
+    /// is an infinite lease, and leases longer than one week are rejected with
+    /// `AddAnyPortError::LeaseDurationTooLong`.
+    pub fn add_any_port_with_lease_duration(
+        &self,
+        protocol: PortMappingProtocol,
+        local_addr: SocketAddrV4,
+        lease_duration: Duration,
+        description: &str,
+    ) -> Result<u16, AddAnyPortError> {
+        let lease_duration =
+            common::lease_duration_secs(lease_duration).ok_or(AddAnyPortError::LeaseDurationTooLong)?;
+        self.add_any_port(protocol, local_addr, lease_duration, description)
+    }
+
+    /// Add a port mapping with any external port, and return the lease the gateway granted.
+    ///
+    /// This behaves like `add_any_port`. See `add_port_mapped` for how the lease is found out.
+    pub fn add_any_port_mapped(
+        &self,
+        protocol: PortMappingProtocol,
+        local_addr: SocketAddrV4,
+        lease_duration: u32,
+        description: &str,
+    ) -> Result<MappedPort, AddAnyPortError> {
+        let external_port = self.add_any_port(protocol, local_addr, lease_duration, description)?;
+        Ok(MappedPort {
+            external_port,
+            lease_duration: self.granted_lease_duration(protocol, external_port, lease_duration),
+        })
+    }
+
+    /// Add a port mapping with any external port, giving up once `timeout` has elapsed.
+    ///
+    /// This behaves like `add_any_port`, but the whole operation, including every fallback
+    /// attempt, must complete within `timeout`. Each request is bounded by the remaining time.
+    ///
+    /// # Returns
+    ///
+    /// The external port that was mapped on success. Otherwise an error, with
+    /// `RequestError::Timeout` if the timeout was exceeded.
+    pub fn add_any_port_with_timeout(
+        &self,
+        protocol: PortMappingProtocol,
+        local_addr: SocketAddrV4,
+        lease_duration: u32,
+        description: &str,
+        timeout: Duration,
+    ) -> Result<u16, AddAnyPortError> {
+        let deadline = Instant::now() + timeout;
+        self.add_any_port_until(protocol, local_addr, lease_duration, description, Some(deadline))
+    }
+
+    fn add_any_port_until(
+        &self,
+        protocol: PortMappingProtocol,
+        local_addr: SocketAddrV4,
+        lease_duration: u32,
+        description: &str,
+        deadline: Option<Instant>,
     ) -> Result<u16, AddAnyPortError> {
         // This function first attempts to call AddAnyPortMapping on the IGD with a random port
         // number. If that fails due to the method being unknown it attempts to call AddPortMapping
@@ -88,25 +670,37 @@ impl Gateway {
         if local_addr.port() == 0 {
             return Err(AddAnyPortError::InternalPortZeroInvalid);
         }
+        if local_addr.ip().is_unspecified() {
+            return Err(AddAnyPortError::InternalClientUnspecified);
+        }
+        if !common::is_valid_internal_client(*local_addr.ip()) {
+            return Err(AddAnyPortError::InternalClientInvalid);
+        }
+        if lease_duration > common::MAX_LEASE_DURATION {
+            return Err(AddAnyPortError::LeaseDurationTooLong);
+        }
 
         let schema = self.control_schema.get("AddAnyPortMapping");
         if let Some(schema) = schema {
-            let external_port = common::random_port();
+            let external_port = self.port_picker.next_port(&self.port_range);
 
-            parsing::parse_add_any_port_mapping_response(self.perform_request(
-                messages::ADD_ANY_PORT_MAPPING_HEADER,
+            parsing::parse_add_any_port_mapping_response(self.perform_request_until(
+                messages::ADD_ANY_PORT_MAPPING_ACTION,
                 &messages::format_add_any_port_mapping_message(
+                    &self.service_type,
                     schema,
                     protocol,
                     external_port,
                     local_addr,
                     lease_duration,
                     description,
+                    None,
                 ),
                 "AddAnyPortMappingResponse",
+                deadline,
             ))
         } else {
-            self.retry_add_random_port_mapping(protocol, local_addr, lease_duration, description)
+            self.retry_add_random_port_mapping(protocol, local_addr, lease_duration, description, deadline)
         }
     }
 
@@ -116,16 +710,21 @@ impl Gateway {
         local_addr: SocketAddrV4,
         lease_duration: u32,
         description: &str,
+        deadline: Option<Instant>,
     ) -> Result<u16, AddAnyPortError> {
         const ATTEMPTS: usize = 20;
 
         for _ in 0..ATTEMPTS {
-            if let Ok(port) = self.add_random_port_mapping(protocol, local_addr, lease_duration, &description) {
-                return Ok(port);
+            match self.add_random_port_mapping(protocol, local_addr, lease_duration, description, deadline) {
+                Ok(port) => return Ok(port),
+                Err(AddAnyPortError::RequestError(e)) if common::is_timeout(&e) => {
+                    return Err(AddAnyPortError::RequestError(e))
+                }
+                Err(_) => {}
             }
         }
 
-        Err(AddAnyPortError::NoPortsAvailable)
+        Err(AddAnyPortError::NoPortsAvailable(None))
     }
 
     fn add_random_port_mapping(
@@ -134,13 +733,22 @@ impl Gateway {
         local_addr: SocketAddrV4,
         lease_duration: u32,
         description: &str,
+        deadline: Option<Instant>,
     ) -> Result<u16, AddAnyPortError> {
-        let external_port = common::random_port();
+        let external_port = self.port_picker.next_port(&self.port_range);
 
-        if let Err(err) = self.add_port_mapping(protocol, external_port, local_addr, lease_duration, &description) {
+        if let Err(err) = self.add_port_mapping(
+            protocol,
+            external_port,
+            local_addr,
+            lease_duration,
+            description,
+            None,
+            deadline,
+        ) {
             match parsing::convert_add_random_port_mapping_error(err) {
                 Some(err) => return Err(err),
-                None => return self.add_same_port_mapping(protocol, local_addr, lease_duration, description),
+                None => return self.add_same_port_mapping(protocol, local_addr, lease_duration, description, deadline),
             }
         }
 
@@ -153,13 +761,23 @@ impl Gateway {
         local_addr: SocketAddrV4,
         lease_duration: u32,
         description: &str,
+        deadline: Option<Instant>,
     ) -> Result<u16, AddAnyPortError> {
-        match self.add_port_mapping(protocol, local_addr.port(), local_addr, lease_duration, description) {
+        match self.add_port_mapping(
+            protocol,
+            local_addr.port(),
+            local_addr,
+            lease_duration,
+            description,
+            None,
+            deadline,
+        ) {
             Ok(_) => Ok(local_addr.port()),
             Err(e) => Err(parsing::convert_add_same_port_mapping_error(e)),
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn add_port_mapping(
         &self,
         protocol: PortMappingProtocol,
@@ -167,10 +785,13 @@ impl Gateway {
         local_addr: SocketAddrV4,
         lease_duration: u32,
         description: &str,
+        remote_host: Option<Ipv4Addr>,
+        deadline: Option<Instant>,
     ) -> Result<(), RequestError> {
-        self.perform_request(
-            messages::ADD_PORT_MAPPING_HEADER,
+        self.perform_request_until(
+            messages::ADD_PORT_MAPPING_ACTION,
             &messages::format_add_port_mapping_message(
+                &self.service_type,
                 self.control_schema
                     .get("AddPortMapping")
                     .ok_or_else(|| RequestError::UnsupportedAction("AddPortMapping".to_string()))?,
@@ -179,8 +800,10 @@ impl Gateway {
                 local_addr,
                 lease_duration,
                 description,
+                remote_host,
             ),
             "AddPortMappingResponse",
+            deadline,
         )?;
 
         Ok(())
@@ -188,8 +811,10 @@ impl Gateway {
 
     /// Add a port mapping.
     ///
-    /// The local_addr is the address where the traffic is sent to.
-    /// The lease_duration parameter is in seconds. A value of 0 is infinite.
+    /// The local_addr is the address where the traffic is sent to. It does not have to be the
+    /// host sending the request, any host on the LAN can be given, but not 0.0.0.0 nor a
+    /// loopback, multicast or broadcast address, which are rejected before any request is sent.
+    /// The lease_duration parameter is in seconds. A value of 0 is infinite, the maximum is 604800 (one week).
     pub fn add_port(
         &self,
         protocol: PortMappingProtocol,
@@ -197,6 +822,149 @@ impl Gateway {
         local_addr: SocketAddrV4,
         lease_duration: u32,
         description: &str,
+    ) -> Result<(), AddPortError> {
+        self.add_port_with_remote_host(protocol, external_port, local_addr, lease_duration, description, None)
+    }
+
+    /// Add a port mapping, for a lease given as a `Duration`.
+    ///
+    /// This behaves like `add_port`. Partial seconds are rounded up, `Duration::from_secs(0)`
+    /// is an infinite lease, and leases longer than one week are rejected with
+    /// `AddPortError::LeaseDurationTooLong`.
+    pub fn add_port_with_lease_duration(
+        &self,
+        protocol: PortMappingProtocol,
+        external_port: u16,
+        local_addr: SocketAddrV4,
+        lease_duration: Duration,
+        description: &str,
+    ) -> Result<(), AddPortError> {
+        let lease_duration = common::lease_duration_secs(lease_duration).ok_or(AddPortError::LeaseDurationTooLong)?;
+        self.add_port(protocol, external_port, local_addr, lease_duration, description)
+    }
+
+    /// Add a port mapping, and return the lease the gateway granted.
+    ///
+    /// Some gateways shorten the requested lease to their own maximum and still succeed, so the
+    /// lease is read back with GetSpecificPortMappingEntry. If that fails, the requested lease is
+    /// returned.
+    pub fn add_port_mapped(
+        &self,
+        protocol: PortMappingProtocol,
+        external_port: u16,
+        local_addr: SocketAddrV4,
+        lease_duration: u32,
+        description: &str,
+    ) -> Result<MappedPort, AddPortError> {
+        self.add_port(protocol, external_port, local_addr, lease_duration, description)?;
+        Ok(MappedPort {
+            external_port,
+            lease_duration: self.granted_lease_duration(protocol, external_port, lease_duration),
+        })
+    }
+
+    fn granted_lease_duration(&self, protocol: PortMappingProtocol, external_port: u16, requested: u32) -> u32 {
+        let result = parsing::parse_specific_port_mapping_lease(self.perform_request(
+            messages::GET_SPECIFIC_PORT_MAPPING_ENTRY_ACTION,
+            &messages::format_get_specific_port_mapping_entry_message(&self.service_type, protocol, external_port),
+            "GetSpecificPortMappingEntryResponse",
+        ));
+        match result {
+            Ok(lease_duration) => lease_duration,
+            Err(e) => {
+                debug!(
+                    "Could not read back the granted lease, assuming the requested one. error: {}",
+                    e
+                );
+                requested
+            }
+        }
+    }
+
+    /// Add the same port mapping for both TCP and UDP.
+    ///
+    /// If the UDP mapping fails after the TCP one was added, the TCP mapping is removed again
+    /// before the error is returned, so that either both mappings exist or neither does.
+    pub fn add_port_both(
+        &self,
+        external_port: u16,
+        local_addr: SocketAddrV4,
+        lease_duration: u32,
+        description: &str,
+    ) -> Result<(), AddPortError> {
+        self.add_port(
+            PortMappingProtocol::TCP,
+            external_port,
+            local_addr,
+            lease_duration,
+            description,
+        )?;
+        if let Err(e) = self.add_port(
+            PortMappingProtocol::UDP,
+            external_port,
+            local_addr,
+            lease_duration,
+            description,
+        ) {
+            if let Err(remove_error) = self.remove_port(PortMappingProtocol::TCP, external_port) {
+                warn!(
+                    "Could not roll back the TCP mapping of port {}. error: {}",
+                    external_port, remove_error
+                );
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Open a pinhole in the IPv6 firewall of the gateway, letting `remote_host` reach the
+    /// `internal_port` of `internal_client`, and return the `UniqueID` the gateway gave to it.
+    ///
+    /// This is the IPv6 counterpart of `add_port`: there is no address translation, so the
+    /// internal port is the one reached from outside. A `remote_host` of `None` and a `remote_port`
+    /// of 0 accept traffic from any host and port. The lease_duration parameter is in seconds,
+    /// from 1 to 86400 (one day).
+    ///
+    /// Only IGDv2 gateways that advertise the `WANIPv6FirewallControl` service in their device
+    /// description support pinholes, the others return `RequestError::UnsupportedAction`.
+    pub fn add_pinhole(
+        &self,
+        protocol: PortMappingProtocol,
+        remote_host: Option<Ipv6Addr>,
+        remote_port: u16,
+        internal_client: Ipv6Addr,
+        internal_port: u16,
+        lease_duration: u32,
+    ) -> Result<u16, AddPinholeError> {
+        if lease_duration == 0 || lease_duration > messages::MAX_PINHOLE_LEASE_DURATION {
+            return Err(AddPinholeError::LeaseDurationInvalid);
+        }
+
+        parsing::parse_add_pinhole_response(self.perform_firewall_request(
+            messages::ADD_PINHOLE_ACTION,
+            &messages::format_add_pinhole_message(
+                protocol,
+                remote_host,
+                remote_port,
+                internal_client,
+                internal_port,
+                lease_duration,
+            ),
+        ))
+    }
+
+    /// Add a port mapping that only applies to traffic from `remote_host`.
+    ///
+    /// A `remote_host` of `None` accepts traffic from any host, like `add_port`.
+    /// See `add_port` for `local_addr` and `lease_duration`.
+    pub fn add_port_with_remote_host(
+        &self,
+        protocol: PortMappingProtocol,
+        external_port: u16,
+        local_addr: SocketAddrV4,
+        lease_duration: u32,
+        description: &str,
+        remote_host: Option<Ipv4Addr>,
     ) -> Result<(), AddPortError> {
         if external_port == 0 {
             return Err(AddPortError::ExternalPortZeroInvalid);
@@ -204,16 +972,193 @@ impl Gateway {
         if local_addr.port() == 0 {
             return Err(AddPortError::InternalPortZeroInvalid);
         }
+        if local_addr.ip().is_unspecified() {
+            return Err(AddPortError::InternalClientUnspecified);
+        }
+        if !common::is_valid_internal_client(*local_addr.ip()) {
+            return Err(AddPortError::InternalClientInvalid);
+        }
+        if lease_duration > common::MAX_LEASE_DURATION {
+            return Err(AddPortError::LeaseDurationTooLong);
+        }
+
+        self.add_port_mapping(
+            protocol,
+            external_port,
+            local_addr,
+            lease_duration,
+            description,
+            remote_host,
+            None,
+        )
+        .map_err(parsing::convert_add_port_error)
+    }
+
+    /// Add a port mapping that lasts as long as the returned `PortMappingLease`.
+    ///
+    /// The mapping is removed when the lease is dropped, including during a panic.
+    /// See `add_port` for `local_addr` and `lease_duration`.
+    pub fn add_port_lease(
+        &self,
+        protocol: PortMappingProtocol,
+        external_port: u16,
+        local_addr: SocketAddrV4,
+        lease_duration: u32,
+        description: &str,
+    ) -> Result<PortMappingLease, AddPortError> {
+        self.add_port(protocol, external_port, local_addr, lease_duration, description)?;
+        Ok(PortMappingLease::new(self.clone(), protocol, external_port))
+    }
+
+    /// Subscribe to the events of the connection service, such as changes of the external IP
+    /// address, instead of polling `get_external_ip`.
+    ///
+    /// The events are received by an HTTP listener bound to `local_addr`, which runs on a thread
+    /// of its own until the returned `EventSubscription` is dropped. The gateway sends the events
+    /// to that address, so it has to be the address of this host on the LAN, not 0.0.0.0; port 0
+    /// picks any free port. The timeout is how long the subscription should last before it has to
+    /// be renewed, the gateway may grant another one.
+    ///
+    /// Returns `RequestError::UnsupportedAction` if the device does not advertise an event
+    /// subscription url, as for gateways created with `from_url`.
+    pub fn subscribe(&self, local_addr: SocketAddr, timeout: Duration) -> Result<EventSubscription, RequestError> {
+        let url = match self.event_url {
+            Some(ref event_url) => parsing::resolve_url(self.addr, event_url),
+            None => return Err(RequestError::UnsupportedAction("SUBSCRIBE".to_string())),
+        };
+        EventSubscription::new(self.clone(), url, local_addr, timeout)
+    }
+
+    /// Add a port mapping, choosing what happens when a mapping for the same external port and
+    /// protocol already exists.
+    ///
+    /// When `overwrite` is true this is the same as `add_port` and the existing mapping is replaced.
+    /// Otherwise the gateway is first asked for the existing mapping with GetSpecificPortMappingEntry
+    /// and `AddPortError::PortInUse` is returned if there is one, whoever it belongs to.
+    pub fn add_port_with_overwrite(
+        &self,
+        protocol: PortMappingProtocol,
+        external_port: u16,
+        local_addr: SocketAddrV4,
+        lease_duration: u32,
+        description: &str,
+        overwrite: bool,
+    ) -> Result<(), AddPortError> {
+        if !overwrite
+            && external_port != 0
+            && self
+                .mapping_exists(protocol, external_port)
+                .map_err(parsing::convert_add_port_error)?
+        {
+            return Err(AddPortError::PortInUse(None));
+        }
+
+        self.add_port(protocol, external_port, local_addr, lease_duration, description)
+    }
+
+    /// Whether the gateway still has a mapping for the external port and protocol, whoever it
+    /// belongs to.
+    ///
+    /// Mappings can disappear without notice, such as when the gateway reboots or their lease
+    /// expires. This asks the gateway with GetSpecificPortMappingEntry, which is cheap enough for
+    /// health checks and renewal loops.
+    pub fn mapping_exists(&self, protocol: PortMappingProtocol, external_port: u16) -> Result<bool, RequestError> {
+        parsing::parse_port_mapping_exists_response(self.perform_request(
+            messages::GET_SPECIFIC_PORT_MAPPING_ENTRY_ACTION,
+            &messages::format_get_specific_port_mapping_entry_message(&self.service_type, protocol, external_port),
+            "GetSpecificPortMappingEntryResponse",
+        ))
+    }
+
+    /// Add a port mapping, succeeding when the gateway already has the same one.
+    ///
+    /// When the gateway answers that the external port is in use, the existing mapping is looked
+    /// up with GetSpecificPortMappingEntry. If it forwards to the same `local_addr`, it is most
+    /// likely one added earlier, such as before a restart, and this succeeds. With
+    /// `refresh_lease` that mapping is removed and added again, so that its lease starts over
+    /// with the given duration and description. A mapping to another address is left alone and
+    /// `AddPortError::PortInUse` is returned.
+    pub fn ensure_port(
+        &self,
+        protocol: PortMappingProtocol,
+        external_port: u16,
+        local_addr: SocketAddrV4,
+        lease_duration: u32,
+        description: &str,
+        refresh_lease: bool,
+    ) -> Result<(), AddPortError> {
+        match self.add_port(protocol, external_port, local_addr, lease_duration, description) {
+            Err(AddPortError::PortInUse(_)) => {}
+            result => return result,
+        }
+        match self.port_mapping_target(protocol, external_port)? {
+            Some(target) if target == local_addr => {}
+            // The mapping went away in the meantime.
+            None => return self.add_port(protocol, external_port, local_addr, lease_duration, description),
+            Some(_) => return Err(AddPortError::PortInUse(None)),
+        }
+        if !refresh_lease {
+            return Ok(());
+        }
+        match self.remove_port(protocol, external_port) {
+            Err(RemovePortError::ActionNotAuthorized) => return Err(AddPortError::ActionNotAuthorized(None)),
+            Err(RemovePortError::RequestError(e)) => return Err(AddPortError::RequestError(e)),
+            // Removed, or gone already.
+            _ => {}
+        }
+        self.add_port(protocol, external_port, local_addr, lease_duration, description)
+    }
+
+    // Where the mapping for the external port and protocol forwards to, if there is one.
+    fn port_mapping_target(
+        &self,
+        protocol: PortMappingProtocol,
+        external_port: u16,
+    ) -> Result<Option<SocketAddrV4>, AddPortError> {
+        parsing::parse_specific_port_mapping_target(self.perform_request(
+            messages::GET_SPECIFIC_PORT_MAPPING_ENTRY_ACTION,
+            &messages::format_get_specific_port_mapping_entry_message(&self.service_type, protocol, external_port),
+            "GetSpecificPortMappingEntryResponse",
+        ))
+        .map_err(parsing::convert_add_port_error)
+    }
+
+    /// Add back the mappings of `set` that the gateway no longer has.
+    ///
+    /// Each mapping is looked up with GetSpecificPortMappingEntry and added again unless the gateway
+    /// already forwards its external port to the same local address. This is meant to be called
+    /// after a restart with a `MappingSet` that was persisted earlier.
+    ///
+    /// # Returns
+    ///
+    /// The mappings that could not be restored, along with the reason.
+    pub fn restore(&self, set: &MappingSet) -> Vec<(Mapping, AddPortError)> {
+        set.iter()
+            .filter_map(|mapping| self.restore_mapping(mapping).err().map(|e| (mapping.clone(), e)))
+            .collect()
+    }
+
+    fn restore_mapping(&self, mapping: &Mapping) -> Result<(), AddPortError> {
+        let target = self.port_mapping_target(mapping.protocol, mapping.external_port)?;
+        if target == Some(mapping.local_addr) {
+            return Ok(());
+        }
 
-        self.add_port_mapping(protocol, external_port, local_addr, lease_duration, description)
-            .map_err(parsing::convert_add_port_error)
+        self.add_port(
+            mapping.protocol,
+            mapping.external_port,
+            mapping.local_addr,
+            mapping.lease_duration,
+            &mapping.description,
+        )
     }
 
     /// Remove a port mapping.
     pub fn remove_port(&self, protocol: PortMappingProtocol, external_port: u16) -> Result<(), RemovePortError> {
         parsing::parse_delete_port_mapping_response(self.perform_request(
-            messages::DELETE_PORT_MAPPING_HEADER,
+            messages::DELETE_PORT_MAPPING_ACTION,
             &messages::format_delete_port_message(
+                &self.service_type,
                 self.control_schema.get("DeletePortMapping").ok_or_else(|| {
                     RemovePortError::RequestError(RequestError::UnsupportedAction("DeletePortMapping".to_string()))
                 })?,
@@ -224,6 +1169,116 @@ impl Gateway {
         ))
     }
 
+    /// Remove the port mappings of both TCP and UDP for an external port.
+    ///
+    /// Both mappings are removed even if removing the first one fails; the first error is returned.
+    pub fn remove_port_both(&self, external_port: u16) -> Result<(), RemovePortError> {
+        let tcp = self.remove_port(PortMappingProtocol::TCP, external_port);
+        let udp = self.remove_port(PortMappingProtocol::UDP, external_port);
+        tcp.and(udp)
+    }
+
+    /// Remove the port mappings with an external port between `start_port` and `end_port`, inclusive.
+    ///
+    /// This uses the DeletePortMappingRange action of IGDv2. When `manage` is true, mappings of
+    /// other clients are removed as well, if the gateway allows it. Gateways that only support IGDv1
+    /// return `RequestError::UnsupportedAction`.
+    pub fn delete_port_mapping_range(
+        &self,
+        start_port: u16,
+        end_port: u16,
+        protocol: PortMappingProtocol,
+        manage: bool,
+    ) -> Result<(), RemovePortError> {
+        let schema = self.control_schema.get("DeletePortMappingRange").ok_or_else(|| {
+            RemovePortError::RequestError(RequestError::UnsupportedAction("DeletePortMappingRange".to_string()))
+        })?;
+        parsing::parse_delete_port_mapping_range_response(self.perform_request(
+            messages::DELETE_PORT_MAPPING_RANGE_ACTION,
+            &messages::format_delete_port_range_message(
+                &self.service_type,
+                schema,
+                protocol,
+                start_port,
+                end_port,
+                manage,
+            ),
+            "DeletePortMappingRangeResponse",
+        ))
+    }
+
+    /// Remove all port mappings with an external port between `start_port` and `end_port`, inclusive.
+    ///
+    /// Gateways that support DeletePortMappingRange (IGDv2) remove the whole range in one request.
+    /// Other gateways get one DeletePortMapping request per port, and every port that could not be
+    /// removed is returned along with its error; ports that were never mapped show up as
+    /// `RemovePortError::NoSuchPortMapping`. The range request never reports individual ports.
+    pub fn remove_port_range(
+        &self,
+        protocol: PortMappingProtocol,
+        start_port: u16,
+        end_port: u16,
+    ) -> Result<Vec<(u16, RemovePortError)>, RemovePortError> {
+        if self.supports_action("DeletePortMappingRange") {
+            self.delete_port_mapping_range(start_port, end_port, protocol, false)?;
+            return Ok(Vec::new());
+        }
+
+        let mut failures = Vec::new();
+        for port in start_port..=end_port {
+            match self.remove_port(protocol, port) {
+                Ok(()) => {}
+                Err(RemovePortError::RequestError(RequestError::UnsupportedAction(action))) => {
+                    return Err(RemovePortError::RequestError(RequestError::UnsupportedAction(action)))
+                }
+                Err(e) => failures.push((port, e)),
+            }
+        }
+        Ok(failures)
+    }
+
+    /// Remove every port mapping for which `pred` returns true, such as the ones with a
+    /// description starting with the name of the application, and return how many were removed.
+    ///
+    /// The mappings are listed with `get_port_mappings` first, so only the ones visible to this
+    /// client are considered. Mappings that are already gone when they are removed are not
+    /// counted. The first other error stops the removal and is returned.
+    pub fn remove_mappings_where<F>(&self, pred: F) -> Result<usize, RemovePortError>
+    where
+        F: Fn(&parsing::PortMappingEntry) -> bool,
+    {
+        let mut removed = 0;
+        for entry in self.get_port_mappings()?.iter().filter(|entry| pred(entry)) {
+            match self.remove_port(entry.protocol, entry.external_port) {
+                Ok(()) => removed += 1,
+                Err(RemovePortError::NoSuchPortMapping) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(removed)
+    }
+
+    /// The lowest external port of `range` that has no mapping for the protocol, or `None` if
+    /// they are all taken.
+    ///
+    /// This lists the mappings with `get_port_mappings`, so the result is predictable, unlike the
+    /// ports `add_any_port` tries. Mappings that are not visible to this client are not known, and
+    /// another client may take the port before it is mapped; `add_port` still reports
+    /// `AddPortError::PortInUse` then.
+    pub fn find_free_external_port(
+        &self,
+        protocol: PortMappingProtocol,
+        range: RangeInclusive<u16>,
+    ) -> Result<Option<u16>, errors::GetGenericPortMappingEntryError> {
+        let used: HashSet<u16> = self
+            .get_port_mappings()?
+            .iter()
+            .filter(|entry| entry.protocol == protocol)
+            .map(|entry| entry.external_port)
+            .collect();
+        Ok(range.into_iter().find(|port| !used.contains(port)))
+    }
+
     /// Get one port mapping entry
     ///
     /// Gets one port mapping entry by its index.
@@ -234,15 +1289,76 @@ impl Gateway {
         index: u32,
     ) -> Result<parsing::PortMappingEntry, errors::GetGenericPortMappingEntryError> {
         parsing::parse_get_generic_port_mapping_entry(self.perform_request(
-            messages::GET_GENERIC_PORT_MAPPING_ENTRY,
-            &messages::formate_get_generic_port_mapping_entry_message(index),
+            messages::GET_GENERIC_PORT_MAPPING_ENTRY_ACTION,
+            &messages::formate_get_generic_port_mapping_entry_message(&self.service_type, index),
             "GetGenericPortMappingEntryResponse",
         ))
     }
+
+    /// Get the port mappings with an external port between `start_port` and `end_port`, inclusive,
+    /// in a single request.
+    ///
+    /// This uses the GetListOfPortMappings action of IGDv2 and returns at most `num_entries`
+    /// mappings. Only the mappings of this client are asked for, gateways with access control
+    /// refuse to list the others to ordinary clients. Gateways that only support IGDv1 return
+    /// `RequestError::UnsupportedAction`; use `get_port_mappings` with those.
+    pub fn get_list_of_port_mappings(
+        &self,
+        start_port: u16,
+        end_port: u16,
+        protocol: PortMappingProtocol,
+        num_entries: u32,
+    ) -> Result<Vec<parsing::PortMappingEntry>, errors::GetListOfPortMappingsError> {
+        let schema = self
+            .control_schema
+            .get("GetListOfPortMappings")
+            .ok_or_else(|| RequestError::UnsupportedAction("GetListOfPortMappings".to_string()))?;
+        parsing::parse_get_list_of_port_mappings_response(self.perform_request(
+            messages::GET_LIST_OF_PORT_MAPPINGS_ACTION,
+            &messages::format_get_list_of_port_mappings_message(
+                &self.service_type,
+                schema,
+                protocol,
+                start_port,
+                end_port,
+                false,
+                num_entries,
+            ),
+            "GetListOfPortMappingsResponse",
+        ))
+    }
+
+    /// Get all port mapping entries
+    ///
+    /// Calls `get_generic_port_mapping_entry` with increasing indices, starting at 0, until the
+    /// gateway reports that the index is out of bounds.
+    /// Not all existing port mappings might be visible to this client.
+    pub fn get_port_mappings(&self) -> Result<Vec<parsing::PortMappingEntry>, errors::GetGenericPortMappingEntryError> {
+        common::collect_port_mappings(|index| self.get_generic_port_mapping_entry(index))
+    }
 }
 
 impl fmt::Display for Gateway {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "http://{}{}", self.addr, self.control_url)
+        write!(f, "{}", parsing::resolve_url(self.addr, &self.control_url))
     }
 }
+
+#[test]
+fn test_from_url() {
+    let gateway = Gateway::from_url(
+        "192.168.1.1:5000".parse::<SocketAddrV4>().unwrap(),
+        "/ctl/IPConn".to_string(),
+    );
+    assert_eq!(gateway.to_string(), "http://192.168.1.1:5000/ctl/IPConn");
+    assert_eq!(gateway.service_type(), "urn:schemas-upnp-org:service:WANIPConnection:1");
+    assert!(gateway.supports_action(messages::ADD_PORT_MAPPING_ACTION));
+    assert!(gateway.supports_action(messages::DELETE_PORT_MAPPING_ACTION));
+    assert!(!gateway.supports_action(messages::ADD_ANY_PORT_MAPPING_ACTION));
+    assert_eq!(
+        gateway.supported_actions(),
+        vec![messages::ADD_PORT_MAPPING_ACTION, messages::DELETE_PORT_MAPPING_ACTION]
+    );
+    assert_eq!(gateway.friendly_name(), "");
+    assert_eq!(gateway.common_interface_url, None);
+}
@@ -0,0 +1,168 @@
+use std::net::SocketAddrV4;
+use std::time::Duration;
+
+use crate::errors::{AddPortError, RemovePortError};
+use crate::gateway::Gateway;
+use crate::PortMappingProtocol;
+
+/// A port mapping that is removed from the gateway when this value is dropped.
+///
+/// Created by `Gateway::add_port_lease`. Errors while removing the mapping on drop are logged;
+/// call `release` to handle them instead.
+#[derive(Debug)]
+pub struct PortMappingLease {
+    gateway: Gateway,
+    protocol: PortMappingProtocol,
+    external_port: u16,
+    released: bool,
+}
+
+impl PortMappingLease {
+    pub(crate) fn new(gateway: Gateway, protocol: PortMappingProtocol, external_port: u16) -> PortMappingLease {
+        PortMappingLease {
+            gateway,
+            protocol,
+            external_port,
+            released: false,
+        }
+    }
+
+    /// The protocol of the mapping.
+    pub fn protocol(&self) -> PortMappingProtocol {
+        self.protocol
+    }
+
+    /// The external port of the mapping.
+    pub fn external_port(&self) -> u16 {
+        self.external_port
+    }
+
+    /// The gateway holding the mapping.
+    pub fn gateway(&self) -> &Gateway {
+        &self.gateway
+    }
+
+    /// Remove the mapping now and return the result.
+    pub fn release(mut self) -> Result<(), RemovePortError> {
+        self.released = true;
+        self.gateway.remove_port(self.protocol, self.external_port)
+    }
+}
+
+impl Drop for PortMappingLease {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+        if let Err(e) = self.gateway.remove_port(self.protocol, self.external_port) {
+            warn!(
+                "Failed to remove port mapping on drop. error: {}, protocol: {}, external_port: {}",
+                e, self.protocol, self.external_port
+            );
+        }
+    }
+}
+
+/// Keeps a port mapping alive by adding it again before its lease expires.
+///
+/// The renewal interval defaults to half of the lease duration and can be changed with
+/// `with_interval`. Call `renew_now` every `interval()`, typically from a dedicated thread.
+///
+/// A `lease_duration` of 0 is infinite and needs no renewal, so `interval()` is `None` for such
+/// mappings unless an interval was set explicitly.
+#[derive(Clone, Debug)]
+pub struct PortMappingRenewer {
+    gateway: Gateway,
+    protocol: PortMappingProtocol,
+    external_port: u16,
+    local_addr: SocketAddrV4,
+    lease_duration: u32,
+    description: String,
+    interval: Option<Duration>,
+}
+
+impl PortMappingRenewer {
+    /// Create a renewer for the mapping with these parameters, as passed to `Gateway::add_port`.
+    ///
+    /// This does not add the mapping; call `renew_now` to add it the first time.
+    pub fn new(
+        gateway: Gateway,
+        protocol: PortMappingProtocol,
+        external_port: u16,
+        local_addr: SocketAddrV4,
+        lease_duration: u32,
+        description: &str,
+    ) -> PortMappingRenewer {
+        let interval = match lease_duration {
+            0 => None,
+            secs => Some(Duration::from_secs(u64::from(secs)) / 2),
+        };
+        PortMappingRenewer {
+            gateway,
+            protocol,
+            external_port,
+            local_addr,
+            lease_duration,
+            description: description.to_string(),
+            interval,
+        }
+    }
+
+    /// Renew the mapping every `interval` instead of every half lease.
+    pub fn with_interval(mut self, interval: Duration) -> PortMappingRenewer {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// How long to wait between renewals, `None` if the mapping never expires.
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval
+    }
+
+    /// Add the mapping again, which restarts its lease on the gateway.
+    pub fn renew_now(&self) -> Result<(), AddPortError> {
+        self.gateway.add_port(
+            self.protocol,
+            self.external_port,
+            self.local_addr,
+            self.lease_duration,
+            &self.description,
+        )
+    }
+}
+
+#[test]
+fn test_renewer_interval() {
+    let gateway = Gateway {
+        addr: "192.168.0.1:1900".parse().unwrap(),
+        root_url: "/rootDesc.xml".into(),
+        control_url: "/ctl/IPConn".into(),
+        control_schema_url: "/WANIPCn.xml".into(),
+        control_schema: Default::default(),
+        service_type: "urn:schemas-upnp-org:service:WANIPConnection:1".into(),
+        connection_retries: 1,
+        port_range: 32_768..=65_535,
+        port_picker: std::sync::Arc::new(crate::RandomPortPicker),
+        headers: Vec::new(),
+        http_timeout: None,
+        common_interface_url: None,
+        event_url: None,
+        services: Default::default(),
+        info: Default::default(),
+        server: None,
+        usn: None,
+        max_age: None,
+        credentials: None,
+        timing_hook: None,
+        soap_hook: None,
+    };
+    let local_addr = "192.168.0.2:4321".parse().unwrap();
+
+    let renewer = PortMappingRenewer::new(gateway.clone(), PortMappingProtocol::TCP, 1234, local_addr, 120, "");
+    assert_eq!(renewer.interval(), Some(Duration::from_secs(60)));
+    let renewer = renewer.with_interval(Duration::from_secs(10));
+    assert_eq!(renewer.interval(), Some(Duration::from_secs(10)));
+
+    let renewer = PortMappingRenewer::new(gateway, PortMappingProtocol::TCP, 1234, local_addr, 0, "");
+    assert_eq!(renewer.interval(), None);
+}
@@ -3,14 +3,31 @@
 //! This library allows you to communicate with an IGD enabled device.
 //! Use one of the `search_gateway` functions to obtain a `Gateway` object.
 //! You can then communicate with the device via this object.
+//!
+//! With the `tracing` feature, the searches, the fetches of the device description and the SOAP
+//! actions are also wrapped in `tracing` spans at the debug level, named `igd.search`,
+//! `igd.fetch_description` and `igd.soap_action`. The `log` records are emitted either way.
+//!
+//! With the `compress` feature, device descriptions and SOAP responses that a gateway sends
+//! with `Content-Encoding: gzip` or `deflate` are decoded. Without it, they are used as they are.
 
 extern crate attohttpc;
+extern crate base64;
 #[macro_use]
 extern crate log;
 #[cfg(feature = "aio")]
 extern crate bytes;
+#[cfg(feature = "compress")]
+extern crate flate2;
 
+extern crate httpdate;
+extern crate if_addrs;
 extern crate rand;
+#[cfg(feature = "serde")]
+extern crate serde;
+extern crate socket2;
+#[cfg(feature = "tracing")]
+extern crate tracing;
 extern crate url;
 extern crate xmltree;
 
@@ -24,29 +41,43 @@ extern crate hyper;
 extern crate tokio;
 
 // data structures
-pub use self::common::parsing::PortMappingEntry;
-pub use self::common::SearchOptions;
+pub use self::common::mapping_set::{Mapping, MappingSet};
+pub use self::common::parsing::{
+    ConnectionTypeInfo, GatewayInfo, LinkProperties, MappedAddress, MappedPort, NatRsipStatus, PortMappingEntry,
+    ServiceUrls, Services, StatusInfo,
+};
+pub use self::common::{PortPicker, RandomPortPicker, SearchOptions, SearchOptionsBuilder, SoapHook, TimingHook};
 pub use self::errors::{
-    AddAnyPortError, AddPortError, GetExternalIpError, GetGenericPortMappingEntryError, RemovePortError, RequestError,
-    SearchError,
+    AddAnyPortError, AddPinholeError, AddPortError, GetExternalIpError, GetGenericPortMappingEntryError,
+    GetListOfPortMappingsError, ParseProtocolError, RemovePortError, RequestError, SearchError,
 };
 pub use self::errors::{Error, Result};
+pub use self::events::{Event, EventSubscription};
 pub use self::gateway::Gateway;
+pub use self::lease::{PortMappingLease, PortMappingRenewer};
 
 // search of gateway
-pub use self::search::search_gateway;
+#[allow(deprecated)]
+pub use self::search::search_gateway_with_info;
+pub use self::search::{search_gateway, search_gateways};
 
 #[cfg(feature = "aio")]
 pub mod aio;
 mod common;
 mod errors;
+mod events;
 mod gateway;
+mod lease;
 mod search;
+pub mod soap;
 
+use std::convert::TryFrom;
 use std::fmt;
+use std::str::FromStr;
 
 /// Represents the protocols available for port mapping.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PortMappingProtocol {
     /// TCP protocol
     TCP,
@@ -66,3 +97,38 @@ impl fmt::Display for PortMappingProtocol {
         )
     }
 }
+
+impl FromStr for PortMappingProtocol {
+    type Err = ParseProtocolError;
+
+    /// Parse `TCP` or `UDP`, in any case.
+    fn from_str(s: &str) -> std::result::Result<PortMappingProtocol, ParseProtocolError> {
+        if s.eq_ignore_ascii_case("TCP") {
+            Ok(PortMappingProtocol::TCP)
+        } else if s.eq_ignore_ascii_case("UDP") {
+            Ok(PortMappingProtocol::UDP)
+        } else {
+            Err(ParseProtocolError(s.to_string()))
+        }
+    }
+}
+
+impl TryFrom<&str> for PortMappingProtocol {
+    type Error = ParseProtocolError;
+
+    fn try_from(s: &str) -> std::result::Result<PortMappingProtocol, ParseProtocolError> {
+        s.parse()
+    }
+}
+
+#[test]
+fn test_parse_port_mapping_protocol() {
+    assert_eq!("tcp".parse(), Ok(PortMappingProtocol::TCP));
+    assert_eq!("TCP".parse(), Ok(PortMappingProtocol::TCP));
+    assert_eq!("Udp".parse(), Ok(PortMappingProtocol::UDP));
+    assert_eq!(PortMappingProtocol::try_from("UDP"), Ok(PortMappingProtocol::UDP));
+    assert_eq!(
+        "sctp".parse::<PortMappingProtocol>(),
+        Err(ParseProtocolError("sctp".to_string()))
+    );
+}
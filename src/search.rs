@@ -1,10 +1,16 @@
 use attohttpc::Method;
 use attohttpc::RequestBuilder;
 use std::collections::HashMap;
-use std::net::{SocketAddrV4, UdpSocket};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
 use std::str;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::common::{messages, parsing, SearchOptions};
+use crate::common::{
+    self, messages, parsing, parsing::DeviceDescription, parsing::GatewayInfo, parsing::SearchResponse, SearchOptions,
+    Timing,
+};
 use crate::errors::SearchError;
 use crate::gateway::Gateway;
 
@@ -13,6 +19,8 @@ use crate::gateway::Gateway;
 /// The default `SearchOptions` should suffice in most cases.
 /// It can be created with `Default::default()` or `SearchOptions::default()`.
 ///
+/// Returns `SearchError::Timeout` if no gateway answered before `options.timeout`.
+///
 /// # Example
 /// ```no_run
 /// use igd::{search_gateway, SearchOptions, Result};
@@ -24,71 +32,404 @@ use crate::gateway::Gateway;
 ///     Ok(())
 /// }
 /// ```
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "igd.search", level = "debug", skip_all, fields(broadcast_address = %options.broadcast_address))
+)]
 pub fn search_gateway(options: SearchOptions) -> Result<Gateway, SearchError> {
-    let socket = UdpSocket::bind(options.bind_addr)?;
-    socket.set_read_timeout(options.timeout)?;
+    let _timing = Timing::start(&options.timing_hook, "search");
+    let socket = common::bind_search_socket(&options)?;
+    let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+    let mut retransmissions = common::Retransmissions::new(options.retransmissions);
 
-    socket.send_to(messages::SEARCH_REQUEST.as_bytes(), options.broadcast_address)?;
+    let request = messages::format_search_request(&options.search_target, options.broadcast_address);
+    socket.send_to(request.as_bytes(), options.broadcast_address)?;
 
     loop {
-        let mut buf = [0u8; 1500];
-        let (read, _) = socket.recv_from(&mut buf)?;
-        let text = str::from_utf8(&buf[..read])?;
+        let response = match receive_search_response_until(&socket, &request, &options, &mut retransmissions, deadline)?
+        {
+            Some(response) => response,
+            None => return Err(SearchError::Timeout),
+        };
+        let text = str::from_utf8(&response)?;
+
+        let response = parsing::parse_search_result(text)?;
+
+        if let Some((gateway, _)) = get_gateway(response, &options) {
+            return Ok(gateway);
+        }
+    }
+}
+
+/// Search gateway, using the given `SearchOptions`, and also return its `GatewayInfo`.
+///
+/// The gateway keeps the same information in its `info` field.
+#[deprecated(since = "0.13.0", note = "use `search_gateway` and the `info` field of the gateway")]
+pub fn search_gateway_with_info(options: SearchOptions) -> Result<(Gateway, GatewayInfo), SearchError> {
+    let gateway = search_gateway(options)?;
+    let info = gateway.info.clone();
+    Ok((gateway, info))
+}
 
-        let (addr, root_url) = parsing::parse_search_result(text)?;
+/// Search all gateways, using the given `SearchOptions`.
+///
+/// Unlike `search_gateway`, this keeps collecting responses until `options.timeout` has elapsed,
+/// and returns every gateway that answered, once each. Without a timeout, responses are collected
+/// for the 3 seconds that gateways are given to answer the search. A gateway that answers from
+/// several addresses is recognized by the USN of its replies.
+///
+/// With `options.all_interfaces`, the search is made from every local IPv4 interface at once, and
+/// the gateways are merged. Interfaces whose search fails are skipped.
+///
+/// The gateways are ordered by latency, fastest first: the time from the search request to the
+/// resolved description of the gateway. With `options.prefer_default_route`, the gateways on the
+/// subnet of the default route of this host come first, whatever their latency.
+pub fn search_gateways(options: SearchOptions) -> Result<Vec<Gateway>, SearchError> {
+    let prefer_default_route = options.prefer_default_route;
+    let mut gateways = if options.all_interfaces {
+        search_gateways_on_interfaces(options)?
+    } else {
+        search_gateways_from(options)?
+    };
+    common::sort_by_latency(&mut gateways, prefer_default_route, |gateway| gateway.addr);
+    Ok(gateways.into_iter().map(|(gateway, _)| gateway).collect())
+}
 
-        let (control_schema_url, control_url) = match get_control_urls(&addr, &root_url) {
+// Search all gateways from `options.bind_addr` only.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "igd.search", level = "debug", skip_all, fields(broadcast_address = %options.broadcast_address))
+)]
+fn search_gateways_from(options: SearchOptions) -> Result<Vec<(Gateway, Duration)>, SearchError> {
+    let _timing = Timing::start(&options.timing_hook, "search");
+    let socket = common::bind_search_socket(&options)?;
+    let deadline = Instant::now() + options.timeout.unwrap_or(messages::SEARCH_RESPONSE_DELAY);
+    let mut retransmissions = common::Retransmissions::new(options.retransmissions);
+
+    let request = messages::format_search_request(&options.search_target, options.broadcast_address);
+    socket.send_to(request.as_bytes(), options.broadcast_address)?;
+    let mut latency = common::SearchLatency::new(Instant::now());
+
+    let mut gateways: Vec<(Gateway, Duration)> = Vec::new();
+    while let Some(response) =
+        receive_search_response_until(&socket, &request, &options, &mut retransmissions, Some(deadline))?
+    {
+        let response = match str::from_utf8(&response)
+            .map_err(SearchError::from)
+            .and_then(parsing::parse_search_result)
+        {
             Ok(o) => o,
             Err(e) => {
-                debug!(
-                    "Error has occurred while getting control urls. error: {}, addr: {}, root_url: {}",
-                    e, addr, root_url
-                );
+                debug!("Ignoring invalid search response. error: {}", e);
                 continue;
             }
         };
+        let known = gateways.iter().any(|(gateway, _)| {
+            common::is_same_device(
+                gateway.addr,
+                gateway.usn.as_deref(),
+                response.addr,
+                response.usn.as_deref(),
+            )
+        });
+        if known {
+            continue;
+        }
 
-        let control_schema = match get_schemas(&addr, &control_schema_url) {
-            Ok(o) => o,
-            Err(e) => {
-                debug!(
-                    "Error has occurred while getting schemas. error: {}, addr: {}, control_schema_url: {}",
-                    e, addr, control_schema_url
-                );
+        let started = Instant::now();
+        match get_gateway(response, &options) {
+            Some((gateway, _)) => gateways.push((gateway, latency.resolved(started, Instant::now()))),
+            None => latency.skipped(started, Instant::now()),
+        }
+    }
+
+    Ok(gateways)
+}
+
+fn search_gateways_on_interfaces(options: SearchOptions) -> Result<Vec<(Gateway, Duration)>, SearchError> {
+    let searches: Vec<_> = options
+        .per_interface()?
+        .into_iter()
+        .map(|options| {
+            let bind_addr = options.bind_addr;
+            (bind_addr, thread::spawn(move || search_gateways_from(options)))
+        })
+        .collect();
+
+    let mut gateways: Vec<(Gateway, Duration)> = Vec::new();
+    for (bind_addr, search) in searches {
+        match search.join() {
+            Ok(Ok(found)) => {
+                common::merge_gateways(&mut gateways, found, |gateway| (gateway.addr, gateway.usn.as_deref()))
+            }
+            Ok(Err(e)) => debug!("Search from {} failed. error: {}", bind_addr, e),
+            Err(_) => debug!("Search from {} panicked", bind_addr),
+        }
+    }
+    Ok(gateways)
+}
+
+// Receive the next search response, resending the request whenever a retransmission is due.
+// Returns `None` once the deadline has passed.
+fn receive_search_response_until(
+    socket: &UdpSocket,
+    request: &str,
+    options: &SearchOptions,
+    retransmissions: &mut common::Retransmissions,
+    deadline: Option<Instant>,
+) -> io::Result<Option<Vec<u8>>> {
+    loop {
+        if retransmissions.due() {
+            socket.send_to(request.as_bytes(), options.broadcast_address)?;
+        }
+
+        let now = Instant::now();
+        if matches!(deadline, Some(deadline) if now >= deadline) {
+            return Ok(None);
+        }
+        let wake = common::earliest_timeout(
+            deadline.map(|deadline| deadline - now),
+            retransmissions.next().map(|next| next.saturating_duration_since(now)),
+        );
+        // A zero read timeout is rejected, it would mean blocking forever.
+        socket.set_read_timeout(wake.map(|wake| wake.max(Duration::from_millis(1))))?;
+
+        match receive_search_response(socket) {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => continue,
+            Ok(ref response) if !parsing::is_search_response(response) => {
+                debug!("Ignoring datagram that is not a search response");
                 continue;
             }
-        };
+            result => return result.map(Some),
+        }
+    }
+}
 
-        return Ok(Gateway {
-            addr,
-            root_url,
-            control_url,
-            control_schema_url,
-            control_schema,
-        });
+// Receive the next search response, skipping the ones that were truncated because they are too large.
+fn receive_search_response(socket: &UdpSocket) -> io::Result<Vec<u8>> {
+    // One extra byte to tell a response that fills the buffer exactly from a truncated one.
+    let mut buf = [0u8; messages::MAX_SEARCH_RESPONSE_SIZE + 1];
+    loop {
+        let (read, from) = socket.recv_from(&mut buf)?;
+        if read > messages::MAX_SEARCH_RESPONSE_SIZE {
+            debug!(
+                "Ignoring search response larger than {} bytes from: {}",
+                messages::MAX_SEARCH_RESPONSE_SIZE,
+                from
+            );
+            continue;
+        }
+        return Ok(buf[..read].to_vec());
     }
 }
 
-fn get_control_urls(addr: &SocketAddrV4, root_url: &str) -> Result<(String, String), SearchError> {
-    let url = format!("http://{}:{}{}", addr.ip(), addr.port(), root_url);
+// Fetch the description and control schema of the gateway that answered the search.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "igd.fetch_description", level = "debug", skip_all, fields(gateway = %response.addr, url = %response.root_url))
+)]
+fn get_gateway(response: SearchResponse, options: &SearchOptions) -> Option<(Gateway, GatewayInfo)> {
+    let SearchResponse {
+        addr,
+        root_url,
+        server,
+        usn,
+        max_age,
+    } = response;
+    let _timing = Timing::start(&options.timing_hook, "fetch_description");
+    let headers = common::request_headers(options);
+    let description_headers = common::with_credentials(&headers, &options.credentials);
 
-    match RequestBuilder::try_new(Method::GET, &url) {
-        Ok(request_builder) => {
-            let response = request_builder.send()?;
-            parsing::parse_control_urls(&response.bytes()?[..])
+    let description = match get_description(&addr, &root_url, &description_headers, options.http_timeout) {
+        Ok(o) => o,
+        Err(e) => {
+            debug!(
+                "Error has occurred while getting control urls. error: {}, addr: {}, root_url: {}",
+                e, addr, root_url
+            );
+            return None;
         }
-        Err(error) => Err(SearchError::HttpError(error)),
-    }
+    };
+    let (control_schema_url, control_url, service_type) = description.connection_services[0].urls.clone();
+
+    let mut gateway = Gateway {
+        addr,
+        root_url,
+        control_url,
+        control_schema_url,
+        control_schema: HashMap::new(),
+        service_type,
+        connection_retries: common::DEFAULT_CONNECTION_RETRIES,
+        port_range: common::DEFAULT_PORT_RANGE,
+        port_picker: common::default_port_picker(),
+        headers,
+        http_timeout: options.http_timeout,
+        common_interface_url: description.common_interface_url.clone(),
+        event_url: description.connection_services[0].event_url.clone(),
+        services: description.services.clone(),
+        info: description.info.clone(),
+        server,
+        usn,
+        max_age,
+        credentials: options.credentials.clone(),
+        timing_hook: options.timing_hook.clone(),
+        soap_hook: options.soap_hook.clone(),
+    };
+    gateway.select_connection_service(
+        &description.connection_services,
+        description.layer3_forwarding_url.as_deref(),
+    );
+
+    gateway.control_schema = match get_schemas(
+        &addr,
+        &gateway.control_schema_url,
+        &description_headers,
+        options.http_timeout,
+    ) {
+        Ok(o) => o,
+        Err(e) => {
+            debug!(
+                "Error has occurred while getting schemas. error: {}, addr: {}, control_schema_url: {}",
+                e, addr, gateway.control_schema_url
+            );
+            return None;
+        }
+    };
+    Some((gateway, description.info))
 }
 
-fn get_schemas(addr: &SocketAddrV4, control_schema_url: &str) -> Result<HashMap<String, Vec<String>>, SearchError> {
-    let url = format!("http://{}:{}{}", addr.ip(), addr.port(), control_schema_url);
+fn get_description(
+    addr: &SocketAddr,
+    root_url: &str,
+    headers: &[(String, String)],
+    http_timeout: Option<Duration>,
+) -> Result<DeviceDescription, SearchError> {
+    let url = parsing::resolve_url(addr, root_url);
+    let (body, final_url) = get(&url, headers, http_timeout)?;
+    parsing::parse_device_description(&body, &url, &final_url)
+}
 
-    match RequestBuilder::try_new(Method::GET, &url) {
-        Ok(request_builder) => {
-            let response = request_builder.send()?;
-            parsing::parse_schemas(&response.bytes()?[..])
+fn get_schemas(
+    addr: &SocketAddr,
+    control_schema_url: &str,
+    headers: &[(String, String)],
+    http_timeout: Option<Duration>,
+) -> Result<HashMap<String, Vec<String>>, SearchError> {
+    let (body, _) = get(&parsing::resolve_url(addr, control_schema_url), headers, http_timeout)?;
+    parsing::parse_schemas(&body[..])
+}
+
+// Fetch a document, following redirects, and return it along with the url it was finally found at.
+fn get(
+    url: &str,
+    headers: &[(String, String)],
+    http_timeout: Option<Duration>,
+) -> Result<(Vec<u8>, String), SearchError> {
+    let mut url = url.to_string();
+    let mut headers = headers.to_vec();
+    for _ in 0..=common::MAX_REDIRECTS {
+        let mut request_builder =
+            common::add_headers(RequestBuilder::try_new(Method::GET, &url)?, &headers)?.follow_redirects(false);
+        if let Some(timeout) = http_timeout {
+            request_builder = request_builder.timeout(timeout);
+        }
+        let response = request_builder.send()?;
+
+        let location = response
+            .headers()
+            .get("Location")
+            .and_then(|location| location.to_str().ok());
+        match location {
+            Some(location) if common::is_redirect(response.status().as_u16()) => {
+                debug!("Following redirect from {} to {}", url, location);
+                let redirected = parsing::join_url(&url, location);
+                if !parsing::is_same_origin(&url, &redirected) {
+                    headers = common::without_credentials(&headers);
+                }
+                url = redirected;
+            }
+            _ if !response.status().is_success() => return Err(SearchError::HttpStatus(response.status().as_u16())),
+            _ => return Ok((response.bytes()?, url)),
         }
-        Err(error) => Err(SearchError::HttpError(error)),
+    }
+    Err(SearchError::InvalidResponse(format!(
+        "too many redirects, last to {}",
+        url
+    )))
+}
+
+#[test]
+fn test_receive_search_response_skips_oversized() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    sender
+        .send_to(&[b'a'; messages::MAX_SEARCH_RESPONSE_SIZE + 100], addr)
+        .unwrap();
+    sender.send_to(b"HTTP/1.1 200 OK\r\n\r\n", addr).unwrap();
+
+    let response = receive_search_response(&socket).unwrap();
+    assert_eq!(response, b"HTTP/1.1 200 OK\r\n\r\n");
+}
+
+#[test]
+fn test_receive_search_response_skips_notify() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    let options = SearchOptions::builder()
+        .broadcast_address(sender.local_addr().unwrap())
+        .build();
+    let mut retransmissions = common::Retransmissions::new(0);
+
+    sender
+        .send_to(
+            b"NOTIFY * HTTP/1.1\r\nNTS: ssdp:byebye\r\nLOCATION: http://127.0.0.1:1/rootDesc.xml\r\n\r\n",
+            addr,
+        )
+        .unwrap();
+    sender.send_to(b"HTTP/1.1 200 OK\r\n\r\n", addr).unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let response = receive_search_response_until(&socket, "", &options, &mut retransmissions, Some(deadline)).unwrap();
+    assert_eq!(response.unwrap(), b"HTTP/1.1 200 OK\r\n\r\n");
+}
+
+#[test]
+fn test_search_gateway_timeout() {
+    // Nothing answers on this socket.
+    let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let options = SearchOptions::builder()
+        .bind_addr("127.0.0.1:0".parse().unwrap())
+        .broadcast_address(silent.local_addr().unwrap())
+        .timeout(Some(Duration::from_millis(100)))
+        .build();
+    match search_gateway(options) {
+        Err(SearchError::Timeout) => {}
+        r => panic!("unexpected result: {:?}", r.map(|gateway| gateway.to_string())),
+    }
+}
+
+#[test]
+fn test_get_http_status() {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = stream.read(&mut [0u8; 1024]);
+        let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    });
+
+    match get(
+        &parsing::resolve_url(addr, "/rootDesc.xml"),
+        &[],
+        Some(Duration::from_secs(5)),
+    ) {
+        Err(SearchError::HttpStatus(404)) => {}
+        r => panic!("unexpected result: {:?}", r),
     }
 }
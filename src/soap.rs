@@ -0,0 +1,19 @@
+//! Building blocks for the SOAP envelopes of actions the crate does not wrap.
+//!
+//! The envelope can be sent with `Gateway::action`, which builds it from a list of arguments,
+//! or by any other HTTP client along with the `SOAPAction` header.
+//!
+//! # Example
+//! ```
+//! use igd::soap::{escape_xml, format_message};
+//!
+//! let message = format_message(format!(
+//!     r#"<u:SetConnectionType xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+//!     <NewConnectionType>{}</NewConnectionType>
+//!     </u:SetConnectionType>"#,
+//!     escape_xml("IP_Routed"),
+//! ));
+//! assert!(message.contains("<NewConnectionType>IP_Routed</NewConnectionType>"));
+//! ```
+
+pub use crate::common::messages::{escape_xml, format_message, MESSAGE_HEAD, MESSAGE_TAIL};
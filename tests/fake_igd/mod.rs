@@ -0,0 +1,445 @@
+//! A fake IGD for the integration tests. It answers the search over UDP, serves the device
+//! description and control schema over HTTP, and answers the actions with a handler.
+
+#![allow(dead_code)]
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use flate2::{write::GzEncoder, Compression};
+use igd::SearchOptions;
+
+pub const SERVICE_TYPE: &str = "urn:schemas-upnp-org:service:WANIPConnection:1";
+pub const CONTROL_URL: &str = "/ctl/IPConn";
+/// Path that redirects to the device description
+pub const REDIRECT_PATH: &str = "/redirect/rootDesc.xml";
+/// Path of the device description sent with `Content-Encoding: gzip`
+pub const GZIP_PATH: &str = "/gzip/rootDesc.xml";
+/// SERVER header of the search reply
+pub const SERVER: &str = "Fake/1.0 UPnP/1.1 rust-igd/0.12";
+/// USN header of the search reply
+pub const USN: &str = "uuid:00000000-0000-0000-0000-000000000000::urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+/// SID of the event subscriptions the fake IGD accepts
+pub const SUBSCRIPTION_ID: &str = "uuid:fake-subscription";
+
+const DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+   <specVersion><major>1</major><minor>0</minor></specVersion>
+   <device>
+      <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+      <friendlyName>Fake IGD</friendlyName>
+      <manufacturer>rust-igd</manufacturer>
+      <modelName>Fake</modelName>
+      <UDN>uuid:00000000-0000-0000-0000-000000000000</UDN>
+      <deviceList>
+         <device>
+            <deviceType>urn:schemas-upnp-org:device:WANDevice:1</deviceType>
+            <deviceList>
+               <device>
+                  <deviceType>urn:schemas-upnp-org:device:WANConnectionDevice:1</deviceType>
+                  <serviceList>
+                     <service>
+                        <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+                        <serviceId>urn:upnp-org:serviceId:WANIPConn1</serviceId>
+                        <controlURL>/ctl/IPConn</controlURL>
+                        <eventSubURL>/evt/IPConn</eventSubURL>
+                        <SCPDURL>/WANIPCn.xml</SCPDURL>
+                     </service>
+                  </serviceList>
+               </device>
+            </deviceList>
+         </device>
+      </deviceList>
+   </device>
+</root>"#;
+
+/// Description of a device with two WAN ports, each with its own connection service, and the
+/// Layer3Forwarding service that tells which one is active.
+pub const MULTI_WAN_DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+   <specVersion><major>1</major><minor>0</minor></specVersion>
+   <device>
+      <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+      <friendlyName>Fake multi-WAN IGD</friendlyName>
+      <UDN>uuid:00000000-0000-0000-0000-000000000000</UDN>
+      <serviceList>
+         <service>
+            <serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>
+            <serviceId>urn:upnp-org:serviceId:L3Forwarding1</serviceId>
+            <controlURL>/ctl/L3F</controlURL>
+            <eventSubURL>/evt/L3F</eventSubURL>
+            <SCPDURL>/L3F.xml</SCPDURL>
+         </service>
+      </serviceList>
+      <deviceList>
+         <device>
+            <deviceType>urn:schemas-upnp-org:device:WANDevice:1</deviceType>
+            <UDN>uuid:00000000-0000-0000-0000-000000000001</UDN>
+            <deviceList>
+               <device>
+                  <deviceType>urn:schemas-upnp-org:device:WANConnectionDevice:1</deviceType>
+                  <UDN>uuid:00000000-0000-0000-0000-000000000011</UDN>
+                  <serviceList>
+                     <service>
+                        <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+                        <serviceId>urn:upnp-org:serviceId:WANIPConn1</serviceId>
+                        <controlURL>/ctl/IPConn</controlURL>
+                        <eventSubURL>/evt/IPConn</eventSubURL>
+                        <SCPDURL>/WANIPCn.xml</SCPDURL>
+                     </service>
+                  </serviceList>
+               </device>
+            </deviceList>
+         </device>
+         <device>
+            <deviceType>urn:schemas-upnp-org:device:WANDevice:1</deviceType>
+            <UDN>uuid:00000000-0000-0000-0000-000000000002</UDN>
+            <deviceList>
+               <device>
+                  <deviceType>urn:schemas-upnp-org:device:WANConnectionDevice:1</deviceType>
+                  <UDN>uuid:00000000-0000-0000-0000-000000000022</UDN>
+                  <serviceList>
+                     <service>
+                        <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+                        <serviceId>urn:upnp-org:serviceId:WANIPConn1</serviceId>
+                        <controlURL>/ctl/IPConn2</controlURL>
+                        <eventSubURL>/evt/IPConn2</eventSubURL>
+                        <SCPDURL>/WANIPCn.xml</SCPDURL>
+                     </service>
+                  </serviceList>
+               </device>
+            </deviceList>
+         </device>
+      </deviceList>
+   </device>
+</root>"#;
+
+const SCHEMA: &str = r#"<?xml version="1.0"?>
+<scpd xmlns="urn:schemas-upnp-org:service-1-0">
+   <actionList>
+      <action>
+         <name>AddPortMapping</name>
+         <argumentList>
+            <argument><name>NewRemoteHost</name><direction>in</direction></argument>
+            <argument><name>NewExternalPort</name><direction>in</direction></argument>
+            <argument><name>NewProtocol</name><direction>in</direction></argument>
+            <argument><name>NewInternalPort</name><direction>in</direction></argument>
+            <argument><name>NewInternalClient</name><direction>in</direction></argument>
+            <argument><name>NewEnabled</name><direction>in</direction></argument>
+            <argument><name>NewPortMappingDescription</name><direction>in</direction></argument>
+            <argument><name>NewLeaseDuration</name><direction>in</direction></argument>
+         </argumentList>
+      </action>
+      <action>
+         <name>DeletePortMapping</name>
+         <argumentList>
+            <argument><name>NewRemoteHost</name><direction>in</direction></argument>
+            <argument><name>NewExternalPort</name><direction>in</direction></argument>
+            <argument><name>NewProtocol</name><direction>in</direction></argument>
+         </argumentList>
+      </action>
+   </actionList>
+</scpd>"#;
+
+/// Answer to an action: the output arguments, or a UPnP error code.
+pub type ActionResult = Result<String, u16>;
+
+/// A running fake IGD. Its threads live until the end of the test process.
+pub struct FakeIgd {
+    /// Address the search request must be sent to
+    pub search_addr: SocketAddr,
+    /// Address of the HTTP server
+    pub http_addr: SocketAddr,
+    path: &'static str,
+}
+
+impl FakeIgd {
+    /// Start a fake IGD on the loopback interface. The handler is called with the name and
+    /// the body of every action it receives.
+    pub fn start<F>(handler: F) -> FakeIgd
+    where
+        F: Fn(&str, &str) -> ActionResult + Send + Sync + 'static,
+    {
+        FakeIgd::start_with_location("/rootDesc.xml", handler)
+    }
+
+    /// Start a fake IGD that announces its device description at `path`.
+    pub fn start_with_location<F>(path: &'static str, handler: F) -> FakeIgd
+    where
+        F: Fn(&str, &str) -> ActionResult + Send + Sync + 'static,
+    {
+        FakeIgd::start_with(path, DESCRIPTION, move |_, action, body| handler(action, body))
+    }
+
+    /// Start a fake IGD that serves the given device description. Actions are answered on every
+    /// control url under `/ctl/`, and the handler is also called with that url.
+    pub fn start_with_description<F>(description: &'static str, handler: F) -> FakeIgd
+    where
+        F: Fn(&str, &str, &str) -> ActionResult + Send + Sync + 'static,
+    {
+        FakeIgd::start_with("/rootDesc.xml", description, handler)
+    }
+
+    fn start_with<F>(path: &'static str, description: &'static str, handler: F) -> FakeIgd
+    where
+        F: Fn(&str, &str, &str) -> ActionResult + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let http_addr = listener.local_addr().unwrap();
+        let handler = Arc::new(handler);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let handler = handler.clone();
+                if let Ok(stream) = stream {
+                    thread::spawn(move || serve(stream, description, &*handler));
+                }
+            }
+        });
+
+        let search_addr = start_search_responder(vec![search_reply(http_addr, path)]);
+        FakeIgd {
+            search_addr,
+            http_addr,
+            path,
+        }
+    }
+
+    /// The reply of this fake IGD to a search.
+    pub fn search_reply(&self) -> String {
+        search_reply(self.http_addr, self.path)
+    }
+
+    /// Options that search this fake IGD only.
+    pub fn search_options(&self) -> SearchOptions {
+        SearchOptions::builder()
+            .bind_addr("127.0.0.1:0".parse().unwrap())
+            .broadcast_address(self.search_addr)
+            .timeout(Some(Duration::from_secs(5)))
+            .build()
+    }
+}
+
+/// Answer every search sent to the returned address with the given replies, in order.
+pub fn start_search_responder(replies: Vec<String>) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let search_addr = socket.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0u8; 2048];
+        while let Ok((read, from)) = socket.recv_from(&mut buf) {
+            if buf[..read].starts_with(b"M-SEARCH") {
+                for reply in &replies {
+                    let _ = socket.send_to(reply.as_bytes(), from);
+                }
+            }
+        }
+    });
+    search_addr
+}
+
+/// The reply to a search of a gateway whose device description is at `path` on `http_addr`.
+pub fn search_reply(http_addr: SocketAddr, path: &str) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\nST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\
+         SERVER: {}\r\nUSN: {}\r\nLOCATION: http://{}{}\r\n\r\n",
+        SERVER, USN, http_addr, path
+    )
+}
+
+/// Answer every HTTP request sent to the returned address with `response`, and send the head of
+/// each request to the returned channel.
+pub fn start_recording_server(response: String) -> (SocketAddr, Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let http_addr = listener.local_addr().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            if let Some((head, _)) = read_request(&mut stream) {
+                let _ = sender.send(head);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        }
+    });
+    (http_addr, receiver)
+}
+
+fn serve(mut stream: TcpStream, description: &str, handler: &dyn Fn(&str, &str, &str) -> ActionResult) {
+    let (head, body) = match read_request(&mut stream) {
+        Some(request) => request,
+        None => return,
+    };
+    let path = head.split_whitespace().nth(1).unwrap_or_default();
+    if head.starts_with("GET") && path == REDIRECT_PATH {
+        let _ = write!(
+            stream,
+            "HTTP/1.1 302 Found\r\nLocation: /rootDesc.xml\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+        return;
+    }
+    if head.starts_with("GET") && path == GZIP_PATH {
+        let body = gzip(description.as_bytes());
+        let _ = write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        );
+        let _ = stream.write_all(&body);
+        return;
+    }
+    let (status, response) = if head.starts_with("GET") && path == "/rootDesc.xml" {
+        ("200 OK", description.to_string())
+    } else if head.starts_with("GET") && path == "/WANIPCn.xml" {
+        ("200 OK", SCHEMA.to_string())
+    } else if head.starts_with("POST") && path.starts_with("/ctl/") {
+        let action = header(&head, "soapaction")
+            .and_then(|soap_action| soap_action.trim_matches('"').rsplit('#').next().map(String::from))
+            .unwrap_or_default();
+        match handler(path, &action, &body) {
+            Ok(arguments) => ("200 OK", action_response(&action, &arguments)),
+            Err(code) => ("500 Internal Server Error", fault_response(code)),
+        }
+    } else if (head.starts_with("SUBSCRIBE") || head.starts_with("UNSUBSCRIBE")) && path.starts_with("/evt/") {
+        serve_event_subscription(stream, &head, path, handler);
+        return;
+    } else {
+        ("404 Not Found", String::new())
+    };
+
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        response.len(),
+        response
+    );
+}
+
+// GENA requests are passed to the handler as `SUBSCRIBE` or `UNSUBSCRIBE` actions. The output
+// arguments of a new subscription are sent to its callback as the first event.
+fn serve_event_subscription(
+    mut stream: TcpStream,
+    head: &str,
+    path: &str,
+    handler: &dyn Fn(&str, &str, &str) -> ActionResult,
+) {
+    let method = head.split_whitespace().next().unwrap_or_default();
+    let sid = header(head, "sid").unwrap_or_else(|| SUBSCRIPTION_ID.to_string());
+    let result = handler(path, method, &sid);
+    let status = match result {
+        Ok(_) => "200 OK",
+        Err(_) => "412 Precondition Failed",
+    };
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {}\r\nSID: {}\r\nTIMEOUT: Second-300\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status, sid
+    );
+    drop(stream);
+
+    let callback = header(head, "callback").and_then(|callback| {
+        let url = callback.trim_matches(|c| c == '<' || c == '>');
+        url.strip_prefix("http://")?
+            .trim_end_matches('/')
+            .parse::<SocketAddr>()
+            .ok()
+    });
+    if let (Some(callback), Ok(variables)) = (callback, result) {
+        let body = format!(
+            r#"<?xml version="1.0"?>
+<e:propertyset xmlns:e="urn:schemas-upnp-org:event-1-0"><e:property>{}</e:property></e:propertyset>"#,
+            variables
+        );
+        if let Ok(mut stream) = TcpStream::connect(callback) {
+            let _ = write!(
+                stream,
+                "NOTIFY / HTTP/1.1\r\nHOST: {}\r\nCONTENT-TYPE: text/xml\r\nNT: upnp:event\r\nNTS: upnp:propchange\r\n\
+                 SID: {}\r\nSEQ: 0\r\nContent-Length: {}\r\n\r\n{}",
+                callback,
+                sid,
+                body.len(),
+                body
+            );
+            let _ = stream.read(&mut [0u8; 1024]);
+        }
+    }
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+// Read the head and the body of an HTTP request.
+fn read_request(stream: &mut TcpStream) -> Option<(String, String)> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 1024];
+    let head_end = loop {
+        let read = stream.read(&mut buf).ok()?;
+        if read == 0 {
+            return None;
+        }
+        data.extend_from_slice(&buf[..read]);
+        if let Some(end) = data.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end;
+        }
+    };
+    let head = String::from_utf8_lossy(&data[..head_end]).into_owned();
+    let content_length = header(&head, "content-length")
+        .and_then(|length| length.parse::<usize>().ok())
+        .unwrap_or(0);
+    while data.len() < head_end + 4 + content_length {
+        let read = stream.read(&mut buf).ok()?;
+        if read == 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..read]);
+    }
+    let body = String::from_utf8_lossy(&data[head_end + 4..]).into_owned();
+    Some((head, body))
+}
+
+fn header(head: &str, name: &str) -> Option<String> {
+    head.lines().skip(1).find_map(|line| {
+        let colon = line.find(':')?;
+        if line[..colon].trim().eq_ignore_ascii_case(name) {
+            Some(line[colon + 1..].trim().to_string())
+        } else {
+            None
+        }
+    })
+}
+
+fn action_response(action: &str, arguments: &str) -> String {
+    format!(
+        r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+<s:Body><u:{action}Response xmlns:u="{service_type}">{arguments}</u:{action}Response></s:Body>
+</s:Envelope>"#,
+        action = action,
+        service_type = SERVICE_TYPE,
+        arguments = arguments
+    )
+}
+
+fn fault_response(code: u16) -> String {
+    format!(
+        r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+<s:Body><s:Fault><faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring><detail>
+<UPnPError xmlns="urn:schemas-upnp-org:control-1-0"><errorCode>{}</errorCode><errorDescription>Fake error</errorDescription></UPnPError>
+</detail></s:Fault></s:Body>
+</s:Envelope>"#,
+        code
+    )
+}
+
+/// Value of an argument in the body of an action.
+pub fn argument<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    let start = body.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = start + body[start..].find(&format!("</{}>", name))?;
+    Some(&body[start..end])
+}
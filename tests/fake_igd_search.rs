@@ -0,0 +1,764 @@
+mod fake_igd;
+
+use std::net::Ipv4Addr;
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use fake_igd::{argument, FakeIgd};
+use igd::{AddPortError, GetExternalIpError, PortMappingProtocol, PortPicker, RequestError};
+
+const EXTERNAL_IP: &str = "<NewExternalIPAddress>203.0.113.1</NewExternalIPAddress>";
+
+fn port_mapping_entry(external_port: u16) -> String {
+    described_port_mapping_entry(external_port, "test")
+}
+
+fn described_port_mapping_entry(external_port: u16, description: &str) -> String {
+    format!(
+        "<NewRemoteHost></NewRemoteHost><NewExternalPort>{}</NewExternalPort><NewProtocol>TCP</NewProtocol>\
+         <NewInternalPort>8080</NewInternalPort><NewInternalClient>192.168.1.2</NewInternalClient>\
+         <NewEnabled>1</NewEnabled><NewPortMappingDescription>{}</NewPortMappingDescription>\
+         <NewLeaseDuration>0</NewLeaseDuration>",
+        external_port, description
+    )
+}
+
+#[test]
+fn test_search_gateway() {
+    let igd = FakeIgd::start(|action, _| match action {
+        "GetExternalIPAddress" => Ok(EXTERNAL_IP.to_string()),
+        _ => Err(401),
+    });
+
+    let gateway = igd::search_gateway(igd.search_options()).unwrap();
+    assert_eq!(gateway.addr.to_string(), igd.http_addr.to_string());
+    assert_eq!(gateway.control_url, fake_igd::CONTROL_URL);
+    assert_eq!(gateway.service_type(), fake_igd::SERVICE_TYPE);
+    assert_eq!(gateway.friendly_name(), "Fake IGD");
+    assert_eq!(gateway.server.as_deref(), Some(fake_igd::SERVER));
+    assert_eq!(gateway.usn.as_deref(), Some(fake_igd::USN));
+    assert_eq!(gateway.max_age, Some(Duration::from_secs(120)));
+    assert!(gateway.supports_action("AddPortMapping"));
+    assert!(!gateway.supports_action("AddAnyPortMapping"));
+    assert_eq!(gateway.get_external_ip().unwrap(), Ipv4Addr::new(203, 0, 113, 1));
+}
+
+#[test]
+fn test_search_gateway_redirected_description() {
+    let igd = FakeIgd::start_with_location(fake_igd::REDIRECT_PATH, |action, _| match action {
+        "GetExternalIPAddress" => Ok(EXTERNAL_IP.to_string()),
+        _ => Err(401),
+    });
+
+    let gateway = igd::search_gateway(igd.search_options()).unwrap();
+    assert_eq!(
+        gateway.control_url,
+        format!("http://{}{}", igd.http_addr, fake_igd::CONTROL_URL)
+    );
+    assert_eq!(gateway.friendly_name(), "Fake IGD");
+    assert_eq!(gateway.get_external_ip().unwrap(), Ipv4Addr::new(203, 0, 113, 1));
+}
+
+#[test]
+fn test_search_gateways_prefer_default_route() {
+    let igd = FakeIgd::start(|_, _| Err(401));
+
+    // The fake gateway is on the loopback interface, never on the default route, and is still found.
+    let options = igd::SearchOptions {
+        timeout: Some(Duration::from_millis(500)),
+        prefer_default_route: true,
+        ..igd.search_options()
+    };
+    let gateways = igd::search_gateways(options).unwrap();
+    assert_eq!(gateways.len(), 1);
+    assert_eq!(gateways[0].addr, igd.http_addr);
+}
+
+#[test]
+fn test_search_gateways_deduplicates_by_usn() {
+    let igd = FakeIgd::start(|_, _| Err(401));
+    let other_igd = FakeIgd::start(|_, _| Err(401));
+    // The same device, with the same USN, answering from two addresses.
+    let search_addr = fake_igd::start_search_responder(vec![igd.search_reply(), other_igd.search_reply()]);
+
+    let options = igd::SearchOptions {
+        broadcast_address: search_addr,
+        timeout: Some(Duration::from_millis(500)),
+        ..igd.search_options()
+    };
+    let gateways = igd::search_gateways(options).unwrap();
+    assert_eq!(gateways.len(), 1);
+    assert_eq!(gateways[0].addr, igd.http_addr);
+    assert_eq!(gateways[0].usn.as_deref(), Some(fake_igd::USN));
+}
+
+// A gateway whose description redirects to another host, searched with credentials. Returns the
+// heads of the requests received by the gateway and by the other host.
+fn cross_origin_redirect() -> (igd::SearchOptions, Receiver<String>, Receiver<String>) {
+    let (other_addr, other_requests) = fake_igd::start_recording_server(
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    );
+    let (http_addr, requests) = fake_igd::start_recording_server(format!(
+        "HTTP/1.1 302 Found\r\nLocation: http://{}/rootDesc.xml\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        other_addr
+    ));
+    let search_addr = fake_igd::start_search_responder(vec![fake_igd::search_reply(http_addr, "/rootDesc.xml")]);
+    let options = igd::SearchOptions::builder()
+        .bind_addr("127.0.0.1:0".parse().unwrap())
+        .broadcast_address(search_addr)
+        .timeout(Some(Duration::from_millis(500)))
+        .credentials("admin", "secret")
+        .build();
+    (options, requests, other_requests)
+}
+
+fn has_authorization(head: &str) -> bool {
+    head.to_ascii_lowercase().contains("\r\nauthorization:")
+}
+
+#[test]
+fn test_search_gateway_cross_origin_redirect_drops_credentials() {
+    let (options, requests, other_requests) = cross_origin_redirect();
+    assert!(igd::search_gateway(options).is_err());
+    assert!(has_authorization(
+        &requests.recv_timeout(Duration::from_secs(1)).unwrap()
+    ));
+    assert!(!has_authorization(
+        &other_requests.recv_timeout(Duration::from_secs(1)).unwrap()
+    ));
+}
+
+#[cfg(feature = "compress")]
+#[test]
+fn test_search_gateway_gzip_description() {
+    let igd = FakeIgd::start_with_location(fake_igd::GZIP_PATH, |action, _| match action {
+        "GetExternalIPAddress" => Ok(EXTERNAL_IP.to_string()),
+        _ => Err(401),
+    });
+
+    let gateway = igd::search_gateway(igd.search_options()).unwrap();
+    assert_eq!(gateway.friendly_name(), "Fake IGD");
+    assert_eq!(gateway.get_external_ip().unwrap(), Ipv4Addr::new(203, 0, 113, 1));
+}
+
+#[test]
+fn test_search_gateway_default_connection_service() {
+    let igd = FakeIgd::start_with_description(fake_igd::MULTI_WAN_DESCRIPTION, |_, action, _| match action {
+        "GetDefaultConnectionService" => Ok("<NewDefaultConnectionService>\
+             uuid:00000000-0000-0000-0000-000000000022:WANConnectionDevice:1,urn:upnp-org:serviceId:WANIPConn1\
+             </NewDefaultConnectionService>"
+            .to_string()),
+        _ => Err(401),
+    });
+
+    let gateway = igd::search_gateway(igd.search_options()).unwrap();
+    assert_eq!(gateway.control_url, "/ctl/IPConn2");
+    assert!(gateway.supports_action("AddPortMapping"));
+    assert_eq!(
+        gateway.control_url_for("urn:schemas-upnp-org:service:Layer3Forwarding:1"),
+        Some("/ctl/L3F")
+    );
+    // The entry of the connection service type is the selected service.
+    let connection_service = &gateway.services[fake_igd::SERVICE_TYPE];
+    assert_eq!(connection_service.control_url, "/ctl/IPConn2");
+    assert_eq!(connection_service.event_url.as_deref(), Some("/evt/IPConn2"));
+}
+
+#[test]
+fn test_search_gateway_without_default_connection_service() {
+    let igd = FakeIgd::start_with_description(fake_igd::MULTI_WAN_DESCRIPTION, |_, _, _| Err(401));
+
+    let gateway = igd::search_gateway(igd.search_options()).unwrap();
+    assert_eq!(gateway.control_url, "/ctl/IPConn");
+}
+
+fn status_info(connection_status: &str) -> String {
+    format!(
+        "<NewConnectionStatus>{}</NewConnectionStatus><NewLastConnectionError>ERROR_NONE</NewLastConnectionError>\
+         <NewUptime>0</NewUptime>",
+        connection_status
+    )
+}
+
+#[test]
+fn test_search_gateway_connected_service() {
+    let igd = FakeIgd::start_with_description(fake_igd::MULTI_WAN_DESCRIPTION, |path, action, _| match action {
+        "GetStatusInfo" if path == "/ctl/IPConn2" => Ok(status_info("Connected")),
+        "GetStatusInfo" => Ok(status_info("Disconnected")),
+        _ => Err(401),
+    });
+
+    let gateway = igd::search_gateway(igd.search_options()).unwrap();
+    assert_eq!(gateway.control_url, "/ctl/IPConn2");
+}
+
+#[test]
+fn test_get_port_mappings_stops_at_invalid_index() {
+    let igd = FakeIgd::start(|action, body| match action {
+        "GetGenericPortMappingEntry" => match argument(body, "NewPortMappingIndex") {
+            Some("0") => Ok(port_mapping_entry(1234)),
+            Some("1") => Ok(port_mapping_entry(5678)),
+            _ => Err(713),
+        },
+        _ => Err(401),
+    });
+
+    let gateway = igd::search_gateway(igd.search_options()).unwrap();
+    let entries = gateway.get_port_mappings().unwrap();
+    let ports: Vec<u16> = entries.iter().map(|entry| entry.external_port).collect();
+    assert_eq!(ports, vec![1234, 5678]);
+}
+
+#[test]
+fn test_remove_mappings_where() {
+    let removed_ports = Arc::new(Mutex::new(Vec::new()));
+    let igd = {
+        let removed_ports = removed_ports.clone();
+        FakeIgd::start(move |action, body| match action {
+            "GetGenericPortMappingEntry" => match argument(body, "NewPortMappingIndex") {
+                Some("0") => Ok(described_port_mapping_entry(1234, "my-app:web")),
+                Some("1") => Ok(described_port_mapping_entry(5678, "other-app")),
+                Some("2") => Ok(described_port_mapping_entry(4321, "my-app:gone")),
+                _ => Err(713),
+            },
+            "DeletePortMapping" => match argument(body, "NewExternalPort") {
+                Some("4321") => Err(714),
+                port => {
+                    removed_ports.lock().unwrap().push(port.unwrap_or_default().to_string());
+                    Ok(String::new())
+                }
+            },
+            _ => Err(401),
+        })
+    };
+
+    let gateway = igd::search_gateway(igd.search_options()).unwrap();
+    let removed = gateway
+        .remove_mappings_where(|entry| entry.port_mapping_description.starts_with("my-app:"))
+        .unwrap();
+    assert_eq!(removed, 1);
+    assert_eq!(*removed_ports.lock().unwrap(), vec!["1234"]);
+}
+
+#[test]
+fn test_find_free_external_port() {
+    let igd = FakeIgd::start(|action, body| match action {
+        "GetGenericPortMappingEntry" => match argument(body, "NewPortMappingIndex") {
+            Some("0") => Ok(port_mapping_entry(40_001)),
+            Some("1") => Ok(port_mapping_entry(40_000)),
+            _ => Err(713),
+        },
+        _ => Err(401),
+    });
+
+    let gateway = igd::search_gateway(igd.search_options()).unwrap();
+    let free = |protocol, range| gateway.find_free_external_port(protocol, range).unwrap();
+    assert_eq!(free(PortMappingProtocol::TCP, 40_000..=40_010), Some(40_002));
+    assert_eq!(free(PortMappingProtocol::UDP, 40_000..=40_010), Some(40_000));
+    assert_eq!(free(PortMappingProtocol::TCP, 40_000..=40_001), None);
+}
+
+#[test]
+fn test_get_any_address_for_local_port() {
+    let igd = FakeIgd::start(|action, _| match action {
+        "GetExternalIPAddress" => Ok(EXTERNAL_IP.to_string()),
+        _ => Err(401),
+    });
+
+    // The fake IGD listens on loopback, so that is the route to it, and traffic can't be
+    // forwarded to a loopback address.
+    let gateway = igd::search_gateway(igd.search_options()).unwrap();
+    assert_eq!(gateway.local_ip().unwrap(), Ipv4Addr::LOCALHOST);
+    match gateway.get_any_address_for_local_port(PortMappingProtocol::TCP, 8080, 60, "test") {
+        Err(igd::AddAnyPortError::InternalClientInvalid) => {}
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn test_add_port_error_code() {
+    let igd = FakeIgd::start(|action, _| match action {
+        "AddPortMapping" => Err(718),
+        _ => Err(401),
+    });
+
+    let gateway = igd::search_gateway(igd.search_options()).unwrap();
+    let local_addr = "192.168.1.2:8080".parse().unwrap();
+    match gateway.add_port(PortMappingProtocol::TCP, 1234, local_addr, 60, "test") {
+        Err(AddPortError::PortInUse(_)) => {}
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn test_mapping_exists() {
+    let igd = FakeIgd::start(|action, body| match action {
+        "GetSpecificPortMappingEntry" => match argument(body, "NewExternalPort") {
+            Some("1234") => Ok(port_mapping_entry(1234)),
+            _ => Err(714),
+        },
+        _ => Err(401),
+    });
+
+    let gateway = igd::search_gateway(igd.search_options()).unwrap();
+    assert!(gateway.mapping_exists(PortMappingProtocol::TCP, 1234).unwrap());
+    assert!(!gateway.mapping_exists(PortMappingProtocol::TCP, 5678).unwrap());
+}
+
+#[derive(Debug, Default)]
+struct RecordingHook(Mutex<Vec<String>>);
+
+impl igd::TimingHook for RecordingHook {
+    fn on_timing(&self, operation: &str, _: Duration) {
+        self.0.lock().unwrap().push(operation.to_string());
+    }
+}
+
+#[test]
+fn test_timing_hook() {
+    let igd = FakeIgd::start(|action, _| match action {
+        "GetSpecificPortMappingEntry" => Ok(port_mapping_entry(1234)),
+        _ => Err(401),
+    });
+
+    let hook = Arc::new(RecordingHook::default());
+    let mut options = igd.search_options();
+    options.timing_hook = Some(hook.clone());
+    let gateway = igd::search_gateway(options).unwrap();
+    assert_eq!(*hook.0.lock().unwrap(), vec!["fetch_description", "search"]);
+
+    gateway.mapping_exists(PortMappingProtocol::TCP, 1234).unwrap();
+    assert_eq!(
+        *hook.0.lock().unwrap(),
+        vec!["fetch_description", "search", "GetSpecificPortMappingEntry"]
+    );
+}
+
+// Action, request and response, or error message, of a SOAP exchange.
+type Exchange = (String, String, Result<String, String>);
+
+#[derive(Debug, Default)]
+struct RecordingSoapHook(Mutex<Vec<Exchange>>);
+
+impl igd::SoapHook for RecordingSoapHook {
+    fn on_exchange(&self, action: &str, request: &str, response: Result<&str, &RequestError>) {
+        let response = response.map(str::to_string).map_err(|e| e.to_string());
+        self.0
+            .lock()
+            .unwrap()
+            .push((action.to_string(), request.to_string(), response));
+    }
+}
+
+#[test]
+fn test_soap_hook() {
+    let igd = FakeIgd::start(|action, _| match action {
+        "GetExternalIPAddress" => Ok("<NewExternalIPAddress>203.0.113.1</NewExternalIPAddress>".to_string()),
+        _ => Err(401),
+    });
+
+    let hook = Arc::new(RecordingSoapHook::default());
+    let mut options = igd.search_options();
+    options.soap_hook = Some(hook.clone());
+    let gateway = igd::search_gateway(options).unwrap();
+    hook.0.lock().unwrap().clear();
+
+    gateway.get_external_ip().unwrap();
+    let exchanges = hook.0.lock().unwrap();
+    assert_eq!(exchanges.len(), 1);
+    let (action, request, response) = &exchanges[0];
+    assert_eq!(action, "GetExternalIPAddress");
+    assert!(request.contains("GetExternalIPAddress xmlns"));
+    assert!(response.as_ref().unwrap().contains("203.0.113.1"));
+}
+
+#[test]
+fn test_ensure_port_with_own_mapping() {
+    let igd = FakeIgd::start(|action, _| match action {
+        "AddPortMapping" => Err(718),
+        "GetSpecificPortMappingEntry" => Ok(port_mapping_entry(1234)),
+        _ => Err(401),
+    });
+
+    let gateway = igd::search_gateway(igd.search_options()).unwrap();
+    let own_addr = "192.168.1.2:8080".parse().unwrap();
+    gateway
+        .ensure_port(PortMappingProtocol::TCP, 1234, own_addr, 60, "test", false)
+        .unwrap();
+    let other_addr = "192.168.1.3:8080".parse().unwrap();
+    match gateway.ensure_port(PortMappingProtocol::TCP, 1234, other_addr, 60, "test", false) {
+        Err(AddPortError::PortInUse(_)) => {}
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn test_unsupported_action() {
+    let igd = FakeIgd::start(|_, _| Err(401));
+
+    let gateway = igd::search_gateway(igd.search_options()).unwrap();
+    assert!(!gateway.supported_actions().contains(&"GetStatusInfo"));
+    match gateway.get_status_info() {
+        Err(RequestError::UnsupportedAction(action)) => assert_eq!(action, "GetStatusInfo"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn test_subscribe_external_ip_events() {
+    let unsubscribed = Arc::new(AtomicBool::new(false));
+    let igd = {
+        let unsubscribed = unsubscribed.clone();
+        FakeIgd::start(move |action, sid| match action {
+            "SUBSCRIBE" => Ok("<ExternalIPAddress>203.0.113.1</ExternalIPAddress>".to_string()),
+            "UNSUBSCRIBE" if sid == fake_igd::SUBSCRIPTION_ID => {
+                unsubscribed.store(true, Ordering::SeqCst);
+                Ok(String::new())
+            }
+            _ => Err(401),
+        })
+    };
+
+    let gateway = igd::search_gateway(igd.search_options()).unwrap();
+    assert_eq!(gateway.event_url.as_deref(), Some("/evt/IPConn"));
+    let mut subscription = gateway
+        .subscribe("127.0.0.1:0".parse().unwrap(), Duration::from_secs(1800))
+        .unwrap();
+    assert_eq!(subscription.sid(), fake_igd::SUBSCRIPTION_ID);
+    assert_eq!(subscription.timeout(), Duration::from_secs(300));
+
+    let event = subscription.events().recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(event.seq, 0);
+    assert_eq!(event.external_ip(), Some(Ipv4Addr::new(203, 0, 113, 1)));
+
+    assert_eq!(
+        subscription.renew(Duration::from_secs(600)).unwrap(),
+        Duration::from_secs(300)
+    );
+    drop(subscription);
+    assert!(unsubscribed.load(Ordering::SeqCst));
+}
+
+#[test]
+fn test_subscribe_without_event_url() {
+    let igd = FakeIgd::start(|_, _| Err(401));
+
+    let gateway = igd::Gateway::from_url(igd.http_addr, fake_igd::CONTROL_URL.to_string());
+    assert_eq!(gateway.control_url_for(fake_igd::SERVICE_TYPE), None);
+    match gateway.subscribe("127.0.0.1:0".parse().unwrap(), Duration::from_secs(1800)) {
+        Err(RequestError::UnsupportedAction(action)) => assert_eq!(action, "SUBSCRIBE"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn test_missing_control_url() {
+    let igd = FakeIgd::start(|_, _| Err(401));
+
+    let gateway = igd::Gateway::from_url(igd.http_addr, "/missing".to_string());
+    match gateway.get_external_ip() {
+        Err(GetExternalIpError::RequestError(RequestError::HttpStatus(404))) => {}
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+// Picks the ports in order, starting from the start of the range.
+#[derive(Debug, Default)]
+struct SequentialPortPicker(AtomicU16);
+
+impl PortPicker for SequentialPortPicker {
+    fn next_port(&self, range: &RangeInclusive<u16>) -> u16 {
+        range.start() + self.0.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+#[test]
+fn test_add_any_port_walks_through_picked_ports() {
+    let igd = FakeIgd::start(|action, body| match action {
+        "AddPortMapping" => match argument(body, "NewExternalPort") {
+            Some("40002") => Ok(String::new()),
+            _ => Err(718),
+        },
+        _ => Err(401),
+    });
+
+    let mut gateway = igd::search_gateway(igd.search_options()).unwrap();
+    gateway.port_range = 40_000..=41_000;
+    gateway.port_picker = Arc::new(SequentialPortPicker::default());
+    let local_addr = "192.168.1.2:8080".parse().unwrap();
+    let port = gateway
+        .add_any_port(PortMappingProtocol::TCP, local_addr, 60, "test")
+        .unwrap();
+    assert_eq!(port, 40_002);
+}
+
+#[cfg(feature = "aio")]
+#[tokio::test]
+async fn test_search_gateway_aio() {
+    let igd = FakeIgd::start(|action, _| match action {
+        "GetExternalIPAddress" => Ok(EXTERNAL_IP.to_string()),
+        _ => Err(401),
+    });
+
+    let gateway = igd::aio::search_gateway(igd.search_options()).await.unwrap();
+    assert_eq!(gateway.control_url(), fake_igd::CONTROL_URL);
+    assert_eq!(gateway.usn.as_deref(), Some(fake_igd::USN));
+    assert_eq!(gateway.get_external_ip().await.unwrap(), Ipv4Addr::new(203, 0, 113, 1));
+}
+
+#[cfg(feature = "aio")]
+#[tokio::test]
+async fn test_search_gateway_cross_origin_redirect_drops_credentials_aio() {
+    let (options, requests, other_requests) = cross_origin_redirect();
+    assert!(igd::aio::search_gateway(options).await.is_err());
+    assert!(has_authorization(
+        &requests.recv_timeout(Duration::from_secs(1)).unwrap()
+    ));
+    assert!(!has_authorization(
+        &other_requests.recv_timeout(Duration::from_secs(1)).unwrap()
+    ));
+}
+
+#[cfg(feature = "aio")]
+#[tokio::test]
+async fn test_search_gateway_description_timeout_aio() {
+    // The gateway answers the search but never sends its description.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let search_addr = fake_igd::start_search_responder(vec![fake_igd::search_reply(
+        listener.local_addr().unwrap(),
+        "/rootDesc.xml",
+    )]);
+    let options = igd::SearchOptions::builder()
+        .bind_addr("127.0.0.1:0".parse().unwrap())
+        .broadcast_address(search_addr)
+        .timeout(Some(Duration::from_secs(5)))
+        .http_timeout(Some(Duration::from_millis(200)))
+        .build();
+
+    match igd::aio::search_gateway(options).await {
+        Err(igd::SearchError::IoError(e)) => assert_eq!(e.kind(), std::io::ErrorKind::TimedOut),
+        result => panic!("unexpected result: {:?}", result.map(|gateway| gateway.addr)),
+    }
+}
+
+#[cfg(feature = "aio")]
+#[tokio::test]
+async fn test_search_gateway_aio_cancelled_on_drop() {
+    use std::io::Read;
+    use std::net::{TcpListener, UdpSocket};
+
+    // The gateway never sends its description, so the search is left fetching it.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let reply = format!(
+        "HTTP/1.1 200 OK\r\nST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\
+         LOCATION: http://{}/rootDesc.xml\r\n\r\n",
+        listener.local_addr().unwrap()
+    );
+    let search_addr = fake_igd::start_search_responder(vec![reply]);
+    let bind_addr = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let options = igd::SearchOptions {
+        bind_addr,
+        broadcast_address: search_addr,
+        timeout: Some(Duration::from_secs(60)),
+        http_timeout: None,
+        ..Default::default()
+    };
+
+    let search = tokio::spawn(igd::aio::search_gateway(options));
+    let mut stream = tokio::task::spawn_blocking(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = [0u8; 17];
+        stream.read_exact(&mut request).unwrap();
+        assert_eq!(&request, b"GET /rootDesc.xml");
+        stream
+    })
+    .await
+    .unwrap();
+    search.abort();
+    assert!(search.await.unwrap_err().is_cancelled());
+
+    // The request for the description is abandoned with its connection, and the socket is closed.
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream.read_to_end(&mut Vec::new()).unwrap();
+    UdpSocket::bind(bind_addr).unwrap();
+}
+
+#[cfg(feature = "aio")]
+#[tokio::test]
+async fn test_search_gateways_deduplicates_by_usn_aio() {
+    let igd = FakeIgd::start(|_, _| Err(401));
+    let other_igd = FakeIgd::start(|_, _| Err(401));
+    let search_addr = fake_igd::start_search_responder(vec![igd.search_reply(), other_igd.search_reply()]);
+
+    let options = igd::SearchOptions {
+        broadcast_address: search_addr,
+        timeout: Some(Duration::from_millis(500)),
+        ..igd.search_options()
+    };
+    let gateways = igd::aio::search_gateways(options).await.unwrap();
+    assert_eq!(gateways.len(), 1);
+    assert_eq!(gateways[0].addr, igd.http_addr);
+}
+
+#[cfg(all(feature = "aio", feature = "compress"))]
+#[tokio::test]
+async fn test_search_gateway_gzip_description_aio() {
+    let igd = FakeIgd::start_with_location(fake_igd::GZIP_PATH, |action, _| match action {
+        "GetExternalIPAddress" => Ok(EXTERNAL_IP.to_string()),
+        _ => Err(401),
+    });
+
+    let gateway = igd::aio::search_gateway(igd.search_options()).await.unwrap();
+    assert_eq!(gateway.friendly_name(), "Fake IGD");
+    assert_eq!(gateway.get_external_ip().await.unwrap(), Ipv4Addr::new(203, 0, 113, 1));
+}
+
+#[cfg(feature = "aio")]
+#[tokio::test]
+async fn test_search_gateway_redirected_description_aio() {
+    let igd = FakeIgd::start_with_location(fake_igd::REDIRECT_PATH, |action, _| match action {
+        "GetExternalIPAddress" => Ok(EXTERNAL_IP.to_string()),
+        _ => Err(401),
+    });
+
+    let gateway = igd::aio::search_gateway(igd.search_options()).await.unwrap();
+    assert_eq!(
+        gateway.control_url(),
+        format!("http://{}{}", igd.http_addr, fake_igd::CONTROL_URL)
+    );
+    assert_eq!(gateway.get_external_ip().await.unwrap(), Ipv4Addr::new(203, 0, 113, 1));
+}
+
+#[cfg(feature = "aio")]
+#[tokio::test]
+async fn test_search_gateway_missing_description_aio() {
+    let igd = FakeIgd::start_with_location("/missing.xml", |_, _| Err(401));
+
+    match igd::aio::search_gateway(igd.search_options()).await {
+        Err(igd::SearchError::HttpStatus(404)) => {}
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[cfg(feature = "aio")]
+#[tokio::test]
+async fn test_add_port_error_code_aio() {
+    let igd = FakeIgd::start(|action, _| match action {
+        "AddPortMapping" => Err(718),
+        _ => Err(401),
+    });
+
+    let gateway = igd::aio::search_gateway(igd.search_options()).await.unwrap();
+    let local_addr = "192.168.1.2:8080".parse().unwrap();
+    match gateway
+        .add_port(PortMappingProtocol::TCP, 1234, local_addr, 60, "test")
+        .await
+    {
+        Err(AddPortError::PortInUse(_)) => {}
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[cfg(feature = "aio")]
+#[tokio::test]
+async fn test_search_gateway_default_connection_service_aio() {
+    let igd = FakeIgd::start_with_description(fake_igd::MULTI_WAN_DESCRIPTION, |_, action, _| match action {
+        "GetDefaultConnectionService" => Ok("<NewDefaultConnectionService>\
+             uuid:00000000-0000-0000-0000-000000000022:WANConnectionDevice:1,urn:upnp-org:serviceId:WANIPConn1\
+             </NewDefaultConnectionService>"
+            .to_string()),
+        _ => Err(401),
+    });
+
+    let gateway = igd::aio::search_gateway(igd.search_options()).await.unwrap();
+    assert_eq!(gateway.control_url(), "/ctl/IPConn2");
+    assert_eq!(
+        gateway.control_url_for("urn:schemas-upnp-org:service:Layer3Forwarding:1"),
+        Some("/ctl/L3F")
+    );
+    assert_eq!(gateway.control_url_for(fake_igd::SERVICE_TYPE), Some("/ctl/IPConn2"));
+}
+
+#[cfg(feature = "aio")]
+#[tokio::test]
+async fn test_listen_gateways_aio() {
+    use futures::StreamExt;
+
+    let igd = FakeIgd::start(|_, _| Err(401));
+    // The announcements are sent straight to the listener instead of its multicast group.
+    let port = std::net::UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let listen_addr: std::net::SocketAddr = ([127, 0, 0, 1], port).into();
+    let options = igd::SearchOptions::builder()
+        .bind_addr("127.0.0.1:0".parse().unwrap())
+        .broadcast_address(listen_addr)
+        .build();
+    let mut gateways = igd::aio::listen_gateways(options).await.unwrap();
+
+    let notify = |nts: &str| {
+        format!(
+            "NOTIFY * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nLOCATION: http://{}/rootDesc.xml\r\n\
+             NT: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\nNTS: {}\r\n\r\n",
+            igd.http_addr, nts
+        )
+    };
+    let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    sender.send_to(notify("ssdp:byebye").as_bytes(), listen_addr).unwrap();
+    sender.send_to(notify("ssdp:alive").as_bytes(), listen_addr).unwrap();
+
+    let gateway = tokio::time::timeout(std::time::Duration::from_secs(5), gateways.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(gateway.addr(), igd.http_addr);
+    assert_eq!(gateway.control_url(), fake_igd::CONTROL_URL);
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn test_tracing_spans() {
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    // Records the name of every span that is created.
+    #[derive(Default)]
+    struct SpanNames(Mutex<Vec<&'static str>>);
+
+    impl Subscriber for SpanNames {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut names = self.0.lock().unwrap();
+            names.push(span.metadata().name());
+            Id::from_u64(names.len() as u64)
+        }
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event<'_>) {}
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    let igd = FakeIgd::start(|action, _| match action {
+        "GetExternalIPAddress" => Ok(EXTERNAL_IP.to_string()),
+        _ => Err(401),
+    });
+
+    let names = Arc::new(SpanNames::default());
+    tracing::subscriber::with_default(names.clone(), || {
+        let gateway = igd::search_gateway(igd.search_options()).unwrap();
+        gateway.get_external_ip().unwrap();
+    });
+    let names = names.0.lock().unwrap();
+    for name in &["igd.search", "igd.fetch_description", "igd.soap_action"] {
+        assert!(names.contains(name), "missing span {} in {:?}", name, names);
+    }
+}